@@ -0,0 +1,456 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use glob::Pattern;
+use ratatui::style::Color;
+use regex::Regex;
+use serde::Deserialize;
+use serialport::{DataBits, FlowControl, Parity, SerialPortInfo, SerialPortType, StopBits};
+
+use crate::{
+    serial::{
+        identity,
+        settings::{Encoding, PortSettings},
+        utils::check_flow,
+    },
+    trigger::{Trigger, TriggerConfig},
+};
+
+/// The config file, for example
+///
+/// ```toml
+/// [layout]
+/// ports_width = 20
+///
+/// [session]
+/// scrollback = true
+///
+/// [defaults]
+/// baud = 115200
+/// line_ending = "\r\n"
+/// download_dir = "/tmp/downloads"
+///
+/// [[defaults.highlight]]
+/// pattern = 'ERROR|FAIL'
+/// color = "lightred"
+///
+/// [[profile]]
+/// name = "esp32"
+/// match = { vid = 0x10c4, pid = 0xea60 }
+/// reset = "r1:d0:s100:r0"
+///
+/// [[profile.macro]]
+/// key = "F2"
+/// send = "AT+GMR\r\n"
+///
+/// [[profile.trigger]]
+/// on = 'Guru Meditation'
+/// pause = true
+///
+/// [[profile]]
+/// name = "console"
+/// match = { path = "/dev/ttyS*" }
+/// baud = 9600
+/// parity = "even"
+/// data_bits = 7
+/// encoding = "latin1"
+/// ```
+///
+/// A port gets the first profile that matches it, on top of the defaults. Only F1 to F12 can be
+/// bound, to macros, the other keys do what they always do.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ConfigFile {
+    layout: LayoutConfig,
+    session: SessionOptions,
+    defaults: ProfileConfig,
+    profile: Vec<ProfileConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LayoutConfig {
+    /// Percentages of the screen.
+    ports_width: Option<u16>,
+    write_height: Option<u16>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ProfileConfig {
+    name: Option<String>,
+    #[serde(rename = "match")]
+    matches: Option<MatchConfig>,
+    baud: Option<u32>,
+    data_bits: Option<u8>,
+    parity: Option<String>,
+    stop_bits: Option<u8>,
+    flow_control: Option<String>,
+    /// Added to every line sent from the write box.
+    line_ending: Option<String>,
+    encoding: Option<String>,
+    /// A control line sequence like `r1:d0:s1000:d1:r0` run instead of the usual DTR reset.
+    reset: Option<String>,
+    /// Where downloads go, those the device starts by itself included.
+    download_dir: Option<PathBuf>,
+    #[serde(rename = "macro")]
+    macros: Vec<MacroConfig>,
+    #[serde(rename = "highlight")]
+    highlights: Vec<HighlightConfig>,
+    #[serde(rename = "trigger")]
+    triggers: Vec<TriggerConfig>,
+}
+
+/// What a port has to have for a profile to apply, everything that is given has to match.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MatchConfig {
+    vid: Option<u16>,
+    pid: Option<u16>,
+    serial: Option<String>,
+    /// Globs, like `path = "/dev/ttyUSB*"`.
+    product: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MacroConfig {
+    /// `F1` to `F12`.
+    key: String,
+    send: String,
+}
+
+#[derive(Deserialize)]
+struct HighlightConfig {
+    pattern: String,
+    /// A color name like `lightred`, or `#rrggbb`.
+    color: String,
+}
+
+/// What happens to the session on exit and start, see `session::Session`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SessionOptions {
+    /// Restore the last session on start, a session asked for with `--session` always is.
+    pub restore: bool,
+    /// Save what the ports show along with the session.
+    pub scrollback: bool,
+}
+
+impl Default for SessionOptions {
+    fn default() -> SessionOptions {
+        SessionOptions {
+            restore: true,
+            scrollback: false,
+        }
+    }
+}
+
+pub struct Layout {
+    pub ports_width: u16,
+    pub write_height: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            ports_width: 15,
+            write_height: 10,
+        }
+    }
+}
+
+/// What a port is opened and shown with.
+#[derive(Clone)]
+pub struct Profile {
+    /// `None` when only the defaults apply.
+    pub name: Option<String>,
+    pub settings: PortSettings,
+    pub line_ending: String,
+    pub reset: Option<String>,
+    /// The current directory unless configured.
+    pub download_dir: PathBuf,
+    /// Function key numbers and what they send.
+    pub macros: Vec<(u8, String)>,
+    /// Received lines matching a pattern are shown in its color, the first match wins.
+    pub highlights: Vec<(Regex, Color)>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            name: None,
+            settings: PortSettings::default(),
+            line_ending: "\n".to_owned(),
+            reset: None,
+            download_dir: PathBuf::from("."),
+            macros: Vec::new(),
+            highlights: Vec::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// `config` applied on top of this profile.
+    fn apply(&mut self, config: &ProfileConfig) -> Result<(), String> {
+        let context = |err: String| match &config.name {
+            Some(name) => format!("profile {}: {}", name, err),
+            None => format!("defaults: {}", err),
+        };
+        if config.name.is_some() {
+            self.name = config.name.clone();
+        }
+        let settings = &mut self.settings;
+        if let Some(baud) = config.baud {
+            settings.baud_rate = baud;
+        }
+        if let Some(data_bits) = config.data_bits {
+            settings.data_bits = match data_bits {
+                5 => DataBits::Five,
+                6 => DataBits::Six,
+                7 => DataBits::Seven,
+                8 => DataBits::Eight,
+                _ => return Err(context(format!("{} data bits", data_bits))),
+            };
+        }
+        if let Some(parity) = &config.parity {
+            settings.parity = match parity.as_str() {
+                "none" => Parity::None,
+                "odd" => Parity::Odd,
+                "even" => Parity::Even,
+                _ => return Err(context(format!("{}: expected none, odd or even", parity))),
+            };
+        }
+        if let Some(stop_bits) = config.stop_bits {
+            settings.stop_bits = match stop_bits {
+                1 => StopBits::One,
+                2 => StopBits::Two,
+                _ => return Err(context(format!("{} stop bits", stop_bits))),
+            };
+        }
+        if let Some(flow_control) = &config.flow_control {
+            settings.flow_control = match flow_control.as_str() {
+                "none" => FlowControl::None,
+                "software" => FlowControl::Software,
+                "hardware" => FlowControl::Hardware,
+                _ => {
+                    return Err(context(format!(
+                        "{}: expected none, software or hardware",
+                        flow_control
+                    )))
+                }
+            };
+        }
+        if let Some(encoding) = &config.encoding {
+            settings.encoding = Encoding::from_str(encoding).map_err(context)?;
+        }
+        if let Some(line_ending) = &config.line_ending {
+            self.line_ending = line_ending.clone();
+        }
+        if let Some(reset) = &config.reset {
+            check_flow(reset).map_err(context)?;
+            self.reset = Some(reset.clone());
+        }
+        if let Some(download_dir) = &config.download_dir {
+            self.download_dir = download_dir.clone();
+        }
+        for config in &config.macros {
+            let key = config
+                .key
+                .strip_prefix(['F', 'f'])
+                .and_then(|number| number.parse::<u8>().ok())
+                .filter(|number| (1..=12).contains(number))
+                .ok_or_else(|| context(format!("{}: expected F1 to F12", config.key)))?;
+            self.macros.retain(|(bound, _)| *bound != key);
+            self.macros.push((key, config.send.clone()));
+        }
+        let mut highlights = Vec::new();
+        for config in &config.highlights {
+            let pattern = Regex::new(&config.pattern).map_err(|err| context(err.to_string()))?;
+            let color = Color::from_str(&config.color)
+                .map_err(|_| context(format!("{}: not a color", config.color)))?;
+            highlights.push((pattern, color));
+        }
+        // a profile's own rules go before those of the defaults
+        self.highlights.splice(0..0, highlights);
+        Ok(())
+    }
+
+    pub fn macro_for(&self, key: u8) -> Option<&str> {
+        self.macros
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|(_, send)| send.as_str())
+    }
+}
+
+struct NamedProfile {
+    matches: MatchConfig,
+    profile: Profile,
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub layout: Layout,
+    pub session: SessionOptions,
+    defaults: Profile,
+    profiles: Vec<NamedProfile>,
+    /// From the defaults and the profiles, those from a profile only fire on its ports.
+    pub triggers: Vec<Trigger>,
+}
+
+/// `$XDG_CONFIG_HOME/determ`, or `~/.config/determ`.
+pub fn dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("determ"))
+}
+
+impl Config {
+    /// Reads `config.toml` in the config directory, which doesn't have to exist.
+    pub fn load() -> io::Result<Config> {
+        let Some(path) = dir().map(|dir| dir.join("config.toml")) else {
+            return Ok(Config::default());
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(err),
+        };
+        let invalid = |err: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        };
+        let file: ConfigFile =
+            toml::from_str(&source).map_err(|err| invalid(err.message().to_owned()))?;
+        Config::new(file).map_err(invalid)
+    }
+
+    fn new(file: ConfigFile) -> Result<Config, String> {
+        let mut defaults = Profile::default();
+        defaults.apply(&file.defaults)?;
+        let mut triggers = file
+            .defaults
+            .triggers
+            .iter()
+            .map(|trigger| Trigger::new(trigger.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut profiles = Vec::new();
+        for config in file.profile {
+            let name = config.name.clone().ok_or("a profile without a name")?;
+            let mut profile = defaults.clone();
+            profile.apply(&config)?;
+            for trigger in &config.triggers {
+                let mut trigger = Trigger::new(trigger.clone())
+                    .map_err(|err| format!("profile {}: {}", name, err))?;
+                trigger.profile = Some(name.clone());
+                triggers.push(trigger);
+            }
+            let matches = config.matches.unwrap_or_default();
+            for pattern in [&matches.product, &matches.path].into_iter().flatten() {
+                Pattern::new(pattern).map_err(|err| format!("profile {}: {}", name, err))?;
+            }
+            profiles.push(NamedProfile { matches, profile });
+        }
+        Ok(Config {
+            layout: Layout {
+                ports_width: file.layout.ports_width.unwrap_or(15).clamp(5, 60),
+                write_height: file.layout.write_height.unwrap_or(10).clamp(5, 50),
+            },
+            session: file.session,
+            defaults,
+            profiles,
+            triggers,
+        })
+    }
+
+    /// The profile for the port called `name`, with what the system knows about it in `info`.
+    pub fn profile_for(
+        &self,
+        name: &str,
+        kernel_name: Option<&str>,
+        info: Option<&SerialPortInfo>,
+    ) -> Profile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.matches.matches(name, kernel_name, info))
+            .map_or_else(|| self.defaults.clone(), |profile| profile.profile.clone())
+    }
+}
+
+impl MatchConfig {
+    fn matches(
+        &self,
+        name: &str,
+        kernel_name: Option<&str>,
+        info: Option<&SerialPortInfo>,
+    ) -> bool {
+        let glob = |pattern: &str, text: &str| {
+            Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(text))
+        };
+        let usb = match info.map(|info| &info.port_type) {
+            Some(SerialPortType::UsbPort(usb)) => Some(usb),
+            _ => None,
+        };
+        self.vid
+            .is_none_or(|vid| usb.is_some_and(|usb| usb.vid == vid))
+            && self
+                .pid
+                .is_none_or(|pid| usb.is_some_and(|usb| usb.pid == pid))
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|serial| usb.and_then(|usb| usb.serial_number.as_ref()) == Some(serial))
+            && self.product.as_ref().is_none_or(|product| {
+                usb.and_then(|usb| usb.product.as_deref())
+                    .is_some_and(|text| glob(product, text))
+            })
+            && self.path.as_ref().is_none_or(|path| {
+                Pattern::new(path)
+                    .is_ok_and(|path| identity::glob_matches(&path, name, kernel_name))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_path_matches_the_stable_or_the_kernel_name() {
+        let file = toml::from_str::<ConfigFile>(
+            r#"
+[[profile]]
+name = "adapter"
+match = { path = "/dev/ttyUSB*" }
+"#,
+        )
+        .unwrap();
+        let config = Config::new(file).unwrap();
+        let by_id = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K5LXY-if00-port0";
+        let profile = |name, kernel_name| config.profile_for(name, kernel_name, None).name;
+        assert_eq!(profile(by_id, Some("/dev/ttyUSB0")), Some("adapter".to_owned()));
+        assert_eq!(profile("/dev/ttyUSB1", None), Some("adapter".to_owned()));
+        assert_eq!(profile(by_id, None), None);
+        assert_eq!(profile("/dev/ttyS0", None), None);
+    }
+
+    #[test]
+    fn a_reset_sequence_is_checked() {
+        let config = |reset: &str| {
+            let source = format!("[[profile]]\nname = \"esp32\"\nreset = \"{}\"", reset);
+            Config::new(toml::from_str::<ConfigFile>(&source).unwrap())
+        };
+        assert!(config("r1:d0:s100:r0").is_ok());
+        for bad in ["", "r1:", "r1,d0", "s99999999999999999999"] {
+            assert!(config(bad).is_err(), "{:?} was taken", bad);
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use core::panic;
+use config::{Config, Profile};
 use crossterm::{
-    event::{self, KeyCode, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, KeyCode, KeyEventKind, KeyModifiers,
+    },
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
@@ -13,30 +16,65 @@ use ratatui::{
     symbols::scrollbar,
     text::{Line, Span, Text},
     widgets::{
+        block::{Position, Title},
         Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar,
         ScrollbarOrientation, ScrollbarState,
     },
 };
-use serial::{utils::monitor, PortCommand};
-use serialport::SerialPortInfo;
+use script::{ScriptEvent, ScriptHandle, ScriptInfo};
+use serial::{
+    fault::FaultConfig,
+    identity,
+    repeat::{RepeatCommand, ScheduleInfo},
+    share::ShareInfo,
+    tx::{Pacing, TxProgress, TxResult},
+    utils::LineErrors,
+    LineDirection, PortCommand, PortEvent,
+};
+use serialport::{SerialPortInfo, SerialPortType};
+use ports::PortPrefs;
+use session::{PortState, SavedLine, Session};
+use regex::Regex;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
+    fs::File,
     io,
-    io::{stdout, Result},
+    io::{stdout, LineWriter, Result, Write},
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::channel,
+        mpsc::{channel, Sender},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use transfer::{TransferDirection, TransferRequest, TransferState};
+use trigger::{LogAction, Trigger, TriggerAction};
 use tui_textarea::{Input, Key, TextArea};
+use ui::{
+    DetailsView, FaultSetup, PacingSetup, RepeatSetup, ShareSetup, TeeSetup, TransferSetup,
+    TransferView,
+};
 
+mod config;
+mod ports;
+mod runner;
+mod script;
 mod serial;
+mod session;
+mod transfer;
+mod trigger;
+mod ui;
+
+/// How often the ports a restored session waits for are looked for.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a script's `notify()` stays in the title.
+const NOTICE_DURATION: Duration = Duration::from_secs(5);
 
 #[derive(Default)]
 struct RenderCache {
-    text: String,
+    text: Text<'static>,
     width: u16,
     height: u16,
     v_scroll: usize,
@@ -44,18 +82,87 @@ struct RenderCache {
     dirty: bool,
 }
 
+struct Entry {
+    direction: LineDirection,
+    text: String,
+    /// When the line reached the UI.
+    time: SystemTime,
+    /// Marked by a trigger.
+    marked: bool,
+}
+
+impl Entry {
+    fn new(direction: LineDirection, text: String) -> Entry {
+        Entry {
+            direction,
+            text,
+            time: SystemTime::now(),
+            marked: false,
+        }
+    }
+
+    fn restored(line: SavedLine) -> Entry {
+        Entry {
+            direction: line.direction,
+            text: line.text,
+            time: UNIX_EPOCH + Duration::from_millis(line.time),
+            marked: line.marked,
+        }
+    }
+
+    fn saved(&self) -> SavedLine {
+        SavedLine {
+            direction: self.direction,
+            time: self
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            text: self.text.clone(),
+            marked: self.marked,
+        }
+    }
+}
+
 struct Port {
     name: String,
     paused: bool,
-    scroll_buffer: VecDeque<String>,
+    scroll_buffer: VecDeque<Entry>,
     rts: bool,
     dtr: bool,
     render_cache: RenderCache,
+    line_errors: LineErrors,
+    error_markers: bool,
+    tx_progress: Option<TxProgress>,
+    last_tx: Option<TxResult>,
+    /// Show what we write along with what we receive.
+    local_echo: bool,
+    /// Prefix lines with the time they arrived and mark received lines too.
+    timestamps: bool,
+    share: Option<ShareInfo>,
+    /// Faults being injected into the port's traffic.
+    faults: Option<FaultConfig>,
+    /// Command received data is piped into, and whether its output goes back to the port.
+    tee: Option<(String, bool)>,
+    /// Where the lines are logged to, if they are.
+    log: Option<(PathBuf, LineWriter<File>)>,
+    /// Text written to this port goes to the other broadcast ports too.
+    broadcast: bool,
+    profile: Profile,
+    /// How many lines were shown, the rest are unread.
+    seen: usize,
+    /// The triggers, by index, that fired on the start of the line being received.
+    partial_triggers: Vec<usize>,
+    /// One of them marks the line once it's there.
+    partial_mark: bool,
 }
 
 impl Port {
-    fn new(name: String, paused: bool) -> Port {
+    fn new(name: String, paused: bool, profile: Profile) -> Port {
         Port {
+            // sniffed and bridged ports show two parties talking, when they said what matters
+            timestamps: name.starts_with(serial::sniff::SCHEME)
+                || name.starts_with(serial::bridge::SCHEME),
             name,
             paused,
             scroll_buffer: VecDeque::with_capacity(1000),
@@ -65,6 +172,86 @@ impl Port {
                 dirty: true,
                 ..RenderCache::default()
             },
+            line_errors: LineErrors::default(),
+            error_markers: false,
+            tx_progress: None,
+            last_tx: None,
+            local_echo: true,
+            share: None,
+            faults: None,
+            tee: None,
+            log: None,
+            broadcast: false,
+            profile,
+            seen: 0,
+            partial_triggers: Vec::new(),
+            partial_mark: false,
+        }
+    }
+
+    /// Whether the line or a write has gone wrong, BREAKs are sent on purpose.
+    fn has_errors(&self) -> bool {
+        let errors = self.line_errors;
+        errors.frame > 0
+            || errors.parity > 0
+            || errors.overrun > 0
+            || matches!(self.last_tx, Some(TxResult::Failed(..)))
+    }
+
+    fn push(&mut self, direction: LineDirection, text: String) {
+        let entry = Entry::new(direction, text);
+        if let Some((path, log)) = &mut self.log {
+            let (date, time) = format_time(entry.time);
+            let marker = match entry.direction {
+                LineDirection::Rx => "RX",
+                LineDirection::Tx => "TX",
+                LineDirection::System => "--",
+            };
+            let line = entry.text.trim_end_matches(['\r', '\n']);
+            if let Err(err) = writeln!(log, "{} {} {} {}", date, time, marker, line) {
+                let path = path.clone();
+                self.log = None;
+                self.scroll_buffer.push_back(Entry::new(
+                    LineDirection::System,
+                    format!("── logging to {} stopped: {} ──", path.display(), err),
+                ));
+            }
+        }
+        self.scroll_buffer.push_back(entry);
+        self.mark_render_dirty();
+    }
+
+    /// Starts logging to a new file in the working directory, or stops logging.
+    fn toggle_log(&mut self) {
+        if let Some((path, _)) = self.log.take() {
+            self.push(
+                LineDirection::System,
+                format!("── stopped logging to {} ──", path.display()),
+            );
+            return;
+        }
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let port = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let path = PathBuf::from(format!("determ-{}-{}.log", port.trim_matches('_'), stamp));
+        match File::create(&path) {
+            Ok(file) => {
+                self.log = Some((path.clone(), LineWriter::new(file)));
+                self.push(
+                    LineDirection::System,
+                    format!("── logging to {} ──", path.display()),
+                );
+            }
+            Err(err) => self.push(
+                LineDirection::System,
+                format!("── could not log to {}: {} ──", path.display(), err),
+            ),
         }
     }
 
@@ -72,7 +259,18 @@ impl Port {
         self.render_cache.dirty = true;
     }
 
-    fn rendered_text(&mut self, width: u16, height: u16, v_scroll: usize) -> &str {
+    fn visible_len(&self) -> usize {
+        if self.local_echo {
+            self.scroll_buffer.len()
+        } else {
+            self.scroll_buffer
+                .iter()
+                .filter(|entry| entry.direction != LineDirection::Tx)
+                .count()
+        }
+    }
+
+    fn rendered_text(&mut self, width: u16, height: u16, v_scroll: usize) -> &Text<'static> {
         let line_count = self.scroll_buffer.len();
         if self.render_cache.dirty
             || self.render_cache.width != width
@@ -80,7 +278,14 @@ impl Port {
             || self.render_cache.v_scroll != v_scroll
             || self.render_cache.line_count != line_count
         {
-            self.render_cache.text = build_visible_text(&self.scroll_buffer, width, height, v_scroll);
+            self.render_cache.text = build_visible_text(
+                &self.scroll_buffer,
+                self.local_echo,
+                self.timestamps,
+                &self.profile.highlights,
+                height,
+                v_scroll,
+            );
             self.render_cache.width = width;
             self.render_cache.height = height;
             self.render_cache.v_scroll = v_scroll;
@@ -88,59 +293,111 @@ impl Port {
             self.render_cache.dirty = false;
         }
 
-        self.render_cache.text.as_str()
+        &self.render_cache.text
     }
 }
 
 fn build_visible_text(
-    scroll_buffer: &VecDeque<String>,
-    width: u16,
+    scroll_buffer: &VecDeque<Entry>,
+    local_echo: bool,
+    timestamps: bool,
+    highlights: &[(Regex, Color)],
     height: u16,
     v_scroll: usize,
-) -> String {
-    if scroll_buffer.len() <= 1 {
-        return height.to_string();
+) -> Text<'static> {
+    let visible = scroll_buffer
+        .iter()
+        .filter(|entry| local_echo || entry.direction != LineDirection::Tx)
+        .collect::<Vec<_>>();
+    if visible.len() <= 1 {
+        return Text::raw(height.to_string());
     }
 
-    let width = usize::from(width).max(1);
     let height = usize::from(height);
-    let len = scroll_buffer.len();
+    let len = visible.len();
     let start = len
         .saturating_sub(v_scroll)
         .saturating_sub(height)
         .saturating_add(2);
     let end = len.saturating_sub(v_scroll);
-    let mut rendered = String::new();
+    let mut rendered = Vec::with_capacity(end.saturating_sub(start));
 
-    for curr_line in scroll_buffer.range(start..end) {
-        let filtered = curr_line
+    for entry in &visible[start.min(end)..end] {
+        let filtered = entry
+            .text
             .chars()
-            .filter(|&c| c != '\0')
+            .filter(|&c| c != '\0' && c != '\n')
             .collect::<String>();
 
-        if filtered.is_empty() {
-            rendered.push('\n');
-            continue;
+        let mut spans = Vec::with_capacity(4);
+        if entry.marked {
+            spans.push(Span::styled(
+                "⚑ ",
+                Style::default()
+                    .fg(Color::LightRed)
+                    .add_modifier(Modifier::BOLD),
+            ));
         }
-
-        let mut line_width = 0;
-        for ch in filtered.chars() {
-            rendered.push(ch);
-            line_width += 1;
-            if line_width >= width {
-                line_width = 0;
-            }
+        if timestamps {
+            spans.push(Span::styled(
+                format!("{} ", format_time(entry.time).1),
+                Style::default().fg(Color::DarkGray),
+            ));
         }
-
-        if !filtered.ends_with('\n') {
-            rendered.push('\n');
+        let highlight = highlights
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&filtered))
+            .map_or_else(Style::default, |(_, color)| Style::default().fg(*color));
+        match entry.direction {
+            LineDirection::Rx if timestamps => {
+                spans.push(Span::styled("« ", Style::default().fg(Color::DarkGray)));
+                spans.push(Span::styled(filtered, highlight));
+            }
+            LineDirection::Rx => spans.push(Span::styled(filtered, highlight)),
+            LineDirection::Tx => {
+                spans.push(Span::styled("» ", Style::default().fg(Color::DarkGray)));
+                spans.push(Span::styled(
+                    filtered,
+                    Style::default().fg(Color::LightCyan),
+                ));
+            }
+            LineDirection::System => spans.push(Span::styled(
+                filtered,
+                Style::default()
+                    .fg(Color::LightYellow)
+                    .add_modifier(Modifier::ITALIC),
+            )),
         }
+        rendered.push(Line::from(spans));
     }
 
-    rendered
+    Text::from(rendered)
+}
+
+/// `time` in local time, as `YYYY-MM-DD` and `HH:MM:SS.mmm`.
+fn format_time(time: SystemTime) -> (String, String) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    (
+        format!(
+            "{:04}-{:02}-{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday
+        ),
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec,
+            since_epoch.subsec_millis()
+        ),
+    )
 }
 
-fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+pub(crate) fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
     let popup_width = width.min(area.width);
     let popup_height = height.min(area.height);
     let x = area.x + area.width.saturating_sub(popup_width) / 2;
@@ -161,12 +418,77 @@ struct App {
     active_port_idx: usize,
     mode: Mode,
     v_scroll: usize,
+    transfer_setup: Option<TransferSetup>,
+    transfer: Option<TransferView>,
+    pacing: Pacing,
+    pacing_setup: Option<PacingSetup>,
+    share_setup: Option<ShareSetup>,
+    open_prompt: Option<TextArea<'static>>,
+    fault_setup: Option<FaultSetup>,
+    tee_setup: Option<TeeSetup>,
+    scripts: Vec<ScriptInfo>,
+    running_scripts: Vec<ScriptHandle>,
+    next_script_id: u64,
+    script_picker: Option<ListState>,
+    /// A message for the user from a script, and when it came.
+    notice: Option<(String, Instant)>,
+    triggers: Vec<Trigger>,
+    /// Switches all triggers off at once, without forgetting which ones are on.
+    triggers_enabled: bool,
+    /// What fired triggers left to do, by port.
+    trigger_actions: Vec<(String, TriggerAction)>,
+    trigger_list: Option<ListState>,
+    /// Periodic sends of all ports, as the serial thread last reported them.
+    schedules: Vec<ScheduleInfo>,
+    repeat_setup: Option<RepeatSetup>,
+    config: Config,
+    /// Ports of the restored session that weren't there yet, opened once they show up.
+    awaiting: Vec<String>,
+    prefs: PortPrefs,
+    /// The ports in the order they are listed, as indices into `ports`.
+    listed: Vec<usize>,
+    /// The kernel names of ports that go by a stable name, as they were last seen.
+    kernel_names: HashMap<String, String>,
+    /// The port being named and its new alias.
+    alias_prompt: Option<(String, TextArea<'static>)>,
+    details: Option<DetailsView>,
 }
 
 impl App {
     pub fn new() -> App {
-        App {
-            ports: serialport::available_ports().expect("No ports found!"),
+        let config = Config::load();
+        let triggers = trigger::load();
+        let prefs = PortPrefs::load();
+        let notice = match (&config, &triggers, &prefs) {
+            (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+                Some((err.to_string(), Instant::now()))
+            }
+            _ => None,
+        };
+        let config = config.unwrap_or_default();
+        let mut prefs = prefs.unwrap_or_default();
+        let mut kernel_names = HashMap::new();
+        let ports = serialport::available_ports()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut info| {
+                let name = identity::stable_name(&info);
+                if name != info.port_name {
+                    kernel_names.insert(name.clone(), std::mem::replace(&mut info.port_name, name));
+                }
+                info
+            })
+            .chain(serial::mock::PORTS.iter().map(|name| SerialPortInfo {
+                port_name: (*name).to_owned(),
+                port_type: SerialPortType::Unknown,
+            }))
+            .collect();
+        // saved before USB ports went by their stable names
+        for (name, kernel_name) in &kernel_names {
+            prefs.rename(kernel_name, name);
+        }
+        let mut app = App {
+            ports,
             // selected_port: None,
             is_active: false,
             // scroll_buffer: VecDeque::with_capacity(1000),
@@ -174,7 +496,304 @@ impl App {
             active_port_idx: 0,
             mode: Mode::Main,
             v_scroll: 0,
+            transfer_setup: None,
+            transfer: None,
+            pacing: Pacing::default(),
+            pacing_setup: None,
+            share_setup: None,
+            open_prompt: None,
+            fault_setup: None,
+            tee_setup: None,
+            scripts: script::discover(),
+            running_scripts: Vec::new(),
+            next_script_id: 0,
+            script_picker: None,
+            notice,
+            triggers: config
+                .triggers
+                .iter()
+                .cloned()
+                .chain(triggers.unwrap_or_default())
+                .collect(),
+            triggers_enabled: true,
+            trigger_actions: Vec::new(),
+            trigger_list: None,
+            schedules: Vec::new(),
+            repeat_setup: None,
+            config,
+            awaiting: Vec::new(),
+            prefs,
+            listed: Vec::new(),
+            kernel_names,
+            alias_prompt: None,
+            details: None,
+        };
+        app.refresh_port_list();
+        app
+    }
+
+    /// Lists favourites first and leaves out hidden ports, unless they are open.
+    fn refresh_port_list(&mut self) {
+        let (mut listed, others): (Vec<_>, Vec<_>) = (0..self.ports.len())
+            .filter(|&idx| {
+                let info = &self.ports[idx];
+                !self.prefs.hide_builtin
+                    || !ports::is_builtin(info)
+                    || self.port_data_index(&info.port_name).is_some()
+            })
+            .partition(|&idx| self.prefs.is_favourite(&self.ports[idx].port_name));
+        listed.extend(others);
+        self.listed = listed;
+    }
+
+    /// Where the port is in the list.
+    fn list_position(&self, name: &str) -> Option<usize> {
+        self.listed
+            .iter()
+            .position(|&idx| self.ports[idx].port_name == name)
+    }
+
+    fn listed_port_name(&self, position: usize) -> Option<String> {
+        let idx = *self.listed.get(position)?;
+        Some(self.ports[idx].port_name.clone())
+    }
+
+    fn port_list_items(&self) -> Vec<ListItem<'static>> {
+        let active = self.ports_data.get(self.active_port_idx);
+        self.listed
+            .iter()
+            .map(|&idx| {
+                let info = &self.ports[idx];
+                let name = &info.port_name;
+                let port = self.port_data_index(name).map(|idx| &self.ports_data[idx]);
+                let (icon, color) = match port {
+                    None => ("○", Color::DarkGray),
+                    Some(port) if port.paused => ("⏸", Color::LightYellow),
+                    Some(_) => ("◉", Color::LightGreen),
+                };
+                let mut spans = vec![Span::styled(
+                    format!("{} ", icon),
+                    Style::default().fg(color),
+                )];
+                if self.prefs.is_favourite(name) {
+                    spans.push(Span::styled("★ ", Style::default().fg(Color::Yellow)));
+                }
+                spans.push(Span::raw(self.display_name(name).to_owned()));
+                if let Some(port) = port {
+                    if port.has_errors() {
+                        spans.push(Span::styled(" ⚠", Style::default().fg(Color::LightRed)));
+                    }
+                    let unread = port.scroll_buffer.len().saturating_sub(port.seen);
+                    if unread > 0 && active.is_none_or(|active| active.name != *name) {
+                        spans.push(Span::styled(
+                            format!(" ✉{}", unread),
+                            Style::default().fg(Color::LightCyan),
+                        ));
+                    }
+                }
+                let details = [self.secondary_name(name), ports::usb_details(info)]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                let mut lines = vec![Line::from(spans)];
+                if !details.is_empty() {
+                    lines.push(Line::from(Span::styled(
+                        format!("  {}", details.join(" · ")),
+                        Style::default().fg(Color::Gray),
+                    )));
+                }
+                ListItem::new(lines)
+            })
+            .collect()
+    }
+
+    /// Whether the port can be opened without waiting for it, only device paths come and go.
+    fn is_available(&self, name: &str) -> bool {
+        !identity::is_device(name) || self.ports.iter().any(|info| info.port_name == name)
+    }
+
+    /// What a port is called on screen: its alias, or its name without the obvious parts.
+    fn display_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.prefs
+            .alias(name)
+            .unwrap_or_else(|| identity::short_name(name))
+    }
+
+    /// Which device a port is now, or its full name when the alias hides it.
+    fn secondary_name(&self, name: &str) -> Option<String> {
+        match self.kernel_names.get(name) {
+            Some(kernel_name) => Some(kernel_name.clone()),
+            None => (self.display_name(name) != name).then(|| name.to_owned()),
+        }
+    }
+
+    /// The stable name of a port plugged in now that was saved by its kernel name.
+    fn stable_name(&self, name: String) -> String {
+        self.kernel_names
+            .iter()
+            .find(|(_, kernel_name)| **kernel_name == name)
+            .map_or(name, |(stable_name, _)| stable_name.clone())
+    }
+
+    /// Brings back the ports of a saved session, the active one opened last so it ends up the
+    /// current port.
+    fn restore_session(&mut self, session: Session, port_tx: &Sender<PortCommand>) {
+        let mut opened = Vec::new();
+        for mut state in session.ports {
+            state.name = self.stable_name(state.name);
+            let idx = self.ensure_port_data(&state.name, port_tx);
+            let available = self.is_available(&state.name);
+            let port = &mut self.ports_data[idx];
+            port.local_echo = state.local_echo;
+            port.timestamps = state.timestamps;
+            port.error_markers = state.error_markers;
+            port.broadcast = state.broadcast;
+            port.scroll_buffer.extend(state.scrollback.into_iter().map(Entry::restored));
+            port.seen = port.scroll_buffer.len();
+            if state.logging {
+                port.toggle_log();
+            }
+            port.paused = true;
+            if state.paused {
+                continue;
+            }
+            if available {
+                opened.push(idx);
+            } else {
+                port.push(
+                    LineDirection::System,
+                    format!("── waiting for {} to show up ──", state.name),
+                );
+                self.awaiting.push(state.name);
+            }
         }
+        self.active_port_idx = session
+            .active
+            .and_then(|name| self.port_data_index(&self.stable_name(name)))
+            .unwrap_or(0);
+        opened.sort_by_key(|&idx| idx == self.active_port_idx);
+        for &idx in &opened {
+            let port = &mut self.ports_data[idx];
+            port.paused = false;
+            let _ = port_tx.send(PortCommand::ChangePort(port.name.clone()));
+        }
+        // a paused or awaited port is shown all the same, and must not get what is meant for
+        // the last port opened
+        if let Some(port) = self.ports_data.get(self.active_port_idx) {
+            if !opened.contains(&self.active_port_idx) {
+                let _ = port_tx.send(PortCommand::Show(port.name.clone()));
+            }
+        }
+        let broadcast = self.broadcast_group();
+        if !broadcast.is_empty() {
+            let _ = port_tx.send(PortCommand::Broadcast(broadcast));
+        }
+        self.mode = match session.pane.as_deref() {
+            Some("ports") => Mode::Listing,
+            Some("write") => Mode::Writing,
+            Some("term") => Mode::Term,
+            _ => Mode::Main,
+        };
+    }
+
+    /// Opens the awaited ports that showed up.
+    fn open_arrived_ports(&mut self, port_tx: &Sender<PortCommand>, stop_flag: &AtomicBool) {
+        let mut present = Vec::new();
+        for info in serialport::available_ports().unwrap_or_default() {
+            let name = identity::stable_name(&info);
+            // the same board may well have come back as another ttyUSB
+            if name != info.port_name {
+                self.kernel_names.insert(name.clone(), info.port_name);
+            }
+            present.push(name);
+        }
+        let (arrived, awaiting) = self
+            .awaiting
+            .drain(..)
+            .partition::<Vec<_>, _>(|name| present.contains(name));
+        self.awaiting = awaiting;
+        for name in arrived {
+            let Some(idx) = self.port_data_index(&name) else {
+                continue;
+            };
+            self.ports_data[idx].paused = false;
+            stop_flag.store(true, Ordering::Relaxed);
+            let open = PortCommand::ChangePort(name.clone());
+            // `On` puts the current port back, which the shown port must not get
+            let _ = port_tx.send(if idx == self.active_port_idx {
+                open
+            } else {
+                PortCommand::On(name, Box::new(open))
+            });
+        }
+    }
+
+    fn session(&self) -> Session {
+        let ports = self
+            .ports_data
+            .iter()
+            .map(|port| PortState {
+                name: port.name.clone(),
+                // still wanted even though it never showed up
+                paused: port.paused && !self.awaiting.contains(&port.name),
+                local_echo: port.local_echo,
+                timestamps: port.timestamps,
+                error_markers: port.error_markers,
+                broadcast: port.broadcast,
+                logging: port.log.is_some(),
+                scrollback: if self.config.session.scrollback {
+                    port.scroll_buffer.iter().map(Entry::saved).collect()
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect();
+        Session {
+            active: self
+                .ports_data
+                .get(self.active_port_idx)
+                .map(|port| port.name.clone()),
+            pane: match self.mode {
+                Mode::Listing => Some("ports".to_owned()),
+                Mode::Writing => Some("write".to_owned()),
+                Mode::Term => Some("term".to_owned()),
+                _ => None,
+            },
+            ports,
+        }
+    }
+
+    fn pause_port(&mut self, idx: usize, port_tx: &Sender<PortCommand>, stop_flag: &AtomicBool) {
+        let port = &mut self.ports_data[idx];
+        port.paused = true;
+        // the injector goes with the closed port
+        port.faults = None;
+        stop_flag.store(true, Ordering::Relaxed);
+        let _ = port_tx.send(PortCommand::PausePort(port.name.clone()));
+    }
+
+    fn start_script(
+        &mut self,
+        script: &ScriptInfo,
+        port_name: &str,
+        port_tx: &Sender<PortCommand>,
+        stop_flag: &Arc<AtomicBool>,
+        script_tx: &Sender<ScriptEvent>,
+    ) {
+        self.next_script_id += 1;
+        self.running_scripts.push(script::run(
+            self.next_script_id,
+            script,
+            port_name.to_owned(),
+            port_tx.clone(),
+            stop_flag.clone(),
+            script_tx.clone(),
+        ));
+        self.add_data_with_name(
+            port_name.to_owned(),
+            LineDirection::System,
+            format!("── script {} started ──", script.name),
+        );
     }
 
     pub fn toggle_is_active(&mut self) {
@@ -184,13 +803,123 @@ impl App {
     pub fn selected_port(&self, idx: usize) -> Option<&SerialPortInfo> {
         Some(&self.ports[idx])
     }
-    fn add_data_with_name(&mut self, name: String, data: String) {
+    fn add_data_with_name(&mut self, name: String, direction: LineDirection, data: String) {
+        let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) else {
+            return;
+        };
+        let line = data.trim_end_matches(['\r', '\n']).to_owned();
+        port.push(direction, data);
+        if direction == LineDirection::Rx {
+            self.fire_triggers(name, &line, false);
+        }
+    }
+
+    /// Fires the triggers matching a received line, or the start of one that's taking long to
+    /// end, like a prompt. Triggers that fired on the start of a line don't fire again when it
+    /// ends, and mark it once it's there.
+    fn fire_triggers(&mut self, name: String, line: &str, partial: bool) {
+        let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) else {
+            return;
+        };
+        let mut fired = std::mem::take(&mut port.partial_triggers);
+        if !partial && std::mem::take(&mut port.partial_mark) {
+            if let Some(entry) = port.scroll_buffer.back_mut() {
+                entry.marked = true;
+            }
+        }
+        if !self.triggers_enabled {
+            return;
+        }
+        for (idx, trigger) in self.triggers.iter_mut().enumerate() {
+            if fired.contains(&idx) {
+                continue;
+            }
+            let kernel_name = self.kernel_names.get(&name).map(String::as_str);
+            let profile = port.profile.name.as_deref();
+            let Some(actions) = trigger.fire(&name, kernel_name, profile, line) else {
+                continue;
+            };
+            fired.push(idx);
+            if trigger.mark && partial {
+                port.partial_mark = true;
+            } else if trigger.mark {
+                if let Some(entry) = port.scroll_buffer.back_mut() {
+                    entry.marked = true;
+                }
+            }
+            if trigger.bell {
+                let _ = stdout().write_all(b"\x07").and_then(|()| stdout().flush());
+            }
+            port.push(
+                LineDirection::System,
+                format!("── trigger {}: {} ──", trigger.name, trigger.describe()),
+            );
+            match trigger.log {
+                Some(LogAction::Start) if port.log.is_none() => port.toggle_log(),
+                Some(LogAction::Stop) if port.log.is_some() => port.toggle_log(),
+                _ => {}
+            }
+            self.trigger_actions
+                .extend(actions.into_iter().map(|action| (name.clone(), action)));
+        }
+        if partial {
+            port.partial_triggers = fired;
+        }
+    }
+
+    fn update_line_errors(&mut self, name: String, counters: LineErrors) {
         if let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) {
-            port.scroll_buffer.push_back(data);
+            if port.error_markers {
+                let previous = port.line_errors;
+                for (label, now, before) in [
+                    ("BREAK", counters.brk, previous.brk),
+                    ("framing error", counters.frame, previous.frame),
+                    ("parity error", counters.parity, previous.parity),
+                    ("overrun", counters.overrun, previous.overrun),
+                ] {
+                    if now > before {
+                        port.push(
+                            LineDirection::System,
+                            format!("── {} received ({} total) ──", label, now),
+                        );
+                    }
+                }
+            }
+            port.line_errors = counters;
             port.mark_render_dirty();
         }
     }
 
+    fn update_tx_progress(&mut self, name: String, progress: TxProgress) {
+        if let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) {
+            port.tx_progress = (!progress.is_done()).then_some(progress);
+        }
+    }
+
+    fn finish_tx(&mut self, name: String, result: TxResult) {
+        if let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) {
+            match &result {
+                TxResult::Sent(_) => {}
+                TxResult::Failed(sent, reason) => port.push(
+                    LineDirection::System,
+                    format!("── write failed after {} bytes: {} ──", sent, reason),
+                ),
+                TxResult::Cancelled(sent) => port.push(
+                    LineDirection::System,
+                    format!("── write cancelled after {} bytes ──", sent),
+                ),
+            }
+            port.last_tx = Some(result);
+            port.mark_render_dirty();
+        }
+    }
+
+    fn update_share(&mut self, name: String, share: Option<ShareInfo>) {
+        if let Some(port) = self.ports_data.iter_mut().find(|port| port.name == name) {
+            port.share = share;
+        }
+    }
+
     fn is_port_open(&self, name: String) -> bool {
         for i in self.ports_data.iter() {
             if i.name == name {
@@ -204,19 +933,126 @@ impl App {
         self.ports_data.iter().position(|port| port.name == name)
     }
 
-    fn ensure_port_data(&mut self, name: &str) -> usize {
+    /// Finds the port's data, or makes it and tells the serial thread what to open the port with.
+    /// Has to happen before the port is opened.
+    fn ensure_port_data(&mut self, name: &str, port_tx: &Sender<PortCommand>) -> usize {
         if let Some(idx) = self.port_data_index(name) {
             idx
         } else {
-            self.ports_data.push(Port::new(name.to_owned(), false));
+            let info = self.ports.iter().find(|info| info.port_name == name);
+            let kernel_name = self.kernel_names.get(name).map(String::as_str);
+            let profile = self.config.profile_for(name, kernel_name, info);
+            let _ = port_tx.send(PortCommand::Settings(
+                name.to_owned(),
+                profile.settings.clone(),
+            ));
+            let _ = port_tx.send(PortCommand::DownloadDir(
+                name.to_owned(),
+                profile.download_dir.clone(),
+            ));
+            self.ports_data.push(Port::new(name.to_owned(), false, profile));
+            // a hidden port shows up in the list while it is open
+            self.refresh_port_list();
             self.ports_data.len() - 1
         }
     }
 
     fn current_port_title(&self) -> String {
         let active_port = &self.ports_data[self.active_port_idx];
-        let status = if active_port.paused { "paused" } else { "active" };
-        format!("{} [{}]", active_port.name, status)
+        let name = match self.secondary_name(&active_port.name) {
+            Some(secondary) => format!("{} ({})", self.display_name(&active_port.name), secondary),
+            None => active_port.name.clone(),
+        };
+        let status = if self.awaiting.contains(&active_port.name) {
+            "waiting"
+        } else if active_port.paused {
+            "paused"
+        } else {
+            "active"
+        };
+        let broadcast = self.broadcast_group();
+        if active_port.broadcast {
+            format!(
+                "{} [{}] ⇶ broadcast to {} ports",
+                name,
+                status,
+                broadcast.len()
+            )
+        } else {
+            format!("{} [{}]", name, status)
+        }
+    }
+
+    fn broadcast_group(&self) -> Vec<String> {
+        self.ports_data
+            .iter()
+            .filter(|port| port.broadcast)
+            .map(|port| port.name.clone())
+            .collect()
+    }
+
+    fn current_port_status(&self) -> String {
+        let active_port = &self.ports_data[self.active_port_idx];
+        let errors = active_port.line_errors;
+        let sending = match (&active_port.tx_progress, &active_port.last_tx) {
+            (Some(progress), _) => format!(
+                "tx {}/{} · {} queued{} · ",
+                progress.sent,
+                progress.total,
+                progress.queued,
+                if progress.awaiting_prompt { " (waiting for prompt)" } else { "" }
+            ),
+            (None, Some(TxResult::Sent(sent))) => format!("tx ok {} · ", sent),
+            (None, Some(TxResult::Failed(..))) => "tx failed · ".to_owned(),
+            (None, Some(TxResult::Cancelled(..))) => "tx cancelled · ".to_owned(),
+            (None, None) => String::new(),
+        };
+        let shared = match &active_port.share {
+            Some(share) => format!(
+                "shared :{} ({} client{}) · ",
+                share.addr.port(),
+                share.clients.len(),
+                if share.clients.len() == 1 { "" } else { "s" }
+            ),
+            None => String::new(),
+        };
+        let scripts = match self
+            .running_scripts
+            .iter()
+            .filter(|handle| handle.port == active_port.name)
+            .count()
+        {
+            0 => String::new(),
+            1 => " · 1 script".to_owned(),
+            count => format!(" · {} scripts", count),
+        };
+        let profile = match &active_port.profile.name {
+            Some(name) => format!(" · {} {}", name, active_port.profile.settings),
+            None => format!(" · {}", active_port.profile.settings),
+        };
+        let repeating = self
+            .schedules
+            .iter()
+            .filter(|schedule| schedule.port == active_port.name && !schedule.paused)
+            .count();
+        format!(
+            " {}{}brk {} · frm {} · par {} · ovr {}{}{}{}{}{}{}{}{}{} ",
+            sending,
+            shared,
+            errors.brk,
+            errors.frame,
+            errors.parity,
+            errors.overrun,
+            if active_port.error_markers { " · markers" } else { "" },
+            if active_port.local_echo { "" } else { " · no echo" },
+            if active_port.log.is_some() { " · logging" } else { "" },
+            if active_port.faults.is_some() { " · faults" } else { "" },
+            if active_port.tee.is_some() { " · piped" } else { "" },
+            scripts,
+            if repeating > 0 { format!(" · {} repeating", repeating) } else { String::new() },
+            if self.triggers_enabled { "" } else { " · triggers off" },
+            profile
+        )
     }
 }
 
@@ -231,16 +1067,49 @@ enum Mode {
     Listing,
     Config,
     Writing,
+    Transfer,
+    Pacing,
+    Share,
+    Open,
+    Faults,
+    Tee,
+    Scripts,
+    Triggers,
+    Repeat,
+    Alias,
+    Details,
 }
 
 fn main() -> Result<()> {
     // let output = "AT\r\n".as_bytes();
     // port.write(output).expect("Write failed!");
 
-    let (tx, rx) = channel::<(String, String)>();
+    // `--mock <virtual port>` serves a virtual device on a pty instead, for testing host tools
+    let args = std::env::args().collect::<Vec<_>>();
+    // `run <script> --port <port>` runs a test script without the TUI, for CI
+    if args.get(1).is_some_and(|command| command == "run") {
+        std::process::exit(runner::main(&args[2..]));
+    }
+    // `--session <name>` restores and saves a session of that name rather than the last one
+    let mut session_name = None;
+    if let [_, flag, name] = args.as_slice() {
+        if flag == "--session" {
+            session_name = Some(name.clone());
+        }
+        if flag == "--mock" {
+            let (path, handle) = serial::mock::serve_on_pty(name)?;
+            println!("{}", path.display());
+            let _ = handle.join();
+            return Ok(());
+        }
+    }
+
+    let (tx, rx) = channel::<(String, LineDirection, String)>();
     let (port_tx, port_rx) = channel::<PortCommand>();
-    let (result_tx, result_rx) = channel::<(String, bool)>();
+    let (result_tx, result_rx) = channel::<PortEvent>();
+    let (script_tx, script_rx) = channel::<ScriptEvent>();
     stdout().execute(EnterAlternateScreen)?;
+    stdout().execute(EnableBracketedPaste)?;
     enable_raw_mode()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.clear()?;
@@ -255,28 +1124,42 @@ fn main() -> Result<()> {
     let mut textarea = TextArea::default();
     // textarea.set_style(Style::default().bg(Color::Yellow));
     textarea.set_block(Block::default().borders(Borders::ALL).title("write"));
-    let mut accessible_ports: Vec<ListItem> = app
-        .ports
-        .iter()
-        .map(|p| ListItem::new(p.port_name.clone()))
-        .collect();
     let mut state = ListState::default();
     let mut scrollbar_state = ScrollbarState::default();
 
-    if accessible_ports.len() >= 0 {
+    if !app.listed.is_empty() {
         state.select(Some(0));
     }
 
-    if let Err(_e) = port_tx.send(PortCommand::ChangePort(
-        app.selected_port(0).unwrap().port_name.clone(),
-    )) {
-    } else {
-        app.ports_data.push(Port::new(
-            app.selected_port(0).unwrap().port_name.clone(),
-            false,
-        ));
+    let restore = session_name.is_some() || app.config.session.restore;
+    let session_name = session_name.unwrap_or_else(|| session::LAST.to_owned());
+    let session = match Session::load(&session_name) {
+        Ok(session) => session.filter(|_| restore),
+        Err(err) => {
+            app.notice = Some((err.to_string(), Instant::now()));
+            None
+        }
+    };
+    if let Some(session) = session {
+        app.restore_session(session, &port_tx);
+    }
+    if let Some(active_port) = app.ports_data.get(app.active_port_idx) {
+        if let Some(position) = app.list_position(&active_port.name) {
+            state.select(Some(position));
+        }
         main_block_title = app.current_port_title();
+    } else {
+        let first_port = app
+            .listed_port_name(0)
+            .unwrap_or_else(|| app.selected_port(0).unwrap().port_name.clone());
+        app.ensure_port_data(&first_port, &port_tx);
+        if let Err(_e) = port_tx.send(PortCommand::ChangePort(first_port)) {
+            app.ports_data.clear();
+        } else {
+            main_block_title = app.current_port_title();
+        }
     }
+    let mut last_device_check = Instant::now();
     let mut temp_v_scroll = 0;
     let context = libudev::Context::new().unwrap();
     let mut dirty = true;
@@ -308,12 +1191,18 @@ fn main() -> Result<()> {
 
                 let middle = Layout::default()
                     .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(15), Constraint::Percentage(85)])
+                    .constraints([
+                        Constraint::Percentage(app.config.layout.ports_width),
+                        Constraint::Percentage(100 - app.config.layout.ports_width),
+                    ])
                     .split(chunks[1]);
 
                 let io_box = Layout::default()
                     .direction(Direction::Vertical)
-                    .constraints([Constraint::Percentage(90), Constraint::Percentage(10)])
+                    .constraints([
+                        Constraint::Percentage(100 - app.config.layout.write_height),
+                        Constraint::Percentage(app.config.layout.write_height),
+                    ])
                     .split(middle[1]);
 
                 let title_block = Block::default()
@@ -322,16 +1211,21 @@ fn main() -> Result<()> {
                 let selected_block = Block::default()
                     .border_style(Style::default().fg(Color::LightGreen))
                     .borders(Borders::ALL);
-                let title = Paragraph::new(Text::styled(
-                    "⏱ determ",
-                    Style::default().fg(Color::LightYellow),
-                ))
+                let title = Paragraph::new(match &app.notice {
+                    Some((notice, _)) => Text::styled(
+                        format!("⏱ determ · {}", notice),
+                        Style::default()
+                            .fg(Color::LightMagenta)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    None => Text::styled("⏱ determ", Style::default().fg(Color::LightYellow)),
+                })
                 .alignment(Alignment::Center);
 
                 frame.render_widget(title, chunks[0]);
 
                 frame.render_stateful_widget(
-                    List::new(accessible_ports.clone())
+                    List::new(app.port_list_items())
                         .block(
                             if app.mode == Mode::Listing {
                                 selected_block.clone()
@@ -353,11 +1247,11 @@ fn main() -> Result<()> {
 
                 let terminal_text = {
                     let active_port = &mut app.ports_data[app.active_port_idx];
-                    scrollbar_state =
-                        scrollbar_state.content_length(active_port.scroll_buffer.len());
+                    active_port.seen = active_port.scroll_buffer.len();
+                    scrollbar_state = scrollbar_state.content_length(active_port.visible_len());
                     active_port
                         .rendered_text(io_box[0].width, io_box[0].height, app.v_scroll)
-                        .to_owned()
+                        .clone()
                 };
                 frame.render_widget(
                     Paragraph::new(terminal_text).block(
@@ -372,6 +1266,11 @@ fn main() -> Result<()> {
                             io_box[0].width,
                             io_box[0].height
                         ))
+                        .title(
+                            Title::from(app.current_port_status())
+                                .position(Position::Bottom)
+                                .alignment(Alignment::Right),
+                        )
                     ),
                     io_box[0],
                 );
@@ -412,16 +1311,212 @@ fn main() -> Result<()> {
                 );
                 frame.render_widget(textarea.widget(), io_box[1]);
 
+                if let Some(view) = &app.transfer {
+                    ui::render_transfer_progress(frame, io_box[0], view);
+                } else if let Some(setup) = &mut app.transfer_setup {
+                    ui::render_transfer_setup(frame, io_box[0], setup);
+                } else if let Some(setup) = &mut app.pacing_setup {
+                    ui::render_pacing_setup(frame, io_box[0], setup);
+                } else if let Some(name) = &mut app.open_prompt {
+                    ui::render_open_prompt(frame, io_box[0], name);
+                } else if let Some((port_name, alias)) = &mut app.alias_prompt {
+                    ui::render_alias_prompt(frame, io_box[0], port_name, alias);
+                } else if let Some(view) = &app.details {
+                    ui::render_port_details(frame, io_box[0], view);
+                } else if let Some(setup) = &mut app.share_setup {
+                    let share = app.ports_data[app.active_port_idx].share.as_ref();
+                    ui::render_share_setup(frame, io_box[0], setup, share);
+                } else if let Some(setup) = &mut app.fault_setup {
+                    let faults = app.ports_data[app.active_port_idx].faults.as_ref();
+                    ui::render_fault_setup(frame, io_box[0], setup, faults);
+                } else if let Some(setup) = &mut app.tee_setup {
+                    let tee = app.ports_data[app.active_port_idx].tee.as_ref();
+                    ui::render_tee_setup(frame, io_box[0], setup, tee);
+                } else if let Some(setup) = &mut app.repeat_setup {
+                    ui::render_repeat_setup(frame, io_box[0], setup, &app.schedules);
+                } else if let Some(list) = &mut app.trigger_list {
+                    ui::render_trigger_list(
+                        frame,
+                        io_box[0],
+                        &app.triggers,
+                        app.triggers_enabled,
+                        list,
+                    );
+                } else if let Some(picker) = &mut app.script_picker {
+                    ui::render_script_picker(
+                        frame,
+                        io_box[0],
+                        &app.scripts,
+                        &app.running_scripts,
+                        picker,
+                    );
+                }
+
                 frame.render_widget(render_footer(&app.mode), chunks[2]);
             })?;
             dirty = false;
         }
 
-        while let Ok((port_name, recv_data)) = rx.try_recv() {
-            app.add_data_with_name(port_name, recv_data);
+        while let Ok((port_name, direction, recv_data)) = rx.try_recv() {
+            app.add_data_with_name(port_name, direction, recv_data);
+            dirty = true;
+        }
+
+        for (port_name, action) in std::mem::take(&mut app.trigger_actions) {
+            match action {
+                TriggerAction::Send(text) => {
+                    stop_flag.store(true, Ordering::Relaxed);
+                    let command = PortCommand::Write(serial::CmdType::Raw(text));
+                    let _ = port_tx.send(PortCommand::On(port_name, Box::new(command)));
+                }
+                TriggerAction::Control(sequence) => {
+                    stop_flag.store(true, Ordering::Relaxed);
+                    let command = PortCommand::Control(sequence);
+                    let _ = port_tx.send(PortCommand::On(port_name, Box::new(command)));
+                }
+                TriggerAction::Pause => {
+                    if let Some(idx) = app
+                        .ports_data
+                        .iter()
+                        .position(|port| port.name == port_name && !port.paused)
+                    {
+                        app.pause_port(idx, &port_tx, &stop_flag);
+                        main_block_title = app.current_port_title();
+                    }
+                }
+                TriggerAction::Run(command, line) => {
+                    if let Err(err) = trigger::run_command(&command, &port_name, &line) {
+                        app.add_data_with_name(
+                            port_name,
+                            LineDirection::System,
+                            format!("── {} failed to start: {} ──", command, err),
+                        );
+                    }
+                }
+            }
+            dirty = true;
+        }
+
+        while let Ok(event) = script_rx.try_recv() {
+            match event {
+                ScriptEvent::Log(port_name, text) => {
+                    app.add_data_with_name(port_name, LineDirection::System, text);
+                }
+                ScriptEvent::Notify(text) => {
+                    app.notice = Some((text, Instant::now()));
+                }
+                ScriptEvent::Finished(id, result) => {
+                    if let Some(idx) = app.running_scripts.iter().position(|handle| handle.id == id)
+                    {
+                        let handle = app.running_scripts.remove(idx);
+                        let note = match result {
+                            Ok(()) => format!("── script {} finished ──", handle.name),
+                            Err(err) => format!("── script {} failed: {} ──", handle.name, err),
+                        };
+                        app.add_data_with_name(handle.port, LineDirection::System, note);
+                    }
+                }
+            }
+            dirty = true;
+        }
+        if !app.awaiting.is_empty() && last_device_check.elapsed() >= DEVICE_CHECK_INTERVAL {
+            last_device_check = Instant::now();
+            app.open_arrived_ports(&port_tx, &stop_flag);
+            main_block_title = app.current_port_title();
+            dirty = true;
+        }
+        if app
+            .notice
+            .as_ref()
+            .is_some_and(|(_, since)| since.elapsed() >= NOTICE_DURATION)
+        {
+            app.notice = None;
             dirty = true;
         }
 
+        while let Ok(event) = result_rx.try_recv() {
+            match event {
+                PortEvent::Opened(port_name) => {
+                    // the counters restart from zero whenever the port is (re)opened
+                    app.update_line_errors(port_name.clone(), LineErrors::default());
+                    let kernel_name = app.kernel_names.get(&port_name).cloned();
+                    for script in app.scripts.clone() {
+                        if script.runs_on_open(&port_name, kernel_name.as_deref()) {
+                            app.start_script(&script, &port_name, &port_tx, &stop_flag, &script_tx);
+                        }
+                    }
+                    dirty = true;
+                }
+                PortEvent::LineErrors(port_name, counters) => {
+                    app.update_line_errors(port_name, counters);
+                    dirty = true;
+                }
+                PortEvent::TxProgress(port_name, progress) => {
+                    app.update_tx_progress(port_name, progress);
+                    dirty = true;
+                }
+                PortEvent::TxFinished(port_name, _id, result) => {
+                    app.finish_tx(port_name, result);
+                    dirty = true;
+                }
+                PortEvent::Shared(port_name, share) => {
+                    app.update_share(port_name, share);
+                    dirty = true;
+                }
+                PortEvent::PartialLine(port_name, line) => {
+                    app.fire_triggers(port_name, &line, true);
+                    dirty = true;
+                }
+                PortEvent::Schedules(schedules) => {
+                    if let Some(setup) = &mut app.repeat_setup {
+                        let selected = setup.list.selected();
+                        let last = schedules.len().checked_sub(1);
+                        setup.list.select(selected.zip(last).map(|(idx, last)| idx.min(last)));
+                    }
+                    app.schedules = schedules;
+                    dirty = true;
+                }
+                PortEvent::TransferStarted(port_name, protocol, dir, cancel) => {
+                    app.add_data_with_name(
+                        port_name,
+                        LineDirection::System,
+                        format!(
+                            "── {} download started by the device, saving to {} ──",
+                            protocol.name(),
+                            dir.display()
+                        ),
+                    );
+                    if app.transfer.is_none() {
+                        app.transfer_setup = None;
+                        app.transfer = Some(TransferView {
+                            progress: transfer::TransferProgress::new(protocol),
+                            cancel,
+                        });
+                        app.mode = Mode::Transfer;
+                        dirty = true;
+                    }
+                }
+                PortEvent::Transfer(port_name, progress) => {
+                    if progress.state != TransferState::Running {
+                        app.add_data_with_name(
+                            port_name,
+                            LineDirection::System,
+                            format!(
+                                "── {} {}: {} ──",
+                                progress.protocol.name(),
+                                progress.file,
+                                progress.state
+                            ),
+                        );
+                    }
+                    if let Some(view) = &mut app.transfer {
+                        view.progress = progress;
+                        dirty = true;
+                    }
+                }
+            }
+        }
+
         if event::poll(Duration::from_millis(20))? {
             match event::read()? {
                 event::Event::Key(key) => {
@@ -432,22 +1527,542 @@ fn main() -> Result<()> {
                     break;
                 }
 
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Transfer {
+                    if let Some(view) = &app.transfer {
+                        if view.progress.state == TransferState::Running {
+                            if key.code == KeyCode::Esc {
+                                view.cancel.store(true, Ordering::Relaxed);
+                            }
+                        } else if key.code == KeyCode::Esc || key.code == KeyCode::Enter {
+                            app.transfer = None;
+                            app.mode = Mode::Term;
+                        }
+                    } else if let Some(setup) = &mut app.transfer_setup {
+                        if key.code == KeyCode::Esc {
+                            app.transfer_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Tab {
+                            setup.protocol = (setup.protocol + 1) % transfer::Protocol::ALL.len();
+                        } else if key.code == KeyCode::BackTab {
+                            setup.receive = !setup.receive;
+                        } else if key.code == KeyCode::Char('r')
+                            && key.modifiers == KeyModifiers::ALT
+                        {
+                            setup.resume = !setup.resume;
+                        } else if key.code == KeyCode::Enter {
+                            let input = setup.path.lines()[0].trim().to_owned();
+                            let protocol = setup.protocol();
+                            let direction = if setup.receive {
+                                TransferDirection::Receive(if input.is_empty() {
+                                    let port = &app.ports_data[app.active_port_idx];
+                                    port.profile.download_dir.clone()
+                                } else {
+                                    PathBuf::from(input)
+                                })
+                            } else {
+                                TransferDirection::Send(
+                                    input
+                                        .split(';')
+                                        .map(str::trim)
+                                        .filter(|path| !path.is_empty())
+                                        .map(PathBuf::from)
+                                        .collect(),
+                                )
+                            };
+                            let cancel = Arc::new(AtomicBool::new(false));
+                            stop_flag.store(true, Ordering::Relaxed);
+                            let _ = port_tx.send(PortCommand::Transfer(TransferRequest {
+                                protocol,
+                                direction,
+                                resume: setup.resume,
+                                cancel: cancel.clone(),
+                            }));
+                            app.transfer_setup = None;
+                            app.transfer = Some(TransferView {
+                                progress: transfer::TransferProgress::new(protocol),
+                                cancel,
+                            });
+                        } else {
+                            setup.path.input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Open {
+                    if let Some(name) = &mut app.open_prompt {
+                        if key.code == KeyCode::Esc {
+                            app.open_prompt = None;
+                            app.mode = Mode::Listing;
+                        } else if key.code == KeyCode::Enter {
+                            let port_name = name.lines()[0].trim().to_owned();
+                            if !port_name.is_empty() {
+                                stop_flag.store(true, Ordering::Relaxed);
+                                app.active_port_idx = app.ensure_port_data(&port_name, &port_tx);
+                                app.ports_data[app.active_port_idx].paused = false;
+                                let _ = port_tx.send(PortCommand::ChangePort(port_name));
+                                main_block_title = app.current_port_title();
+                            }
+                            app.open_prompt = None;
+                            app.mode = Mode::Term;
+                        } else {
+                            name.input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Alias {
+                    if let Some((port_name, alias)) = &mut app.alias_prompt {
+                        if key.code == KeyCode::Esc {
+                            app.alias_prompt = None;
+                            app.mode = Mode::Listing;
+                        } else if key.code == KeyCode::Enter {
+                            app.prefs.set_alias(port_name, &alias.lines()[0]);
+                            if let Err(err) = app.prefs.save() {
+                                app.notice = Some((err.to_string(), Instant::now()));
+                            }
+                            app.alias_prompt = None;
+                            app.mode = Mode::Listing;
+                            if !app.ports_data.is_empty() {
+                                main_block_title = app.current_port_title();
+                            }
+                        } else {
+                            alias.input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Details {
+                    if let Some(view) = &mut app.details {
+                        if key.code == KeyCode::Esc {
+                            app.details = None;
+                            app.mode = Mode::Listing;
+                        } else if key.code == KeyCode::Up {
+                            view.scroll = view.scroll.saturating_sub(1);
+                        } else if key.code == KeyCode::Down {
+                            view.scroll = view.scroll.saturating_add(1);
+                        } else if key.code == KeyCode::Char('r') {
+                            *view = DetailsView::new(view.port.clone());
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Share {
+                    if let Some(setup) = &mut app.share_setup {
+                        if key.code == KeyCode::Esc {
+                            app.share_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Tab {
+                            setup.read_only = !setup.read_only;
+                        } else if key.code == KeyCode::Delete {
+                            let _ = port_tx.send(PortCommand::StopShare);
+                        } else if key.code == KeyCode::Enter {
+                            let address = setup.address.lines()[0].trim().to_owned();
+                            let _ = port_tx.send(PortCommand::Share(address, setup.read_only));
+                        } else {
+                            setup.address.input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Repeat {
+                    if let Some(setup) = &mut app.repeat_setup {
+                        let fields = setup.fields.len() + 1;
+                        let selected = setup
+                            .list
+                            .selected()
+                            .and_then(|idx| app.schedules.get(idx));
+                        if key.code == KeyCode::Esc {
+                            app.repeat_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Tab {
+                            setup.focus = (setup.focus + 1) % fields;
+                        } else if key.code == KeyCode::BackTab {
+                            setup.focus = (setup.focus + fields - 1) % fields;
+                        } else if setup.focus == RepeatSetup::LIST {
+                            let last = app.schedules.len().saturating_sub(1);
+                            let command = match key.code {
+                                KeyCode::Up => {
+                                    let idx = setup.list.selected().unwrap_or(0);
+                                    setup.list.select(Some(idx.saturating_sub(1)));
+                                    None
+                                }
+                                KeyCode::Down => {
+                                    let idx = setup.list.selected().map_or(0, |idx| idx + 1);
+                                    setup.list.select(Some(idx.min(last)));
+                                    None
+                                }
+                                KeyCode::Char(' ') => selected.map(|schedule| {
+                                    RepeatCommand::Pause(schedule.id, !schedule.paused)
+                                }),
+                                KeyCode::Delete => {
+                                    selected.map(|schedule| RepeatCommand::Remove(schedule.id))
+                                }
+                                _ => None,
+                            };
+                            if let Some(command) = command {
+                                stop_flag.store(true, Ordering::Relaxed);
+                                let _ = port_tx.send(PortCommand::Repeat(command));
+                            }
+                        } else if key.code == KeyCode::Enter {
+                            match setup.parse() {
+                                Ok((data, every, count)) => {
+                                    stop_flag.store(true, Ordering::Relaxed);
+                                    let _ = port_tx.send(PortCommand::Repeat(
+                                        RepeatCommand::Add(data, every, count),
+                                    ));
+                                    setup.error = None;
+                                }
+                                Err(err) => setup.error = Some(err),
+                            }
+                        } else {
+                            setup.focused().input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Triggers {
+                    if let Some(list) = &mut app.trigger_list {
+                        if key.code == KeyCode::Esc {
+                            app.trigger_list = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Up {
+                            list.select(list.selected().map(|idx| idx.saturating_sub(1)));
+                        } else if key.code == KeyCode::Down {
+                            let last = app.triggers.len().saturating_sub(1);
+                            list.select(list.selected().map(|idx| (idx + 1).min(last)));
+                        } else if key.code == KeyCode::Char(' ') || key.code == KeyCode::Enter {
+                            if let Some(trigger) =
+                                list.selected().and_then(|idx| app.triggers.get_mut(idx))
+                            {
+                                trigger.enabled = !trigger.enabled;
+                            }
+                        } else if key.code == KeyCode::Char('a') {
+                            app.triggers_enabled = !app.triggers_enabled;
+                        } else if key.code == KeyCode::Char('r') {
+                            match trigger::load() {
+                                Ok(triggers) => {
+                                    let config = app.config.triggers.iter().cloned();
+                                    app.triggers = config.chain(triggers).collect();
+                                }
+                                Err(err) => app.notice = Some((err.to_string(), Instant::now())),
+                            }
+                            list.select(Some(0));
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Scripts {
+                    if let Some(picker) = &mut app.script_picker {
+                        let selected = picker.selected().and_then(|idx| app.scripts.get(idx));
+                        if key.code == KeyCode::Esc {
+                            app.script_picker = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Up {
+                            picker.select(picker.selected().map(|idx| idx.saturating_sub(1)));
+                        } else if key.code == KeyCode::Down {
+                            let last = app.scripts.len().saturating_sub(1);
+                            picker.select(picker.selected().map(|idx| (idx + 1).min(last)));
+                        } else if key.code == KeyCode::Delete {
+                            if let Some(script) = selected {
+                                for handle in &app.running_scripts {
+                                    if handle.name == script.name {
+                                        handle.stop();
+                                    }
+                                }
+                            }
+                        } else if key.code == KeyCode::Enter {
+                            if let Some(script) = selected.cloned() {
+                                let port_name = app.ports_data[app.active_port_idx].name.clone();
+                                app.start_script(
+                                    &script,
+                                    &port_name,
+                                    &port_tx,
+                                    &stop_flag,
+                                    &script_tx,
+                                );
+                                app.script_picker = None;
+                                app.mode = Mode::Term;
+                            }
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Tee {
+                    if let Some(setup) = &mut app.tee_setup {
+                        if key.code == KeyCode::Esc {
+                            app.tee_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Tab {
+                            setup.feed_back = !setup.feed_back;
+                        } else if key.code == KeyCode::Delete {
+                            app.ports_data[app.active_port_idx].tee = None;
+                            let _ = port_tx.send(PortCommand::Tee(None));
+                            app.tee_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Enter {
+                            let command = setup.command.lines()[0].trim().to_owned();
+                            let tee = (!command.is_empty()).then_some((command, setup.feed_back));
+                            app.ports_data[app.active_port_idx].tee = tee.clone();
+                            let _ = port_tx.send(PortCommand::Tee(tee));
+                            app.tee_setup = None;
+                            app.mode = Mode::Term;
+                        } else {
+                            setup.command.input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Faults {
+                    if let Some(setup) = &mut app.fault_setup {
+                        if key.code == KeyCode::Esc {
+                            app.fault_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Delete {
+                            app.ports_data[app.active_port_idx].faults = None;
+                            let _ = port_tx.send(PortCommand::Faults(None));
+                            app.fault_setup = None;
+                            app.mode = Mode::Term;
+                        } else if key.code == KeyCode::Enter {
+                            match setup.parse() {
+                                Ok(faults) => {
+                                    app.ports_data[app.active_port_idx].faults = faults;
+                                    let _ = port_tx.send(PortCommand::Faults(faults));
+                                    app.fault_setup = None;
+                                    app.mode = Mode::Term;
+                                }
+                                Err(err) => setup.error = Some(err),
+                            }
+                        } else {
+                            setup.spec.input(key);
+                            setup.error = None;
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
+                if key.kind == KeyEventKind::Press && app.mode == Mode::Pacing {
+                    if let Some(setup) = &mut app.pacing_setup {
+                        if key.code == KeyCode::Esc {
+                            app.pacing_setup = None;
+                            app.mode = Mode::Writing;
+                        } else if key.code == KeyCode::Tab {
+                            setup.focus = (setup.focus + 1) % setup.fields.len();
+                        } else if key.code == KeyCode::BackTab {
+                            setup.focus = (setup.focus + setup.fields.len() - 1) % setup.fields.len();
+                        } else if key.code == KeyCode::Enter {
+                            match setup.parse() {
+                                Ok((file, pacing)) => {
+                                    let data = match file {
+                                        Some(path) => match std::fs::read(&path) {
+                                            Ok(data) => Some(data),
+                                            Err(err) => {
+                                                setup.error =
+                                                    Some(format!("{}: {}", path.display(), err));
+                                                dirty = true;
+                                                continue;
+                                            }
+                                        },
+                                        None => None,
+                                    };
+                                    if let Some(data) = data {
+                                        stop_flag.store(true, Ordering::Relaxed);
+                                        let _ = port_tx
+                                            .send(PortCommand::SendPaced(data, pacing.clone()));
+                                    }
+                                    app.pacing = pacing;
+                                    app.pacing_setup = None;
+                                    app.mode = Mode::Writing;
+                                }
+                                Err(err) => setup.error = Some(err),
+                            }
+                        } else {
+                            setup.focused().input(key);
+                        }
+                    }
+                    dirty = true;
+                    continue;
+                }
+
                 if key.kind == KeyEventKind::Press {
+                    if key.code == KeyCode::Char('o') && key.modifiers == KeyModifiers::ALT {
+                        app.pacing_setup = Some(PacingSetup::new(&app.pacing));
+                        app.mode = Mode::Pacing;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('x') && key.modifiers == KeyModifiers::ALT {
+                        stop_flag.store(true, Ordering::Relaxed);
+                        let _ = port_tx.send(PortCommand::CancelTx);
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('n') && key.modifiers == KeyModifiers::ALT {
+                        app.open_prompt = Some(TextArea::default());
+                        app.mode = Mode::Open;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('t') && key.modifiers == KeyModifiers::ALT {
+                        let share = app.ports_data[app.active_port_idx].share.as_ref();
+                        app.share_setup = Some(ShareSetup::new(share));
+                        app.mode = Mode::Share;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('w') && key.modifiers == KeyModifiers::ALT {
+                        let active_port = &mut app.ports_data[app.active_port_idx];
+                        active_port.broadcast = !active_port.broadcast;
+                        let note = if active_port.broadcast {
+                            "── joined the broadcast group ──"
+                        } else {
+                            "── left the broadcast group ──"
+                        };
+                        active_port.push(LineDirection::System, note.to_owned());
+                        stop_flag.store(true, Ordering::Relaxed);
+                        let _ = port_tx.send(PortCommand::Broadcast(app.broadcast_group()));
+                        main_block_title = app.current_port_title();
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('u') && key.modifiers == KeyModifiers::ALT {
+                        app.repeat_setup = Some(RepeatSetup::new());
+                        app.mode = Mode::Repeat;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('k') && key.modifiers == KeyModifiers::ALT {
+                        let mut list = ListState::default();
+                        list.select(Some(0));
+                        app.trigger_list = Some(list);
+                        app.mode = Mode::Triggers;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('a') && key.modifiers == KeyModifiers::ALT {
+                        app.scripts = script::discover();
+                        let mut picker = ListState::default();
+                        picker.select(Some(0));
+                        app.script_picker = Some(picker);
+                        app.mode = Mode::Scripts;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if let KeyCode::F(number) = key.code {
+                        if let Some(text) =
+                            app.ports_data[app.active_port_idx].profile.macro_for(number)
+                        {
+                            stop_flag.store(true, Ordering::Relaxed);
+                            let _ = port_tx
+                                .send(PortCommand::Write(serial::CmdType::Raw(text.to_owned())));
+                            dirty = true;
+                            continue;
+                        }
+                        if let Some(script) = app
+                            .scripts
+                            .iter()
+                            .find(|script| script.key == Some(number))
+                            .cloned()
+                        {
+                            let port_name = app.ports_data[app.active_port_idx].name.clone();
+                            app.start_script(&script, &port_name, &port_tx, &stop_flag, &script_tx);
+                            dirty = true;
+                            continue;
+                        }
+                    }
+
+                    if key.code == KeyCode::Char('c') && key.modifiers == KeyModifiers::ALT {
+                        let tee = app.ports_data[app.active_port_idx].tee.as_ref();
+                        app.tee_setup = Some(TeeSetup::new(tee));
+                        app.mode = Mode::Tee;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('g') && key.modifiers == KeyModifiers::ALT {
+                        let faults = app.ports_data[app.active_port_idx].faults.as_ref();
+                        app.fault_setup = Some(FaultSetup::new(faults));
+                        app.mode = Mode::Faults;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('f') && key.modifiers == KeyModifiers::ALT {
+                        app.transfer_setup = Some(TransferSetup::new());
+                        app.mode = Mode::Transfer;
+                        dirty = true;
+                        continue;
+                    }
+
                     if key.code == KeyCode::Char('p') && key.modifiers == KeyModifiers::ALT {
                         let active_port = &mut app.ports_data[app.active_port_idx];
+                        // whatever the user chooses, the session no longer waits for it
+                        app.awaiting.retain(|name| *name != active_port.name);
                         if active_port.paused {
                             active_port.paused = false;
                             let _ = port_tx.send(PortCommand::ChangePort(active_port.name.clone()));
                         } else {
-                            active_port.paused = true;
-                            stop_flag.store(true, Ordering::Relaxed);
-                            let _ = port_tx.send(PortCommand::PausePort(active_port.name.clone()));
+                            app.pause_port(app.active_port_idx, &port_tx, &stop_flag);
                         }
                         main_block_title = app.current_port_title();
                         dirty = true;
                         continue;
                     }
 
+                    if key.code == KeyCode::Char('m') && key.modifiers == KeyModifiers::ALT {
+                        let active_port = &mut app.ports_data[app.active_port_idx];
+                        active_port.error_markers = !active_port.error_markers;
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('e') && key.modifiers == KeyModifiers::ALT {
+                        let active_port = &mut app.ports_data[app.active_port_idx];
+                        active_port.local_echo = !active_port.local_echo;
+                        active_port.mark_render_dirty();
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('i') && key.modifiers == KeyModifiers::ALT {
+                        let active_port = &mut app.ports_data[app.active_port_idx];
+                        active_port.timestamps = !active_port.timestamps;
+                        active_port.mark_render_dirty();
+                        dirty = true;
+                        continue;
+                    }
+
+                    if key.code == KeyCode::Char('l') && key.modifiers == KeyModifiers::ALT {
+                        app.ports_data[app.active_port_idx].toggle_log();
+                        dirty = true;
+                        continue;
+                    }
+
                     if app.mode != Mode::Listing
                         && (key.code == KeyCode::Up
                             || key.code == KeyCode::Down
@@ -487,8 +2102,11 @@ fn main() -> Result<()> {
                         }
                     } else if app.mode == Mode::Listing {
                         let idx: usize = state.selected().unwrap_or(0);
-                        if key.code == KeyCode::Down {
-                            if idx < accessible_ports.len() - 1 {
+                        let selected_name = app.listed_port_name(idx);
+                        if app.listed.is_empty() && key.code != KeyCode::Char('h') {
+                            // nothing to pick from, until hidden ports are shown again
+                        } else if key.code == KeyCode::Down {
+                            if idx < app.listed.len() - 1 {
                                 state.select(Some(idx + 1));
                             } else {
                                 state.select(Some(0));
@@ -504,20 +2122,52 @@ fn main() -> Result<()> {
                             if idx > 0 {
                                 state.select(Some(idx - 1));
                             } else {
-                                state.select(Some(accessible_ports.len() - 1));
+                                state.select(Some(app.listed.len() - 1));
                             }
                             dirty = true;
+                        } else if key.code == KeyCode::Char('d') {
+                            if let Some(name) = selected_name {
+                                app.details = Some(DetailsView::new(name));
+                                app.mode = Mode::Details;
+                                dirty = true;
+                            }
+                        } else if key.code == KeyCode::Char('a') {
+                            if let Some(name) = selected_name {
+                                let mut alias = TextArea::default();
+                                alias.insert_str(app.prefs.alias(&name).unwrap_or_default());
+                                app.alias_prompt = Some((name, alias));
+                                app.mode = Mode::Alias;
+                                dirty = true;
+                            }
+                        } else if matches!(key.code, KeyCode::Char('f') | KeyCode::Char('h')) {
+                            if key.code == KeyCode::Char('f') {
+                                if let Some(name) = &selected_name {
+                                    app.prefs.toggle_favourite(name);
+                                }
+                            } else {
+                                app.prefs.hide_builtin = !app.prefs.hide_builtin;
+                            }
+                            if let Err(err) = app.prefs.save() {
+                                app.notice = Some((err.to_string(), Instant::now()));
+                            }
+                            app.refresh_port_list();
+                            // stay on the same port as it moves
+                            let position = selected_name
+                                .and_then(|name| app.list_position(&name))
+                                .unwrap_or(0);
+                            state.select((!app.listed.is_empty()).then_some(position));
+                            dirty = true;
                         } else if key.code == KeyCode::Enter {
                             stop_flag.store(true, Ordering::Relaxed);
-                            let selected_port_name =
-                                app.ports[state.selected().unwrap()].port_name.clone();
+                            let selected_port_name = selected_name.unwrap();
                             if !app.is_port_open(selected_port_name.clone()) {
+                                app.active_port_idx =
+                                    app.ensure_port_data(&selected_port_name, &port_tx);
                                 if let Err(_e) =
                                     port_tx.clone().send(PortCommand::ChangePort(selected_port_name.clone()))
                                 {
                                     panic!("{}", _e);
                                 } else {
-                                    app.active_port_idx = app.ensure_port_data(&selected_port_name);
                                     app.ports_data[app.active_port_idx].paused = false;
                                     main_block_title = app.current_port_title();
                                     dirty = true;
@@ -530,6 +2180,8 @@ fn main() -> Result<()> {
                                     let _ = port_tx
                                         .clone()
                                         .send(PortCommand::ChangePort(selected_port_name.clone()));
+                                } else {
+                                    let _ = port_tx.send(PortCommand::Show(selected_port_name));
                                 }
                                 main_block_title = app.current_port_title();
                                 dirty = true;
@@ -538,7 +2190,8 @@ fn main() -> Result<()> {
                     } else if app.mode == Mode::Writing {
                         if key.code == KeyCode::Enter {
                             let mut tmp_data = textarea.lines()[0].clone();
-                            tmp_data.push('\n');
+                            let port = &app.ports_data[app.active_port_idx];
+                            tmp_data.push_str(&port.profile.line_ending);
                             port_tx.send(PortCommand::Write(serial::CmdType::Raw(tmp_data)));
                             textarea = TextArea::default();
                             textarea.set_block(Block::default().borders(Borders::ALL).title("write"));
@@ -564,6 +2217,27 @@ fn main() -> Result<()> {
                                 app.ports_data[app.active_port_idx].rts,
                             )));
                             dirty = true;
+                        } else if key.code == KeyCode::Char('b')
+                            && key.modifiers == KeyModifiers::ALT
+                        {
+                            // hold the line in BREAK, long enough for a SysRq
+                            let _ = port_tx.send(PortCommand::Break(Duration::from_millis(250)));
+                        } else if key.code == KeyCode::Char('z')
+                            && key.modifiers == KeyModifiers::ALT
+                        {
+                            // the device's own way to reset, from its profile
+                            let port = &mut app.ports_data[app.active_port_idx];
+                            match &port.profile.reset {
+                                Some(reset) => {
+                                    stop_flag.store(true, Ordering::Relaxed);
+                                    let _ = port_tx.send(PortCommand::Control(reset.clone()));
+                                }
+                                None => port.push(
+                                    LineDirection::System,
+                                    "── no reset sequence in the port's profile ──".to_owned(),
+                                ),
+                            }
+                            dirty = true;
                         } else if key.code == KeyCode::Left
                             && key.modifiers == KeyModifiers::CONTROL
                         {
@@ -617,6 +2291,46 @@ fn main() -> Result<()> {
                     // break;
                 }
                 }
+                event::Event::Paste(text) => {
+                    if app.mode == Mode::Writing {
+                        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                        // complete lines go out paced, an unterminated tail stays in the write box
+                        let (lines, tail) = match text.rfind('\n') {
+                            Some(idx) => text.split_at(idx + 1),
+                            None => ("", text.as_str()),
+                        };
+                        if !lines.is_empty() {
+                            stop_flag.store(true, Ordering::Relaxed);
+                            let _ = port_tx.send(PortCommand::SendPaced(
+                                lines.as_bytes().to_vec(),
+                                app.pacing.clone(),
+                            ));
+                        }
+                        textarea.insert_str(tail);
+                        dirty = true;
+                    } else if let Some(setup) = &mut app.pacing_setup {
+                        setup.focused().insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some(setup) = &mut app.transfer_setup {
+                        setup.path.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some(name) = &mut app.open_prompt {
+                        name.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some((_, alias)) = &mut app.alias_prompt {
+                        alias.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some(setup) = &mut app.share_setup {
+                        setup.address.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some(setup) = &mut app.fault_setup {
+                        setup.spec.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    } else if let Some(setup) = &mut app.tee_setup {
+                        setup.command.insert_str(text.replace(['\r', '\n'], ""));
+                        dirty = true;
+                    }
+                }
                 event::Event::Resize(_, _) => {
                     dirty = true;
                 }
@@ -625,8 +2339,14 @@ fn main() -> Result<()> {
         }
     }
 
+    stdout().execute(DisableBracketedPaste)?;
     stdout().execute(LeaveAlternateScreen)?;
     disable_raw_mode()?;
+    if !app.ports_data.is_empty() {
+        if let Err(err) = app.session().save(&session_name) {
+            eprintln!("could not save the session: {}", err);
+        }
+    }
     Ok(())
 }
 
@@ -639,7 +2359,25 @@ fn render_footer<'a>(mode: &Mode) -> Paragraph<'a> {
     // Style::default().bg(Color::LightGreen).fg(Color::White)
 
     let line = Line::from(match mode {
-        Mode::Config | Mode::Main | Mode::Term | Mode::Listing => {
+        Mode::Listing => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Open "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Details "),
+            Span::styled(" d ", STYLE),
+            Span::raw(" Alias "),
+            Span::styled(" a ", STYLE),
+            Span::raw(" Favourite "),
+            Span::styled(" f ", STYLE),
+            Span::raw(" Hide ttyS* "),
+            Span::styled(" h ", STYLE),
+            Span::raw(" Pause/Resume "),
+            Span::styled(" Alt + p ", STYLE),
+            Span::raw(" Open by name "),
+            Span::styled(" Alt + n ", STYLE),
+        ],
+        Mode::Config | Mode::Main | Mode::Term => {
             vec![
                 Span::raw("Quit "),
                 Span::styled(" Alt + q ", STYLE),
@@ -649,8 +2387,48 @@ fn render_footer<'a>(mode: &Mode) -> Paragraph<'a> {
                 Span::styled("Alt + s ", STYLE),
                 Span::raw(" Scroll "),
                 Span::styled(" 🠕 🠗 ", STYLE),
+                Span::raw(" Error markers "),
+                Span::styled(" Alt + m ", STYLE),
+                Span::raw(" Local echo "),
+                Span::styled(" Alt + e ", STYLE),
+                Span::raw(" Timestamps "),
+                Span::styled(" Alt + i ", STYLE),
+                Span::raw(" Log "),
+                Span::styled(" Alt + l ", STYLE),
+                Span::raw(" File transfer "),
+                Span::styled(" Alt + f ", STYLE),
+                Span::raw(" Share "),
+                Span::styled(" Alt + t ", STYLE),
+                Span::raw(" Faults "),
+                Span::styled(" Alt + g ", STYLE),
+                Span::raw(" Pipe "),
+                Span::styled(" Alt + c ", STYLE),
+                Span::raw(" Scripts "),
+                Span::styled(" Alt + a ", STYLE),
+                Span::raw(" Triggers "),
+                Span::styled(" Alt + k ", STYLE),
+                Span::raw(" Repeat "),
+                Span::styled(" Alt + u ", STYLE),
+                Span::raw(" Broadcast "),
+                Span::styled(" Alt + w ", STYLE),
+                Span::raw(" Open by name "),
+                Span::styled(" Alt + n ", STYLE),
             ]
         }
+        Mode::Transfer => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Protocol "),
+            Span::styled(" Tab ", STYLE),
+            Span::raw(" Send/Receive "),
+            Span::styled(" Shift + Tab ", STYLE),
+            Span::raw(" Resume "),
+            Span::styled(" Alt + r ", STYLE),
+            Span::raw(" Start "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Cancel/Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
         Mode::Writing => vec![
             Span::raw("Quit "),
             Span::styled(" Alt + q ", STYLE),
@@ -666,6 +2444,124 @@ fn render_footer<'a>(mode: &Mode) -> Paragraph<'a> {
             Span::styled(r#" DTR "#, STYLE),
             Span::raw(" Alt+r "),
             Span::styled(r#" RTS "#, STYLE),
+            Span::raw(" Alt+b "),
+            Span::styled(r#" BREAK "#, STYLE),
+            Span::raw(" Alt+z "),
+            Span::styled(r#" Reset "#, STYLE),
+            Span::raw(" Alt+o "),
+            Span::styled(r#" Send file/pacing "#, STYLE),
+            Span::raw(" Alt+x "),
+            Span::styled(r#" Cancel sending "#, STYLE),
+        ],
+        Mode::Details => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Scroll "),
+            Span::styled(" 🠕 🠗 ", STYLE),
+            Span::raw(" Refresh "),
+            Span::styled(" r ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Alias => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Save "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Open => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Open "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Repeat => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Next field "),
+            Span::styled(" Tab ", STYLE),
+            Span::raw(" Start "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Pause/Resume "),
+            Span::styled(" Space ", STYLE),
+            Span::raw(" Remove "),
+            Span::styled(" Del ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Triggers => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Select "),
+            Span::styled(" 🠕 🠗 ", STYLE),
+            Span::raw(" Toggle "),
+            Span::styled(" Space ", STYLE),
+            Span::raw(" All on/off "),
+            Span::styled(" a ", STYLE),
+            Span::raw(" Reload "),
+            Span::styled(" r ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Scripts => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Select "),
+            Span::styled(" 🠕 🠗 ", STYLE),
+            Span::raw(" Run "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Stop "),
+            Span::styled(" Del ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Tee => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Stdout to port "),
+            Span::styled(" Tab ", STYLE),
+            Span::raw(" Run "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Stop "),
+            Span::styled(" Del ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Faults => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Apply "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Stop "),
+            Span::styled(" Del ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Share => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Read-only "),
+            Span::styled(" Tab ", STYLE),
+            Span::raw(" Share "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Stop "),
+            Span::styled(" Del ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
+        ],
+        Mode::Pacing => vec![
+            Span::raw("Quit "),
+            Span::styled(" Alt + q ", STYLE),
+            Span::raw(" Next field "),
+            Span::styled(" Tab ", STYLE),
+            Span::raw(" Apply "),
+            Span::styled(" Enter ", STYLE),
+            Span::raw(" Close "),
+            Span::styled(" Esc ", STYLE),
         ],
     });
 
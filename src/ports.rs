@@ -0,0 +1,150 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::config;
+
+/// What the user told us about ports, kept in `ports.toml` in the config directory, for example
+///
+/// ```toml
+/// hide_builtin = true
+///
+/// [[port]]
+/// name = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K5LXY-if00-port0"
+/// alias = "gateway"
+/// favourite = true
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+pub struct PortPrefs {
+    /// Leave the built-in `ttyS*` ports out of the list, most of them aren't wired to anything.
+    #[serde(default)]
+    pub hide_builtin: bool,
+    #[serde(default, rename = "port")]
+    ports: Vec<PortPref>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortPref {
+    name: String,
+    alias: Option<String>,
+    #[serde(default)]
+    favourite: bool,
+}
+
+fn path() -> Option<PathBuf> {
+    config::dir().map(|dir| dir.join("ports.toml"))
+}
+
+impl PortPrefs {
+    /// Reads the file, which doesn't have to exist.
+    pub fn load() -> io::Result<PortPrefs> {
+        let Some(path) = path() else {
+            return Ok(PortPrefs::default());
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(PortPrefs::default()),
+            Err(err) => return Err(err),
+        };
+        toml::from_str(&source).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err.message()),
+            )
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no config directory to save to")
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let source = toml::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        fs::write(path, source)
+    }
+
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(|pref| pref.alias.as_deref())
+    }
+
+    pub fn is_favourite(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|pref| pref.favourite)
+    }
+
+    /// Sets the alias of a port, an empty one removes it.
+    pub fn set_alias(&mut self, name: &str, alias: &str) {
+        let alias = alias.trim();
+        self.get_mut(name).alias = (!alias.is_empty()).then(|| alias.to_owned());
+        self.forget_unused();
+    }
+
+    pub fn toggle_favourite(&mut self, name: &str) {
+        let pref = self.get_mut(name);
+        pref.favourite = !pref.favourite;
+        self.forget_unused();
+    }
+
+    /// Moves what was saved for `from` to `to`, unless `to` has something of its own.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if self.get(to).is_some() {
+            return;
+        }
+        if let Some(pref) = self.ports.iter_mut().find(|pref| pref.name == from) {
+            pref.name = to.to_owned();
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&PortPref> {
+        self.ports.iter().find(|pref| pref.name == name)
+    }
+
+    fn get_mut(&mut self, name: &str) -> &mut PortPref {
+        match self.ports.iter().position(|pref| pref.name == name) {
+            Some(idx) => &mut self.ports[idx],
+            None => {
+                self.ports.push(PortPref {
+                    name: name.to_owned(),
+                    alias: None,
+                    favourite: false,
+                });
+                self.ports.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn forget_unused(&mut self) {
+        self.ports
+            .retain(|pref| pref.alias.is_some() || pref.favourite);
+    }
+}
+
+/// The built-in UARTs, `/dev/ttyS0` and so on.
+pub fn is_builtin(info: &SerialPortInfo) -> bool {
+    info.port_name.starts_with("/dev/ttyS") && !matches!(info.port_type, SerialPortType::UsbPort(_))
+}
+
+/// What the system knows about a USB port: product, manufacturer and serial number.
+pub fn usb_details(info: &SerialPortInfo) -> Option<String> {
+    let SerialPortType::UsbPort(usb) = &info.port_type else {
+        return None;
+    };
+    let details = [
+        usb.product.clone(),
+        usb.manufacturer.clone(),
+        usb.serial_number
+            .as_ref()
+            .map(|serial| format!("#{}", serial)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    if details.is_empty() {
+        Some(format!("{:04x}:{:04x}", usb.vid, usb.pid))
+    } else {
+        Some(details.join(" · "))
+    }
+}
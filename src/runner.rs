@@ -0,0 +1,734 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, ErrorKind, LineWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    config, format_time,
+    serial::{identity, settings::PortSettings, transport, LineDirection},
+};
+
+const USAGE: &str = "usage: determ run <script.toml> --port <port> [--baud <rate>] \
+                     [--junit <file>] [--transcript <file>]";
+
+/// How long a read waits, which is also how late a timeout can be noticed.
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+/// How much received text an `expect` looks back on.
+const EXPECT_WINDOW: usize = 64 * 1024;
+
+/// A test script, for example
+///
+/// ```toml
+/// name = "modem smoke test"
+/// timeout_ms = 2000
+///
+/// [[step]]
+/// name = "attention"
+/// send = "AT\r\n"
+/// expect = 'OK'
+/// retries = 2
+///
+/// [[step]]
+/// name = "serial number"
+/// send = "AT+CGSN\r\n"
+/// expect = '(?P<imei>\d{15})\r\nOK'
+///
+/// [[step]]
+/// send = "AT+LOOKUP=${imei}\r\n"
+/// expect = 'FOUND ${imei}'
+/// timeout_ms = 10000
+/// ```
+///
+/// Named groups in `expect` capture into variables that later steps use as `${name}`.
+#[derive(Deserialize)]
+struct Config {
+    name: Option<String>,
+    /// Defaults for the steps.
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    retries: u32,
+    #[serde(default, rename = "step")]
+    steps: Vec<StepConfig>,
+}
+
+#[derive(Deserialize)]
+struct StepConfig {
+    name: Option<String>,
+    /// Waited before sending.
+    #[serde(default)]
+    delay_ms: u64,
+    send: Option<String>,
+    expect: Option<String>,
+    timeout_ms: Option<u64>,
+    /// How many more times the step is tried, send included, when `expect` times out.
+    retries: Option<u32>,
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+    /// The port or the transcript failed, which ends the run.
+    Error(String),
+    /// An earlier step failed.
+    Skipped,
+}
+
+struct StepResult {
+    name: String,
+    outcome: Outcome,
+    time: Duration,
+    attempts: u32,
+}
+
+struct Options {
+    script: PathBuf,
+    port: String,
+    /// Overrides the baud rate of the port's profile.
+    baud: Option<u32>,
+    junit: PathBuf,
+    transcript: PathBuf,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut script = None;
+        let (mut port, mut baud, mut junit, mut transcript) = (None, None, None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let slot = match arg.as_str() {
+                "--port" => &mut port,
+                "--baud" => &mut baud,
+                "--junit" => &mut junit,
+                "--transcript" => &mut transcript,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                path => {
+                    script = Some(PathBuf::from(path));
+                    continue;
+                }
+            };
+            *slot = Some(
+                args.next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?
+                    .clone(),
+            );
+        }
+        let script = script.ok_or("no script given")?;
+        let port = port.ok_or("no --port given")?;
+        let baud = baud
+            .map(|baud| {
+                baud.parse::<u32>()
+                    .ok()
+                    .filter(|baud| *baud > 0)
+                    .ok_or_else(|| format!("--baud {}: not a baud rate", baud))
+            })
+            .transpose()?;
+        let stem = script
+            .file_stem()
+            .map_or_else(|| "determ".into(), |stem| stem.to_string_lossy());
+        Ok(Options {
+            junit: junit.map_or_else(|| format!("{}.junit.xml", stem).into(), PathBuf::from),
+            transcript: transcript
+                .map_or_else(|| format!("{}.transcript.log", stem).into(), PathBuf::from),
+            script,
+            port,
+            baud,
+        })
+    }
+}
+
+/// Everything that went over the port, one timestamped line at a time, in the format of the
+/// TUI's logs.
+struct Transcript {
+    file: LineWriter<File>,
+    /// Received text that hasn't ended its line yet.
+    line: String,
+}
+
+impl Transcript {
+    fn create(path: &Path) -> io::Result<Transcript> {
+        Ok(Transcript {
+            file: LineWriter::new(File::create(path)?),
+            line: String::new(),
+        })
+    }
+
+    fn write(&mut self, direction: LineDirection, text: &str) -> io::Result<()> {
+        let (date, time) = format_time(SystemTime::now());
+        let marker = match direction {
+            LineDirection::Rx => "RX",
+            LineDirection::Tx => "TX",
+            LineDirection::System => "--",
+        };
+        writeln!(self.file, "{} {} {} {}", date, time, marker, text)
+    }
+
+    fn sent(&mut self, text: &str) -> io::Result<()> {
+        self.flush_received()?;
+        for line in text.lines() {
+            self.write(LineDirection::Tx, line.trim_end_matches('\r'))?;
+        }
+        Ok(())
+    }
+
+    fn received(&mut self, text: &str) -> io::Result<()> {
+        self.line.push_str(text);
+        while let Some(end) = self.line.find('\n') {
+            let line = self.line.drain(..=end).collect::<String>();
+            self.write(LineDirection::Rx, line.trim_end_matches(['\r', '\n']))?;
+        }
+        Ok(())
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        self.flush_received()?;
+        self.write(LineDirection::System, &format!("── {} ──", text))
+    }
+
+    fn flush_received(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = std::mem::take(&mut self.line);
+        self.write(LineDirection::Rx, line.trim_end_matches('\r'))
+    }
+}
+
+/// Runs `determ run`, returning the exit code: 0 when every step passed, 1 when one failed and 2
+/// when the script couldn't be run at all.
+pub fn main(args: &[String]) -> i32 {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            return 2;
+        }
+    };
+    let settings = match port_settings(&options) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+    match run(&options, &settings) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("{}", err);
+            2
+        }
+    }
+}
+
+/// What the port is opened with: its profile in `config.toml`, like in the TUI, and `--baud`.
+fn port_settings(options: &Options) -> io::Result<PortSettings> {
+    let path = fs::canonicalize(&options.port).ok();
+    let info = serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|info| {
+            options.port == info.port_name
+                || options.port == identity::stable_name(info)
+                || path.as_deref() == Some(Path::new(&info.port_name))
+        });
+    let (name, kernel_name) = match &info {
+        Some(info) => (identity::stable_name(info), Some(info.port_name.as_str())),
+        None => (options.port.clone(), None),
+    };
+    let mut settings = config::Config::load()?
+        .profile_for(&name, kernel_name, info.as_ref())
+        .settings;
+    if let Some(baud) = options.baud {
+        settings.baud_rate = baud;
+    }
+    Ok(settings)
+}
+
+/// Runs the script. The error is for the script not being run at all, once the steps have
+/// started the JUnit file is always written.
+fn run(options: &Options, settings: &PortSettings) -> io::Result<bool> {
+    let source = fs::read_to_string(&options.script).map_err(|err| {
+        io::Error::new(err.kind(), format!("{}: {}", options.script.display(), err))
+    })?;
+    let config = toml::from_str::<Config>(&source).map_err(|err| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", options.script.display(), err.message()),
+        )
+    })?;
+    let suite = config.name.clone().unwrap_or_else(|| {
+        options
+            .script
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+    });
+
+    let mut transcript = Transcript::create(&options.transcript).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("{}: {}", options.transcript.display(), err),
+        )
+    })?;
+    let mut port = transport::open_with(&options.port, settings, READ_TIMEOUT)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", options.port, err)))?;
+    transcript.note(&format!("running {} on {}", suite, options.port))?;
+    for note in port.take_notes() {
+        transcript.write(LineDirection::System, &note)?;
+    }
+
+    let started = SystemTime::now();
+    let mut received = String::new();
+    let mut variables = HashMap::new();
+    let mut results = Vec::new();
+    let mut failed = false;
+    for (idx, step) in config.steps.iter().enumerate() {
+        let name = step
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("step {}", idx + 1));
+        if failed {
+            println!("- {} (skipped)", name);
+            results.push(StepResult {
+                name,
+                outcome: Outcome::Skipped,
+                time: Duration::ZERO,
+                attempts: 0,
+            });
+            continue;
+        }
+        let start = Instant::now();
+        let retries = step.retries.unwrap_or(config.retries);
+        let timeout = Duration::from_millis(
+            step.timeout_ms
+                .or(config.timeout_ms)
+                .unwrap_or(DEFAULT_TIMEOUT_MS),
+        );
+        let mut attempts = 0;
+        let outcome = (|| {
+            transcript.note(&name)?;
+            let outcome = loop {
+                attempts += 1;
+                let result = run_step(
+                    &mut *port,
+                    step,
+                    timeout,
+                    &mut received,
+                    &mut variables,
+                    &mut transcript,
+                )?;
+                match result {
+                    Err(StepError::TimedOut(err)) if attempts <= retries => {
+                        transcript.note(&format!("{}, retry {} of {}", err, attempts, retries))?;
+                    }
+                    Err(StepError::TimedOut(err) | StepError::Invalid(err)) => {
+                        break Outcome::Failed(err)
+                    }
+                    Ok(()) => break Outcome::Passed,
+                }
+            };
+            match &outcome {
+                Outcome::Passed => transcript.note(&format!("{} passed", name))?,
+                Outcome::Failed(err) => transcript.note(&format!("{} failed: {}", name, err))?,
+                Outcome::Error(_) | Outcome::Skipped => {}
+            }
+            Ok::<_, io::Error>(outcome)
+        })()
+        .unwrap_or_else(|err| {
+            // the transcript may be what failed
+            let _ = transcript.note(&format!("{} failed: {}", name, err));
+            Outcome::Error(err.to_string())
+        });
+        let time = start.elapsed();
+        match &outcome {
+            Outcome::Passed => println!("✓ {} ({:.2} s)", name, time.as_secs_f64()),
+            Outcome::Failed(err) | Outcome::Error(err) => {
+                failed = true;
+                println!("✗ {}: {}", name, err);
+            }
+            Outcome::Skipped => {}
+        }
+        results.push(StepResult {
+            name,
+            outcome,
+            time,
+            attempts,
+        });
+    }
+    let flushed = transcript.flush_received();
+
+    let junit = junit(&suite, &options.port, started, &results);
+    fs::write(&options.junit, junit).map_err(|err| {
+        io::Error::new(err.kind(), format!("{}: {}", options.junit.display(), err))
+    })?;
+    flushed.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("{}: {}", options.transcript.display(), err),
+        )
+    })?;
+    Ok(!failed)
+}
+
+enum StepError {
+    /// Worth another try.
+    TimedOut(String),
+    Invalid(String),
+}
+
+/// Sends what the step sends and waits for what it expects, once. The outer error is for the
+/// port or the transcript failing, which ends the whole run.
+fn run_step(
+    port: &mut dyn transport::Transport,
+    step: &StepConfig,
+    timeout: Duration,
+    received: &mut String,
+    variables: &mut HashMap<String, String>,
+    transcript: &mut Transcript,
+) -> io::Result<Result<(), StepError>> {
+    if step.delay_ms > 0 {
+        let until = Instant::now() + Duration::from_millis(step.delay_ms);
+        while Instant::now() < until {
+            receive(port, received, transcript)?;
+        }
+    }
+    if let Some(send) = &step.send {
+        let text = match substitute(send, variables, false) {
+            Ok(text) => text,
+            Err(err) => return Ok(Err(StepError::Invalid(err))),
+        };
+        port.write_all(text.as_bytes())?;
+        port.flush()?;
+        transcript.sent(&text)?;
+    }
+    let Some(expect) = &step.expect else {
+        return Ok(Ok(()));
+    };
+    let regex = match substitute(expect, variables, true)
+        .and_then(|pattern| Regex::new(&pattern).map_err(|err| err.to_string()))
+    {
+        Ok(regex) => regex,
+        Err(err) => return Ok(Err(StepError::Invalid(err))),
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(captures) = regex.captures(received) {
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    variables.insert(name.to_owned(), value.as_str().to_owned());
+                    transcript.note(&format!("{} = {}", name, value.as_str()))?;
+                }
+            }
+            let end = captures.get(0).map_or(0, |found| found.end());
+            received.drain(..end);
+            return Ok(Ok(()));
+        }
+        if Instant::now() >= deadline {
+            return Ok(Err(StepError::TimedOut(format!(
+                "expected {} within {} ms",
+                expect,
+                timeout.as_millis()
+            ))));
+        }
+        receive(port, received, transcript)?;
+    }
+}
+
+/// Reads what the port has, for up to `READ_TIMEOUT`.
+fn receive(
+    port: &mut dyn transport::Transport,
+    received: &mut String,
+    transcript: &mut Transcript,
+) -> io::Result<()> {
+    let mut buf = [0_u8; 1024];
+    let read = match port.read(&mut buf) {
+        Ok(read) => read,
+        Err(err) if err.kind() == ErrorKind::TimedOut => 0,
+        Err(err) => return Err(err),
+    };
+    for note in port.take_notes() {
+        transcript.write(LineDirection::System, &note)?;
+    }
+    if read == 0 {
+        return Ok(());
+    }
+    let text = String::from_utf8_lossy(&buf[..read]);
+    transcript.received(&text)?;
+    received.push_str(&text);
+    if received.len() > EXPECT_WINDOW {
+        let mut cut = received.len() - EXPECT_WINDOW;
+        while !received.is_char_boundary(cut) {
+            cut += 1;
+        }
+        received.drain(..cut);
+    }
+    Ok(())
+}
+
+/// Replaces `${name}` with captured variables, escaped when `text` is a regex.
+fn substitute(
+    text: &str,
+    variables: &HashMap<String, String>,
+    escape: bool,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+        let value = variables
+            .get(name)
+            .ok_or_else(|| format!("nothing was captured into {}", name))?;
+        out.push_str(&rest[..start]);
+        if escape {
+            out.push_str(&regex::escape(value));
+        } else {
+            out.push_str(value);
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn junit(suite: &str, port: &str, started: SystemTime, results: &[StepResult]) -> String {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|step| f(&step.outcome)).count();
+    let failures = count(|outcome| matches!(outcome, Outcome::Failed(_)));
+    let errors = count(|outcome| matches!(outcome, Outcome::Error(_)));
+    let skipped = count(|outcome| matches!(outcome, Outcome::Skipped));
+    let total = results
+        .iter()
+        .map(|step| step.time)
+        .sum::<Duration>()
+        .as_secs_f64();
+    let (date, time) = format_time(started);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        failures,
+        errors,
+        skipped,
+        total
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" \
+         time=\"{:.3}\" timestamp=\"{}T{}\" hostname=\"{}\">",
+        escape(suite),
+        results.len(),
+        failures,
+        errors,
+        skipped,
+        total,
+        date,
+        &time[..8],
+        escape(port)
+    );
+    for step in results {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&step.name),
+            escape(suite),
+            step.time.as_secs_f64()
+        );
+        match &step.outcome {
+            Outcome::Passed if step.attempts <= 1 => xml.push_str("/>\n"),
+            Outcome::Passed => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <system-out>passed after {} attempts</system-out>\n    </testcase>",
+                    step.attempts
+                );
+            }
+            Outcome::Failed(err) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <failure message=\"{0}\">{0} ({1} attempt{2})</failure>\n    </testcase>",
+                    escape(err),
+                    step.attempts,
+                    if step.attempts == 1 { "" } else { "s" }
+                );
+            }
+            Outcome::Error(err) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <error message=\"{0}\">{0}</error>\n    </testcase>",
+                    escape(err)
+                );
+            }
+            Outcome::Skipped => {
+                xml.push_str(
+                    ">\n      <skipped message=\"an earlier step failed\"/>\n    </testcase>\n",
+                );
+            }
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // control characters aren't allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\t') => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::mock::harness::{PtyDevice, TempDir};
+
+    const RULES: &str = r#"
+[[rule]]
+on = '^AT$'
+reply = "OK\r\n"
+
+[[rule]]
+on = '^AT\+CGSN$'
+reply = "490154203237518\r\nOK\r\n"
+delay_ms = 50
+
+[[rule]]
+on = '^AT\+LOOKUP=(\d+)$'
+reply = "FOUND $1\r\n"
+"#;
+
+    fn options(scratch: &TempDir, script: &str, port: &Path) -> Options {
+        Options {
+            script: scratch.write("script.toml", script),
+            port: port.display().to_string(),
+            baud: None,
+            junit: scratch.path().join("junit.xml"),
+            transcript: scratch.path().join("transcript.log"),
+        }
+    }
+
+    #[test]
+    fn passes_against_a_simulated_modem() {
+        let scratch = TempDir::new("runner-pass");
+        let device = PtyDevice::simulate(&scratch.write("modem.toml", RULES)).unwrap();
+        let options = options(
+            &scratch,
+            r#"
+name = "modem"
+timeout_ms = 2000
+
+[[step]]
+name = "attention"
+send = "AT\r\n"
+expect = 'OK'
+
+[[step]]
+name = "serial number"
+send = "AT+CGSN\r\n"
+expect = '(?P<imei>\d{15})\r\nOK'
+
+[[step]]
+send = "AT+LOOKUP=${imei}\r\n"
+expect = 'FOUND ${imei}'
+"#,
+            device.path(),
+        );
+        assert!(run(&options, &PortSettings::default()).unwrap());
+        let junit = fs::read_to_string(&options.junit).unwrap();
+        assert!(junit.contains("tests=\"3\" failures=\"0\""));
+        assert!(junit.contains("<testcase name=\"step 3\""));
+        let transcript = fs::read_to_string(&options.transcript).unwrap();
+        assert!(transcript.contains("TX AT+LOOKUP=490154203237518"));
+        assert!(transcript.contains("RX FOUND 490154203237518"));
+        assert!(transcript.contains("imei = 490154203237518"));
+    }
+
+    #[test]
+    fn fails_a_step_that_times_out_and_skips_the_rest() {
+        let scratch = TempDir::new("runner-fail");
+        let device = PtyDevice::simulate(&scratch.write("modem.toml", RULES)).unwrap();
+        let options = options(
+            &scratch,
+            r#"
+[[step]]
+name = "attention"
+send = "AT\r\n"
+expect = 'OK'
+
+[[step]]
+name = "unknown command"
+send = "AT+NOPE\r\n"
+expect = 'OK'
+timeout_ms = 200
+retries = 1
+
+[[step]]
+name = "never run"
+send = "AT\r\n"
+"#,
+            device.path(),
+        );
+        assert!(!run(&options, &PortSettings::default()).unwrap());
+        let junit = fs::read_to_string(&options.junit).unwrap();
+        assert!(junit.contains("tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\""));
+        assert!(junit.contains("expected OK within 200 ms (2 attempts)"));
+        assert!(junit.contains("<skipped message=\"an earlier step failed\"/>"));
+    }
+
+    #[test]
+    fn records_a_port_that_goes_away_and_still_writes_the_junit_file() {
+        let scratch = TempDir::new("runner-gone");
+        let device = PtyDevice::simulate(&scratch.write("modem.toml", RULES)).unwrap();
+        let options = options(
+            &scratch,
+            r#"
+[[step]]
+name = "attention"
+send = "AT\r\n"
+expect = 'OK'
+
+[[step]]
+name = "unplugged"
+delay_ms = 2000
+
+[[step]]
+name = "never run"
+send = "AT\r\n"
+"#,
+            device.path(),
+        );
+        let unplug = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(device);
+        });
+        assert!(!run(&options, &PortSettings::default()).unwrap());
+        unplug.join().unwrap();
+        let junit = fs::read_to_string(&options.junit).unwrap();
+        assert!(junit.contains("tests=\"3\" failures=\"0\" errors=\"1\" skipped=\"1\""));
+        assert!(junit.contains("<testcase name=\"unplugged\""));
+        assert!(junit.contains("<error message="));
+        let transcript = fs::read_to_string(&options.transcript).unwrap();
+        assert!(transcript.contains("unplugged failed:"));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use glob::Pattern;
+use regex::Regex;
+use rhai::{Dynamic, Engine, EvalAltResult};
+
+use crate::serial::{identity, CmdType, PortCommand};
+
+/// Where scripts are looked for, relative to the working directory.
+const SCRIPT_DIRS: [&str; 2] = [".", "scripts"];
+const EXTENSION: &str = "rhai";
+
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much received text `expect` looks back on.
+const EXPECT_WINDOW: usize = 64 * 1024;
+/// How often waiting scripts check whether they were stopped.
+const CANCEL_CHECK: Duration = Duration::from_millis(50);
+
+/// A script found in one of the script directories. Comments at the top of the file can bind it
+/// to a function key and have it run whenever a matching port is opened:
+///
+/// ```text
+/// //! key: F5
+/// //! on-open: /dev/ttyUSB*
+/// ```
+#[derive(Clone)]
+pub struct ScriptInfo {
+    pub path: PathBuf,
+    pub name: String,
+    pub key: Option<u8>,
+    pub on_open: Option<Pattern>,
+}
+
+impl ScriptInfo {
+    fn load(path: &Path) -> Option<ScriptInfo> {
+        let source = fs::read_to_string(path).ok()?;
+        let mut info = ScriptInfo {
+            path: path.to_owned(),
+            name: path.file_stem()?.to_string_lossy().into_owned(),
+            key: None,
+            on_open: None,
+        };
+        for header in source.lines().map_while(|line| line.strip_prefix("//!")) {
+            match header.split_once(':') {
+                Some((name, value)) if name.trim() == "key" => {
+                    let value = value.trim();
+                    info.key = value
+                        .strip_prefix(['F', 'f'])
+                        .and_then(|number| number.parse().ok());
+                }
+                Some((name, value)) if name.trim() == "on-open" => {
+                    info.on_open = Pattern::new(value.trim()).ok();
+                }
+                _ => {}
+            }
+        }
+        Some(info)
+    }
+
+    /// Whether the script runs when `port_name` is opened.
+    pub fn runs_on_open(&self, port_name: &str, kernel_name: Option<&str>) -> bool {
+        self.on_open
+            .as_ref()
+            .is_some_and(|pattern| identity::glob_matches(pattern, port_name, kernel_name))
+    }
+}
+
+/// The scripts in the script directories, by name.
+pub fn discover() -> Vec<ScriptInfo> {
+    let mut scripts = SCRIPT_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .filter_map(|path| ScriptInfo::load(&path))
+        .collect::<Vec<_>>();
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
+    scripts
+}
+
+pub enum ScriptEvent {
+    /// `log()` or `print()` from the script running on a port.
+    Log(String, String),
+    Notify(String),
+    /// A script ended, with the error that stopped it if it failed.
+    Finished(u64, Result<(), String>),
+}
+
+/// A script running on a port, in a thread of its own.
+pub struct ScriptHandle {
+    pub id: u64,
+    pub name: String,
+    pub port: String,
+    cancel: Arc<AtomicBool>,
+}
+
+impl ScriptHandle {
+    pub fn stop(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What a script has received and not yet matched with `expect`.
+struct Received {
+    data: Receiver<Vec<u8>>,
+    text: String,
+}
+
+fn stopped() -> Box<EvalAltResult> {
+    "stopped".into()
+}
+
+/// Runs `script` on `port`. It talks to the port through the same commands as the UI and sees
+/// what the port receives through a watcher the serial thread feeds.
+pub fn run(
+    id: u64,
+    script: &ScriptInfo,
+    port: String,
+    port_tx: Sender<PortCommand>,
+    stop_flag: Arc<AtomicBool>,
+    events: Sender<ScriptEvent>,
+) -> ScriptHandle {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let handle = ScriptHandle {
+        id,
+        name: script.name.clone(),
+        port: port.clone(),
+        cancel: cancel.clone(),
+    };
+    let path = script.path.clone();
+    thread::spawn(move || {
+        let (watch_tx, watch_rx) = mpsc::channel();
+        let _ = port_tx.send(PortCommand::Watch(port.clone(), watch_tx));
+        let received = Rc::new(RefCell::new(Received {
+            data: watch_rx,
+            text: String::new(),
+        }));
+        let engine = engine(&port, &port_tx, &stop_flag, &events, &cancel, &received);
+        let result = engine
+            .run_file(path)
+            .map_err(|err| err.to_string().replace('\n', " "));
+        let _ = events.send(ScriptEvent::Finished(id, result));
+    });
+    handle
+}
+
+fn engine(
+    port: &str,
+    port_tx: &Sender<PortCommand>,
+    stop_flag: &Arc<AtomicBool>,
+    events: &Sender<ScriptEvent>,
+    cancel: &Arc<AtomicBool>,
+    received: &Rc<RefCell<Received>>,
+) -> Engine {
+    let mut engine = Engine::new();
+    // commands are sent for this port, whichever one is shown
+    let command = {
+        let (port, port_tx, stop_flag) = (port.to_owned(), port_tx.clone(), stop_flag.clone());
+        move |command: PortCommand| {
+            stop_flag.store(true, Ordering::Relaxed);
+            let _ = port_tx.send(PortCommand::On(port.clone(), Box::new(command)));
+        }
+    };
+
+    let send = command.clone();
+    engine.register_fn("send", move |text: &str| {
+        send(PortCommand::Write(CmdType::Raw(text.to_owned())));
+    });
+    let send = command.clone();
+    engine.register_fn("set_dtr", move |level: bool| send(PortCommand::SetDtr(level)));
+    let send = command;
+    engine.register_fn("set_rts", move |level: bool| send(PortCommand::SetRts(level)));
+
+    let sleep_cancel = cancel.clone();
+    engine.register_fn(
+        "sleep",
+        move |millis: i64| -> Result<(), Box<EvalAltResult>> {
+            let until = Instant::now() + Duration::from_millis(millis.max(0) as u64);
+            while Instant::now() < until {
+                if sleep_cancel.load(Ordering::Relaxed) {
+                    return Err(stopped());
+                }
+                thread::sleep(CANCEL_CHECK.min(until.saturating_duration_since(Instant::now())));
+            }
+            Ok(())
+        },
+    );
+
+    let expect = {
+        let (received, cancel) = (received.clone(), cancel.clone());
+        move |pattern: &str, timeout: Duration| -> Result<String, Box<EvalAltResult>> {
+            let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+            let deadline = Instant::now() + timeout;
+            let mut received = received.borrow_mut();
+            loop {
+                if let Some(found) = regex.find(&received.text) {
+                    let matched = found.as_str().to_owned();
+                    let end = found.end();
+                    received.text.drain(..end);
+                    return Ok(matched);
+                }
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(stopped());
+                }
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(format!(
+                        "expect({}) timed out after {} ms",
+                        pattern,
+                        timeout.as_millis()
+                    )
+                    .into());
+                }
+                match received.data.recv_timeout(left.min(CANCEL_CHECK)) {
+                    Ok(bytes) => {
+                        received.text.push_str(&String::from_utf8_lossy(&bytes));
+                        if received.text.len() > EXPECT_WINDOW {
+                            let mut cut = received.text.len() - EXPECT_WINDOW;
+                            while !received.text.is_char_boundary(cut) {
+                                cut += 1;
+                            }
+                            received.text.drain(..cut);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err("the port was closed".into())
+                    }
+                }
+            }
+        }
+    };
+    let expect_default = expect.clone();
+    engine.register_fn("expect", move |pattern: &str| {
+        expect_default(pattern, EXPECT_TIMEOUT)
+    });
+    engine.register_fn("expect", move |pattern: &str, millis: i64| {
+        expect(pattern, Duration::from_millis(millis.max(0) as u64))
+    });
+
+    let (log_port, log_events) = (port.to_owned(), events.clone());
+    engine.register_fn("log", move |text: &str| {
+        let _ = log_events.send(ScriptEvent::Log(log_port.clone(), text.to_owned()));
+    });
+    let (print_port, print_events) = (port.to_owned(), events.clone());
+    engine.on_print(move |text| {
+        let _ = print_events.send(ScriptEvent::Log(print_port.clone(), text.to_owned()));
+    });
+    let notify_events = events.clone();
+    engine.register_fn("notify", move |text: &str| {
+        let _ = notify_events.send(ScriptEvent::Notify(text.to_owned()));
+    });
+
+    let progress_cancel = cancel.clone();
+    engine.on_progress(move |_| {
+        progress_cancel
+            .load(Ordering::Relaxed)
+            .then_some(Dynamic::UNIT)
+    });
+    engine
+}
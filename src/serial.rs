@@ -1,18 +1,66 @@
 use std::{
     collections::HashMap,
     io::ErrorKind,
+    net::SocketAddr,
+    path::PathBuf,
     sync::{
-        mpsc::{Receiver, Sender},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
         atomic::{AtomicBool, Ordering},
         Arc,
     },
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use serialport::SerialPort;
+use serde::{Deserialize, Serialize};
 
-use self::utils::parse_flow;
+use crate::transfer::{
+    self, zmodem, Protocol, TransferDirection, TransferProgress, TransferRequest,
+};
+
+use self::{
+    fault::{FaultConfig, FaultHandle},
+    repeat::{RepeatCommand, ScheduleInfo, Schedules},
+    settings::{Encoding, PortSettings},
+    share::{Share, ShareInfo},
+    tee::Tee,
+    transport::Transport,
+    tx::{Pacing, TxOutcome, TxProgress, TxQueue, TxResult},
+    utils::{parse_flow, LineErrors},
+};
+
+pub mod bridge;
+pub mod details;
+pub mod fault;
+pub mod identity;
+pub mod mock;
+pub mod repeat;
+pub mod rfc2217;
+pub mod settings;
+pub mod share;
+pub mod sim;
+pub mod sniff;
+pub mod tee;
+pub mod transport;
+pub mod tx;
+
+/// How often the kernel error counters of the open ports are sampled.
+const LINE_ERRORS_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the start of a line waits for its end before it is reported anyway, so prompts that
+/// never end their line, like `login: `, can fire triggers.
+const PARTIAL_LINE_IDLE: Duration = Duration::from_millis(200);
+
+/// Where a line shown in the scrollback came from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineDirection {
+    /// Received from the device.
+    Rx,
+    /// Written to the device, the local echo.
+    Tx,
+    /// Notes of our own: markers, transfer results and the like.
+    System,
+}
 
 pub enum CmdType {
     Dtr(bool),
@@ -23,38 +71,233 @@ pub enum CmdType {
 pub enum PortCommand {
     Write(CmdType),
     ChangePort(String),
+    /// Make the given port the current one without opening it. What is meant for the current
+    /// port is dropped while it isn't open, rather than going to another one.
+    Show(String),
     PausePort(String),
+    /// Hold the TX line of the current port in the BREAK state for the given duration.
+    Break(Duration),
+    /// Run a file transfer on the current port. Reading the port is suspended until it ends.
+    Transfer(TransferRequest),
+    /// Queue data for the current port, written out at the given pace.
+    SendPaced(Vec<u8>, Pacing),
+    /// Drop whatever is still queued for the current port.
+    CancelTx,
+    /// Expose the current port on a TCP listener at the given address, optionally read-only.
+    Share(String, bool),
+    StopShare,
+    /// Inject faults into the traffic of the current port, or stop.
+    Faults(Option<FaultConfig>),
+    /// Pipe what the current port receives into a command, writing its stdout back to the port
+    /// if asked to, or stop. The command is restarted whenever the port is opened again.
+    Tee(Option<(String, bool)>),
+    /// Set the DTR or RTS line of the current port, unlike `CmdType::Dtr` and `CmdType::Rts`
+    /// which run reset sequences.
+    SetDtr(bool),
+    SetRts(bool),
+    /// Run a control line sequence like `r1:d0:s1000:d1:r0` on the current port.
+    Control(String),
+    /// Add, pause or remove data sent periodically, see `repeat::Schedules`.
+    Repeat(RepeatCommand),
+    /// What to open the given port with the next time it is opened, from its profile.
+    Settings(String, PortSettings),
+    /// Where ZMODEM downloads the device on the given port starts by itself go.
+    DownloadDir(String, PathBuf),
+    /// The ports text written to one of them goes to. They keep being read while another port
+    /// is shown, so their answers can be compared.
+    Broadcast(Vec<String>),
+    /// Send everything the port receives to the given channel too, until it is dropped.
+    Watch(String, Sender<Vec<u8>>),
+    /// Run a command on the given port rather than the current one.
+    On(String, Box<PortCommand>),
+}
+
+pub enum PortEvent {
+    Opened(String),
+    /// Line errors counted on a port since it was opened.
+    LineErrors(String, LineErrors),
+    /// A transfer the UI did not ask for (a ZMODEM download started by the device) began, saving
+    /// into the given directory.
+    TransferStarted(String, Protocol, PathBuf, Arc<AtomicBool>),
+    Transfer(String, TransferProgress),
+    TxProgress(String, TxProgress),
+    /// A queued write was fully sent, failed or was cancelled.
+    TxFinished(String, u64, TxResult),
+    /// The port's TCP share or its clients changed, `None` once it is no longer shared.
+    Shared(String, Option<ShareInfo>),
+    /// The periodic sends of all ports, whenever they change.
+    Schedules(Vec<ScheduleInfo>),
+    /// A line that has been waiting `PARTIAL_LINE_IDLE` for its end, only for the triggers. It
+    /// comes again as a line once it ends.
+    PartialLine(String, String),
+}
+
+pub enum ReadEvent {
+    Line(String),
+    /// The device started a ZMODEM download (`sz` on the other end).
+    ZmodemRequest,
+}
+
+fn report_tx(
+    port_name: &str,
+    outcome: TxOutcome,
+    ui_tx: &Sender<(String, LineDirection, String)>,
+    result_tx: &Sender<PortEvent>,
+) {
+    for line in outcome.echo {
+        let _ = ui_tx.send((port_name.to_owned(), LineDirection::Tx, line));
+    }
+    for (id, result) in outcome.finished {
+        let _ = result_tx.send(PortEvent::TxFinished(port_name.to_owned(), id, result));
+    }
+    if let Some(progress) = outcome.progress {
+        let _ = result_tx.send(PortEvent::TxProgress(port_name.to_owned(), progress));
+    }
+}
+
+/// Reports the unfinished line of `port_name` once nothing arrived for it in `PARTIAL_LINE_IDLE`,
+/// and again whenever it grows and goes quiet once more. `partial_lines` has when the unfinished
+/// lines last grew, `None` once reported.
+fn report_partial_line(
+    port_name: &str,
+    pending_buffer: &[u8],
+    received: &[u8],
+    encoding: Encoding,
+    partial_lines: &mut HashMap<String, Option<Instant>>,
+    result_tx: &Sender<PortEvent>,
+) {
+    // lines still waiting in the buffer are read first
+    if pending_buffer.is_empty() || pending_buffer.contains(&b'\n') {
+        partial_lines.remove(port_name);
+        return;
+    }
+    match partial_lines.get(port_name) {
+        Some(_) if !received.is_empty() => {}
+        Some(Some(grew)) if grew.elapsed() >= PARTIAL_LINE_IDLE => {
+            let line = encoding.decode(pending_buffer).trim_end_matches('\r').to_owned();
+            let _ = result_tx.send(PortEvent::PartialLine(port_name.to_owned(), line));
+            partial_lines.insert(port_name.to_owned(), None);
+            return;
+        }
+        Some(_) => return,
+        None => {}
+    }
+    partial_lines.insert(port_name.to_owned(), Some(Instant::now()));
+}
+
+fn report_share_clients(
+    port_name: &str,
+    share: &Share,
+    connected: &[SocketAddr],
+    disconnected: &[SocketAddr],
+    ui_tx: &Sender<(String, LineDirection, String)>,
+    result_tx: &Sender<PortEvent>,
+) {
+    if connected.is_empty() && disconnected.is_empty() {
+        return;
+    }
+    for (addr, what) in connected
+        .iter()
+        .map(|addr| (addr, "connected"))
+        .chain(disconnected.iter().map(|addr| (addr, "disconnected")))
+    {
+        let _ = ui_tx.send((
+            port_name.to_owned(),
+            LineDirection::System,
+            format!("── share client {} {} ──", addr, what),
+        ));
+    }
+    let _ = result_tx.send(PortEvent::Shared(port_name.to_owned(), Some(share.info())));
+}
+
+/// Hands what `port_name` received to whoever watches it, forgetting watchers that are gone.
+fn watch(watchers: &mut Vec<(String, Sender<Vec<u8>>)>, port_name: &str, received: &[u8]) {
+    if received.is_empty() {
+        return;
+    }
+    watchers.retain(|(watched, watcher)| {
+        watched != port_name || watcher.send(received.to_vec()).is_ok()
+    });
+}
+
+fn start_tee(
+    port_name: &str,
+    command: &str,
+    feed_back: bool,
+    tees: &mut HashMap<String, Tee>,
+    ui_tx: &Sender<(String, LineDirection, String)>,
+) {
+    let note = match Tee::spawn(command, feed_back) {
+        Ok(tee) => {
+            tees.insert(port_name.to_owned(), tee);
+            format!(
+                "── piping received data into {}{} ──",
+                command,
+                if feed_back { ", its output goes to the port" } else { "" }
+            )
+        }
+        Err(err) => format!("── could not run {}: {} ──", command, err),
+    };
+    let _ = ui_tx.send((port_name.to_owned(), LineDirection::System, note));
+}
+
+fn run_transfer(
+    port: &mut Box<dyn Transport>,
+    port_name: &str,
+    request: &TransferRequest,
+    result_tx: &Sender<PortEvent>,
+) {
+    transfer::run(port, request, &mut |progress| {
+        let _ = result_tx.send(PortEvent::Transfer(port_name.to_owned(), progress.clone()));
+    });
 }
 
+/// Assembles the next line received on `port`. Returns `None` once the port has nothing more to
+/// read for now; everything read on the way is also appended to `received`.
 pub fn read_line(
-    port: &mut Box<dyn SerialPort>,
+    port: &mut Box<dyn Transport>,
     pending_buffer: &mut Vec<u8>,
+    received: &mut Vec<u8>,
     stop_flag: &AtomicBool,
-) -> Option<String> {
+    encoding: Encoding,
+) -> Option<ReadEvent> {
     let mut serial_buf = [0_u8; 256];
     loop {
-        if let Some(newline_idx) = pending_buffer.iter().position(|&byte| byte == b'\n') {
+        // the ZMODEM handshake is caught before it gets mixed into the lines
+        let zmodem_idx = zmodem::find_init(pending_buffer);
+        if let Some(newline_idx) = pending_buffer
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .filter(|&idx| zmodem_idx.is_none_or(|zmodem_idx| idx < zmodem_idx))
+        {
             let mut line = pending_buffer.drain(..=newline_idx).collect::<Vec<_>>();
             while matches!(line.last(), Some(b'\n' | b'\r')) {
                 line.pop();
             }
 
-            return Some(String::from_utf8_lossy(&line).into_owned());
+            return Some(ReadEvent::Line(encoding.decode(&line)));
+        }
+        if zmodem_idx.is_some() {
+            pending_buffer.clear();
+            return Some(ReadEvent::ZmodemRequest);
         }
 
         match port.read(&mut serial_buf) {
             Ok(bytes_read) if bytes_read > 0 => {
                 pending_buffer.extend_from_slice(&serial_buf[..bytes_read]);
-            }
-            Ok(_) => {
+                received.extend_from_slice(&serial_buf[..bytes_read]);
                 if stop_flag.swap(false, Ordering::Relaxed) {
                     return None;
                 }
             }
+            Ok(_) => {
+                // nothing more for now, let the serial thread get to its other work
+                stop_flag.store(false, Ordering::Relaxed);
+                return None;
+            }
             Err(err) if err.kind() == ErrorKind::TimedOut => {
-                if stop_flag.swap(false, Ordering::Relaxed) {
-                    return None;
-                }
+                stop_flag.store(false, Ordering::Relaxed);
+                return None;
             }
             Err(_) => {
                 return None;
@@ -64,87 +307,514 @@ pub fn read_line(
 }
 
 pub fn serial_thread(
-    ui_tx: Sender<(String, String)>,
+    ui_tx: Sender<(String, LineDirection, String)>,
     port_rx: Receiver<PortCommand>,
-    result_tx: Sender<(String, bool)>,
+    result_tx: Sender<PortEvent>,
     stop_flag: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
     let mut serial_bookkeeping = HashMap::new();
     let mut read_buffers: HashMap<String, Vec<u8>> = HashMap::new();
+    // the kernel counters of every open port sampled when it was opened, and the last ones sent
+    let mut line_errors: HashMap<String, (LineErrors, LineErrors)> = HashMap::new();
+    let mut tx_queues: HashMap<String, TxQueue> = HashMap::new();
+    let mut shares: HashMap<String, Share> = HashMap::new();
+    // ports that got a fault injector put in front of them, it stays until they are closed
+    let mut faults: HashMap<String, FaultHandle> = HashMap::new();
+    // commands attached to ports, kept while a port is paused so they come back with it
+    let mut tee_commands: HashMap<String, (String, bool)> = HashMap::new();
+    let mut tees: HashMap<String, Tee> = HashMap::new();
+    let mut watchers: Vec<(String, Sender<Vec<u8>>)> = Vec::new();
+    // timed here rather than by the UI so they stay on time while it is busy
+    let mut schedules = Schedules::default();
+    let mut broadcast: Vec<String> = Vec::new();
+    let mut settings: HashMap<String, PortSettings> = HashMap::new();
+    let mut download_dirs: HashMap<String, PathBuf> = HashMap::new();
+    let mut partial_lines: HashMap<String, Option<Instant>> = HashMap::new();
+    // ports holding a BREAK, and when to let go of it, so the other ports keep running meanwhile
+    let mut breaks: HashMap<String, Instant> = HashMap::new();
     std::thread::spawn(move || {
-        let mut port: Option<Box<dyn SerialPort>> = None;
         let mut port_name = String::new();
-        match port_rx.recv().expect("Failed to read the port") {
-            PortCommand::ChangePort(req_port_name) => {
-                port = Some(
-                    serialport::new(&req_port_name, 115_200)
-                        .timeout(Duration::from_millis(100))
-                        .open()
-                        .expect("Failed to open port"),
-                );
-                port_name = req_port_name;
-            }
-            _ => {}
-        };
-        if port.is_some() {
-            serial_bookkeeping.insert(port_name.clone(), port.unwrap());
-            read_buffers.insert(port_name.clone(), Vec::new());
-        }
+        let mut last_line_errors_check = Instant::now();
         loop {
-            if let Ok(cmd) = port_rx.recv_timeout(Duration::from_millis(5)) {
+            // don't sit on the command channel while paced data is waiting to go out
+            let command_wait = if tx_queues.values().all(TxQueue::is_idle) {
+                schedules.next_due().map_or(Duration::from_millis(5), |due| {
+                    due.saturating_duration_since(Instant::now())
+                        .min(Duration::from_millis(5))
+                })
+            } else {
+                Duration::ZERO
+            };
+            let cmd = match port_rx.recv_timeout(command_wait) {
+                Ok(cmd) => Some(cmd),
+                // the UI is gone, and nobody is left to tell anything
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => None,
+            };
+            if let Some(cmd) = cmd {
+                // a command for another port runs as if that port were the current one
+                let (cmd, current) = match cmd {
+                    PortCommand::On(name, cmd) => {
+                        (*cmd, Some(std::mem::replace(&mut port_name, name)))
+                    }
+                    cmd => (cmd, None),
+                };
                 match cmd {
                     PortCommand::ChangePort(req_name) => {
-                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&req_name.clone()) {
+                        if serial_bookkeeping.contains_key(&req_name) {
                             port_name = req_name.clone();
                         } else {
-                            match serialport::new(&req_name, 115_200)
-                                .timeout(Duration::from_millis(5))
-                                .open()
-                            {
+                            let port_settings = settings.get(&req_name).cloned().unwrap_or_default();
+                            match transport::open_with(
+                                &req_name,
+                                &port_settings,
+                                Duration::from_millis(5),
+                            ) {
                                 Ok(p) => {
                                     port_name = req_name.clone();
+                                    let baseline = p.line_errors().unwrap_or_default();
                                     serial_bookkeeping.insert(port_name.clone(), p);
                                     read_buffers.entry(port_name.clone()).or_default();
+                                    line_errors.insert(
+                                        port_name.clone(),
+                                        (baseline, LineErrors::default()),
+                                    );
 
-                                    result_tx.send((req_name.clone(), true));
+                                    let _ = result_tx.send(PortEvent::Opened(req_name.clone()));
+                                    if let Some((command, feed_back)) = tee_commands.get(&req_name)
+                                    {
+                                        start_tee(
+                                            &req_name, command, *feed_back, &mut tees, &ui_tx,
+                                        );
+                                    }
                                 }
-                                Err(_e) => {
-                                    panic!("{}", _e);
+                                Err(err) => {
+                                    let _ = ui_tx.send((
+                                        req_name.clone(),
+                                        LineDirection::System,
+                                        format!("── could not open {}: {} ──", req_name, err),
+                                    ));
                                 }
                             }
                         }
                     }
+                    PortCommand::Show(req_name) => port_name = req_name,
                     PortCommand::PausePort(req_name) => {
+                        if breaks.remove(&req_name).is_some() {
+                            if let Some(tmp_port) = serial_bookkeeping.get_mut(&req_name) {
+                                let _ = tmp_port.clear_break();
+                            }
+                        }
                         serial_bookkeeping.remove(&req_name);
                         read_buffers.remove(&req_name);
+                        line_errors.remove(&req_name);
+                        tx_queues.remove(&req_name);
+                        faults.remove(&req_name);
+                        tees.remove(&req_name);
+                        watchers.retain(|(watched, _)| *watched != req_name);
+                        if shares.remove(&req_name).is_some() {
+                            let _ = result_tx.send(PortEvent::Shared(req_name.clone(), None));
+                        }
+                    }
+                    PortCommand::Break(duration) => {
+                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
+                            if !breaks.contains_key(&port_name) && tmp_port.set_break().is_ok() {
+                                breaks.insert(port_name.clone(), Instant::now() + duration);
+                            }
+                        }
+                    }
+                    PortCommand::Transfer(request) => {
+                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
+                            run_transfer(tmp_port, &port_name, &request, &result_tx);
+                            // whatever was half-assembled before the transfer is stale now
+                            read_buffers.entry(port_name.clone()).or_default().clear();
+                        }
+                    }
+                    PortCommand::SendPaced(data, pacing) => {
+                        if serial_bookkeeping.contains_key(&port_name) {
+                            tx_queues
+                                .entry(port_name.clone())
+                                .or_default()
+                                .push(data, pacing);
+                        }
+                    }
+                    PortCommand::CancelTx => {
+                        if let Some(queue) = tx_queues.get_mut(&port_name) {
+                            report_tx(&port_name, queue.cancel(), &ui_tx, &result_tx);
+                        }
+                    }
+                    PortCommand::Share(addr, read_only) => {
+                        if serial_bookkeeping.contains_key(&port_name) {
+                            shares.remove(&port_name);
+                            let note = match Share::bind(&addr, read_only) {
+                                Ok(share) => {
+                                    let info = share.info();
+                                    shares.insert(port_name.clone(), share);
+                                    let note = format!(
+                                        "── shared on {}{} ──",
+                                        info.addr,
+                                        if read_only { " (read-only)" } else { "" }
+                                    );
+                                    let _ = result_tx
+                                        .send(PortEvent::Shared(port_name.clone(), Some(info)));
+                                    note
+                                }
+                                Err(err) => {
+                                    let _ = result_tx.send(PortEvent::Shared(port_name.clone(), None));
+                                    format!("── sharing on {} failed: {} ──", addr, err)
+                                }
+                            };
+                            let _ = ui_tx.send((port_name.clone(), LineDirection::System, note));
+                        }
+                    }
+                    PortCommand::StopShare => {
+                        if shares.remove(&port_name).is_some() {
+                            let _ = ui_tx.send((
+                                port_name.clone(),
+                                LineDirection::System,
+                                "── no longer shared ──".to_owned(),
+                            ));
+                            let _ = result_tx.send(PortEvent::Shared(port_name.clone(), None));
+                        }
+                    }
+                    PortCommand::Faults(config) => {
+                        if !faults.contains_key(&port_name) {
+                            if let Some(port) = serial_bookkeeping.remove(&port_name) {
+                                let (port, handle) = fault::wrap(port);
+                                serial_bookkeeping.insert(port_name.clone(), port);
+                                faults.insert(port_name.clone(), handle);
+                            }
+                        }
+                        if let Some(handle) = faults.get(&port_name) {
+                            handle.lock().unwrap().configure(config);
+                        }
+                    }
+                    PortCommand::Tee(tee) => {
+                        if serial_bookkeeping.contains_key(&port_name) {
+                            tees.remove(&port_name);
+                            match tee {
+                                Some((command, feed_back)) => {
+                                    start_tee(&port_name, &command, feed_back, &mut tees, &ui_tx);
+                                    tee_commands.insert(port_name.clone(), (command, feed_back));
+                                }
+                                None => {
+                                    if tee_commands.remove(&port_name).is_some() {
+                                        let _ = ui_tx.send((
+                                            port_name.clone(),
+                                            LineDirection::System,
+                                            "── no longer piping into a command ──".to_owned(),
+                                        ));
+                                    }
+                                }
+                            }
+                        }
                     }
                     PortCommand::Write(cmd) => match cmd {
+                        // what is typed on a port of the broadcast group goes to all of them,
+                        // while scripts and triggers naming a port only write to that one
+                        CmdType::Raw(data)
+                            if current.is_none() && broadcast.contains(&port_name) =>
+                        {
+                            for name in broadcast.iter() {
+                                if serial_bookkeeping.contains_key(name) {
+                                    let encoding = settings.get(name).map(|s| s.encoding);
+                                    tx_queues.entry(name.clone()).or_default().push(
+                                        encoding.unwrap_or_default().encode(&data),
+                                        Pacing::default(),
+                                    );
+                                }
+                            }
+                        }
                         CmdType::Raw(data) => {
-                            if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name.clone()) {
-                                ui_tx.send((port_name.clone(), data.clone()));
-                                tmp_port.write(data.as_bytes());
+                            if serial_bookkeeping.contains_key(&port_name) {
+                                let encoding = settings.get(&port_name).map(|s| s.encoding);
+                                tx_queues.entry(port_name.clone()).or_default().push(
+                                    encoding.unwrap_or_default().encode(&data),
+                                    Pacing::default(),
+                                );
                             }
                         }
                         CmdType::Dtr(level) => {
                             if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name.clone()) {
-                                parse_flow(tmp_port, "r1:d0:s1000:d1:r0".to_owned());
+                                let _ = parse_flow(tmp_port, "r1:d0:s1000:d1:r0".to_owned());
                             }
                         }
                         CmdType::Rts(level) => {
                             if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name.clone()) {
-                                parse_flow(
+                                let _ = parse_flow(
                                     tmp_port,
                                     "r0:d0:s100:d1:r0:s100:r1:d0:r1:s100:r0:d0".to_owned(),
                                 );
                             }
                         }
                     },
+                    PortCommand::SetDtr(level) => {
+                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
+                            let _ = tmp_port.write_data_terminal_ready(level);
+                        }
+                    }
+                    PortCommand::SetRts(level) => {
+                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
+                            let _ = tmp_port.write_request_to_send(level);
+                        }
+                    }
+                    PortCommand::Control(sequence) => {
+                        if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
+                            if let Err(err) = parse_flow(tmp_port, sequence) {
+                                let _ = ui_tx.send((
+                                    port_name.clone(),
+                                    LineDirection::System,
+                                    format!("── control sequence {} ──", err),
+                                ));
+                            }
+                        }
+                    }
+                    PortCommand::Repeat(command) => {
+                        if let Some(note) = schedules.apply(command, &port_name) {
+                            let _ = ui_tx.send((port_name.clone(), LineDirection::System, note));
+                        }
+                        let _ = result_tx.send(PortEvent::Schedules(schedules.info()));
+                    }
+                    PortCommand::Settings(name, port_settings) => {
+                        settings.insert(name, port_settings);
+                    }
+                    PortCommand::DownloadDir(name, dir) => {
+                        download_dirs.insert(name, dir);
+                    }
+                    PortCommand::Broadcast(names) => broadcast = names,
+                    PortCommand::Watch(name, watcher) => watchers.push((name, watcher)),
+                    PortCommand::On(..) => {}
+                }
+                if let Some(current) = current {
+                    port_name = current;
+                }
+            }
+            breaks.retain(|name, until| {
+                if Instant::now() < *until {
+                    return true;
+                }
+                if let Some(tmp_port) = serial_bookkeeping.get_mut(name) {
+                    let _ = tmp_port.clear_break();
+                }
+                false
+            });
+            for (name, port) in serial_bookkeeping.iter_mut() {
+                for note in port.take_notes() {
+                    let _ = ui_tx.send((name.clone(), LineDirection::System, note));
+                }
+                let sent = port.take_sent();
+                for line in String::from_utf8_lossy(&sent).lines() {
+                    let line = line.trim_end_matches('\r');
+                    if !line.is_empty() {
+                        let _ = ui_tx.send((name.clone(), LineDirection::Tx, line.to_owned()));
+                    }
+                }
+            }
+            for (name, share) in shares.iter_mut() {
+                let poll = share.poll();
+                if !poll.data.is_empty() {
+                    tx_queues
+                        .entry(name.clone())
+                        .or_default()
+                        .push(poll.data, Pacing::default());
+                }
+                report_share_clients(
+                    name,
+                    share,
+                    &poll.connected,
+                    &poll.disconnected,
+                    &ui_tx,
+                    &result_tx,
+                );
+            }
+            let mut ended = Vec::new();
+            for (name, tee) in tees.iter_mut() {
+                let poll = tee.poll();
+                if !poll.data.is_empty() && serial_bookkeeping.contains_key(name) {
+                    tx_queues
+                        .entry(name.clone())
+                        .or_default()
+                        .push(poll.data, Pacing::default());
+                }
+                for line in poll.stdout {
+                    let _ =
+                        ui_tx.send((name.clone(), LineDirection::System, format!("│ {}", line)));
+                }
+                for line in poll.stderr {
+                    let _ = ui_tx.send((
+                        name.clone(),
+                        LineDirection::System,
+                        format!("── {}: {} ──", tee.command(), line),
+                    ));
+                }
+                if let Some(status) = poll.exited {
+                    let _ = ui_tx.send((
+                        name.clone(),
+                        LineDirection::System,
+                        format!("── {} ended: {} ──", tee.command(), status),
+                    ));
+                    ended.push(name.clone());
+                }
+            }
+            for name in ended {
+                tees.remove(&name);
+            }
+            let due = schedules.take_due(|name| serial_bookkeeping.contains_key(name));
+            if !due.is_empty() {
+                for (name, data) in due {
+                    tx_queues
+                        .entry(name)
+                        .or_default()
+                        .push(data, Pacing::default());
+                }
+                let _ = result_tx.send(PortEvent::Schedules(schedules.info()));
+            }
+            for (name, queue) in tx_queues.iter_mut() {
+                // what is written during a BREAK would be lost, so it waits until the line is back
+                if breaks.contains_key(name) {
+                    continue;
+                }
+                if let Some(tmp_port) = serial_bookkeeping.get_mut(name) {
+                    report_tx(name, queue.service(tmp_port), &ui_tx, &result_tx);
                 }
             }
             if let Some(tmp_port) = serial_bookkeeping.get_mut(&port_name) {
                 let pending_buffer = read_buffers.entry(port_name.clone()).or_default();
-                if let Some(line_data) = read_line(tmp_port, pending_buffer, stop_flag.as_ref()) {
-                    ui_tx.send((port_name.clone(), line_data));
+                let mut received = Vec::new();
+                let encoding = settings.get(&port_name).map(|s| s.encoding);
+                let read_event = read_line(
+                    tmp_port,
+                    pending_buffer,
+                    &mut received,
+                    stop_flag.as_ref(),
+                    encoding.unwrap_or_default(),
+                );
+                if let Some(queue) = tx_queues.get_mut(&port_name) {
+                    queue.on_received(&received);
+                }
+                if let Some(note) = tees
+                    .get_mut(&port_name)
+                    .and_then(|tee| tee.received(&received))
+                {
+                    let _ = ui_tx.send((port_name.clone(), LineDirection::System, note));
+                }
+                watch(&mut watchers, &port_name, &received);
+                if let Some(share) = shares.get_mut(&port_name).filter(|_| !received.is_empty()) {
+                    let dropped = share.broadcast(&received);
+                    report_share_clients(&port_name, share, &[], &dropped, &ui_tx, &result_tx);
+                }
+                report_partial_line(
+                    &port_name,
+                    pending_buffer,
+                    &received,
+                    encoding.unwrap_or_default(),
+                    &mut partial_lines,
+                    &result_tx,
+                );
+                match read_event {
+                    Some(ReadEvent::Line(line_data)) => {
+                        let _ = ui_tx.send((port_name.clone(), LineDirection::Rx, line_data));
+                    }
+                    // with share clients connected the download is theirs to take
+                    Some(ReadEvent::ZmodemRequest)
+                        if !shares.get(&port_name).is_some_and(Share::has_clients) =>
+                    {
+                        let dir = download_dirs
+                            .get(&port_name)
+                            .cloned()
+                            .unwrap_or_else(|| PathBuf::from("."));
+                        let request = TransferRequest {
+                            protocol: Protocol::Zmodem,
+                            direction: TransferDirection::Receive(dir.clone()),
+                            resume: false,
+                            cancel: Arc::new(AtomicBool::new(false)),
+                        };
+                        let _ = result_tx.send(PortEvent::TransferStarted(
+                            port_name.clone(),
+                            request.protocol,
+                            dir,
+                            request.cancel.clone(),
+                        ));
+                        run_transfer(tmp_port, &port_name, &request, &result_tx);
+                        pending_buffer.clear();
+                    }
+                    Some(ReadEvent::ZmodemRequest) | None => {}
+                }
+            }
+            // shared ports keep being read for their clients, relaying ports for the other side,
+            // broadcast ones for comparing and paced ones for their prompt, while another port
+            // is shown
+            for (name, tmp_port) in serial_bookkeeping.iter_mut() {
+                let watched = watchers.iter().any(|(watched, _)| watched == name);
+                if *name == port_name
+                    || !(shares.contains_key(name)
+                        || tees.contains_key(name)
+                        || watched
+                        || broadcast.contains(name)
+                        || tx_queues.get(name).is_some_and(TxQueue::is_awaiting_prompt)
+                        || tmp_port.relays())
+                {
+                    continue;
+                }
+                let pending_buffer = read_buffers.entry(name.clone()).or_default();
+                let mut received = Vec::new();
+                let encoding = settings.get(name).map(|s| s.encoding);
+                let read_event = read_line(
+                    tmp_port,
+                    pending_buffer,
+                    &mut received,
+                    &AtomicBool::new(false),
+                    encoding.unwrap_or_default(),
+                );
+                if let Some(queue) = tx_queues.get_mut(name) {
+                    queue.on_received(&received);
+                }
+                if let Some(note) = tees.get_mut(name).and_then(|tee| tee.received(&received)) {
+                    let _ = ui_tx.send((name.clone(), LineDirection::System, note));
+                }
+                watch(&mut watchers, name, &received);
+                if let Some(share) = shares.get_mut(name).filter(|_| !received.is_empty()) {
+                    let dropped = share.broadcast(&received);
+                    report_share_clients(name, share, &[], &dropped, &ui_tx, &result_tx);
+                }
+                report_partial_line(
+                    name,
+                    pending_buffer,
+                    &received,
+                    encoding.unwrap_or_default(),
+                    &mut partial_lines,
+                    &result_tx,
+                );
+                match read_event {
+                    Some(ReadEvent::Line(line_data)) => {
+                        let _ = ui_tx.send((name.clone(), LineDirection::Rx, line_data));
+                    }
+                    // a ZMODEM download is left to the share client that asked for it, and not
+                    // taken at all while nobody did
+                    Some(ReadEvent::ZmodemRequest)
+                        if !shares.get(name).is_some_and(Share::has_clients) =>
+                    {
+                        let _ = ui_tx.send((
+                            name.clone(),
+                            LineDirection::System,
+                            "── ZMODEM download not taken while another port is shown ──"
+                                .to_owned(),
+                        ));
+                    }
+                    Some(ReadEvent::ZmodemRequest) | None => {}
+                }
+            }
+            if last_line_errors_check.elapsed() >= LINE_ERRORS_INTERVAL {
+                last_line_errors_check = Instant::now();
+                for (name, (baseline, last)) in line_errors.iter_mut() {
+                    let port = serial_bookkeeping.get(name);
+                    if let Some(counters) = port.and_then(|port| port.line_errors()) {
+                        let counters = counters.since(baseline);
+                        if counters != *last {
+                            *last = counters;
+                            let _ = result_tx.send(PortEvent::LineErrors(name.clone(), counters));
+                        }
+                    }
                 }
             }
         }
@@ -152,13 +822,31 @@ pub fn serial_thread(
 }
 
 pub mod utils {
-    use serialport::SerialPort;
     use std::time::Duration;
 
-    pub fn parse_flow(port: &mut Box<dyn SerialPort>, flow_string: String) {
+    use regex::Regex;
+
+    use super::transport::Transport;
+
+    /// Checks a control line sequence like `r1:d0:s1000:d1:r0`: `d` and `r` set DTR and RTS, `s`
+    /// sleeps for the given milliseconds.
+    pub fn check_flow(flow_string: &str) -> Result<(), String> {
+        let step = Regex::new(r"^([dr][01]|s\d+)$").unwrap();
+        match flow_string
+            .split(':')
+            .find(|p| !step.is_match(p) || p[1..].parse::<u64>().is_err())
+        {
+            Some(bad) => Err(format!("{}: not d0/d1, r0/r1 or s<ms>", bad)),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a control line sequence, see `check_flow`. Nothing is run unless all of it is valid.
+    pub fn parse_flow(port: &mut Box<dyn Transport>, flow_string: String) -> Result<(), String> {
+        check_flow(&flow_string)?;
         for p in flow_string.split(":").collect::<Vec<_>>() {
             let op = p.as_bytes()[0] as char;
-            let value = p[1..].parse::<u64>().unwrap();
+            let value = p[1..].parse::<u64>().map_err(|err| err.to_string())?;
             // println!("{}- {} ", p, value);
 
             if op == 'd' {
@@ -170,11 +858,12 @@ pub mod utils {
             } else {
             }
         }
+        Ok(())
     }
-    fn dtr(port: &mut Box<dyn SerialPort>, level: bool) {
+    fn dtr(port: &mut Box<dyn Transport>, level: bool) {
         port.write_data_terminal_ready(level);
     }
-    fn rts(port: &mut Box<dyn SerialPort>, level: bool) {
+    fn rts(port: &mut Box<dyn Transport>, level: bool) {
         port.write_request_to_send(level);
     }
     fn sleep(ms: u64) {
@@ -187,10 +876,64 @@ pub mod utils {
     use std::os::unix::io::AsRawFd;
     use std::ptr;
 
-    use libc::{c_int, c_short, c_ulong, c_void, timespec};
+    use libc::{c_int, c_short, c_ulong, c_void, timespec, TIOCGICOUNT};
     use libudev::Event;
     use libudev::EventType;
 
+    /// Mirror of the kernel's `struct serial_icounter_struct`.
+    #[repr(C)]
+    #[derive(Default)]
+    struct serial_icounter_struct {
+        cts: c_int,
+        dsr: c_int,
+        rng: c_int,
+        dcd: c_int,
+        rx: c_int,
+        tx: c_int,
+        frame: c_int,
+        overrun: c_int,
+        parity: c_int,
+        brk: c_int,
+        buf_overrun: c_int,
+        reserved: [c_int; 9],
+    }
+
+    /// BREAK conditions and framing, parity and overrun errors seen on a port.
+    #[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+    pub struct LineErrors {
+        pub brk: u32,
+        pub frame: u32,
+        pub parity: u32,
+        pub overrun: u32,
+    }
+
+    impl LineErrors {
+        pub fn since(&self, baseline: &LineErrors) -> LineErrors {
+            LineErrors {
+                brk: self.brk.wrapping_sub(baseline.brk),
+                frame: self.frame.wrapping_sub(baseline.frame),
+                parity: self.parity.wrapping_sub(baseline.parity),
+                overrun: self.overrun.wrapping_sub(baseline.overrun),
+            }
+        }
+    }
+
+    /// Reads the error counters of a tty with the `TIOCGICOUNT` ioctl. Returns `None` for
+    /// devices whose driver does not keep them (ptys, most CDC-ACM adapters, ...).
+    pub fn read_line_errors(fd: c_int) -> Option<LineErrors> {
+        let mut icount = serial_icounter_struct::default();
+        let result = unsafe { libc::ioctl(fd, TIOCGICOUNT, &mut icount) };
+        if result < 0 {
+            return None;
+        }
+        Some(LineErrors {
+            brk: icount.brk as u32,
+            frame: icount.frame as u32,
+            parity: icount.parity as u32,
+            overrun: (icount.overrun as u32).wrapping_add(icount.buf_overrun as u32),
+        })
+    }
+
     #[repr(C)]
     struct pollfd {
         fd: c_int,
@@ -261,3 +1004,146 @@ pub mod utils {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::serial::mock::harness::PtyDevice;
+
+    struct Thread {
+        port_tx: Sender<PortCommand>,
+        ui_rx: Receiver<(String, LineDirection, String)>,
+        result_rx: Receiver<PortEvent>,
+    }
+
+    impl Thread {
+        fn start() -> Thread {
+            let (ui_tx, ui_rx) = channel();
+            let (port_tx, port_rx) = channel();
+            let (result_tx, result_rx) = channel();
+            serial_thread(ui_tx, port_rx, result_tx, Arc::new(AtomicBool::new(false)));
+            Thread {
+                port_tx,
+                ui_rx,
+                result_rx,
+            }
+        }
+
+        /// Waits up to `timeout` for a partial line and returns it.
+        fn partial_line(&self, timeout: Duration) -> Option<(String, String)> {
+            let deadline = Instant::now() + timeout;
+            while let Ok(event) = self
+                .result_rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                if let PortEvent::PartialLine(name, line) = event {
+                    return Some((name, line));
+                }
+            }
+            None
+        }
+
+        fn send(&self, cmd: PortCommand) {
+            self.port_tx.send(cmd).unwrap();
+        }
+
+        /// Waits for the line and returns the ones before it.
+        fn expect(&self, port: &str, direction: LineDirection, text: &str) -> Vec<String> {
+            let deadline = Instant::now() + Duration::from_secs(2);
+            let mut before = Vec::new();
+            while let Ok((name, dir, line)) = self
+                .ui_rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                if name == port && dir == direction && line == text {
+                    return before;
+                }
+                before.push(line);
+            }
+            panic!(
+                "no {:?} line {:?} on {}, got {:?}",
+                direction, text, port, before
+            );
+        }
+    }
+
+    #[test]
+    fn talks_to_a_virtual_port() {
+        let thread = Thread::start();
+        thread.send(PortCommand::ChangePort("virtual:loopback".to_owned()));
+        thread.send(PortCommand::Write(CmdType::Raw("hello\n".to_owned())));
+        thread.expect("virtual:loopback", LineDirection::Tx, "hello");
+        thread.expect("virtual:loopback", LineDirection::Rx, "hello");
+    }
+
+    #[test]
+    fn talks_to_a_mock_device_on_a_pty() {
+        let device = PtyDevice::mock("virtual:echo:20").unwrap();
+        let name = device.path().display().to_string();
+        let thread = Thread::start();
+        thread.send(PortCommand::ChangePort(name.clone()));
+        thread.send(PortCommand::Write(CmdType::Raw("AT\r\n".to_owned())));
+        thread.expect(&name, LineDirection::Rx, "AT");
+    }
+
+    #[test]
+    fn reports_a_line_that_does_not_end() {
+        let thread = Thread::start();
+        thread.send(PortCommand::ChangePort("virtual:loopback".to_owned()));
+        thread.send(PortCommand::Write(CmdType::Raw("login: ".to_owned())));
+        let started = Instant::now();
+        let partial = thread.partial_line(Duration::from_secs(2));
+        assert_eq!(
+            partial,
+            Some(("virtual:loopback".to_owned(), "login: ".to_owned()))
+        );
+        assert!(started.elapsed() >= PARTIAL_LINE_IDLE);
+        // once is enough while nothing more arrives
+        assert_eq!(thread.partial_line(PARTIAL_LINE_IDLE * 2), None);
+        thread.send(PortCommand::Write(CmdType::Raw("root\n".to_owned())));
+        thread.expect("virtual:loopback", LineDirection::Rx, "login: root");
+    }
+
+    #[test]
+    fn takes_commands_before_the_first_port() {
+        let thread = Thread::start();
+        let (watcher, watched) = channel();
+        thread.send(PortCommand::Watch("virtual:loopback".to_owned(), watcher));
+        thread.send(PortCommand::ChangePort("virtual:loopback".to_owned()));
+        thread.send(PortCommand::Write(CmdType::Raw("hello\n".to_owned())));
+        thread.expect("virtual:loopback", LineDirection::Rx, "hello");
+        assert_eq!(watched.try_iter().flatten().collect::<Vec<_>>(), b"hello\n");
+    }
+
+    #[test]
+    fn writes_to_a_shown_port_that_is_not_open_go_nowhere() {
+        let thread = Thread::start();
+        let (watcher, _watched) = channel();
+        thread.send(PortCommand::Watch("virtual:loopback".to_owned(), watcher));
+        thread.send(PortCommand::ChangePort("virtual:loopback".to_owned()));
+        // a restored session whose shown port is still awaited
+        thread.send(PortCommand::Show("/dev/ttyUSB9".to_owned()));
+        thread.send(PortCommand::Write(CmdType::Raw("hello\n".to_owned())));
+        thread.send(PortCommand::On(
+            "virtual:loopback".to_owned(),
+            Box::new(PortCommand::Write(CmdType::Raw("marker\n".to_owned()))),
+        ));
+        let before = thread.expect("virtual:loopback", LineDirection::Rx, "marker");
+        assert!(!before.contains(&"hello".to_owned()), "got {:?}", before);
+    }
+
+    #[test]
+    fn a_break_does_not_hold_up_the_other_ports() {
+        let thread = Thread::start();
+        thread.send(PortCommand::ChangePort("virtual:loopback".to_owned()));
+        thread.send(PortCommand::ChangePort("virtual:echo".to_owned()));
+        thread.send(PortCommand::On(
+            "virtual:loopback".to_owned(),
+            Box::new(PortCommand::Break(Duration::from_secs(10))),
+        ));
+        thread.send(PortCommand::Write(CmdType::Raw("AT\r\n".to_owned())));
+        thread.expect("virtual:echo", LineDirection::Rx, "AT");
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use super::{
+    transport::{self, timed_out, Transport},
+    utils::LineErrors,
+};
+
+/// Prefix of port names that connect two ports to each other.
+pub const SCHEME: &str = "bridge:";
+
+/// How long a line without its line ending may wait before it is passed through the filters as
+/// it is, so prompts get through.
+const LINE_IDLE: Duration = Duration::from_millis(100);
+
+/// A bridge file, for example
+///
+/// ```toml
+/// a = "/dev/ttyUSB0"
+/// b = "sim:modem.toml"
+///
+/// [a_to_b]
+/// drop = '^DEBUG'
+///
+/// [b_to_a]
+/// delay_ms = 200
+///
+/// [[b_to_a.rewrite]]
+/// find = '^OK$'
+/// replace = "ERROR"
+/// ```
+#[derive(Deserialize)]
+struct Config {
+    a: String,
+    b: String,
+    #[serde(default)]
+    a_to_b: FilterConfig,
+    #[serde(default)]
+    b_to_a: FilterConfig,
+}
+
+#[derive(Deserialize, Default)]
+struct FilterConfig {
+    /// Lines matching this are not forwarded.
+    drop: Option<String>,
+    #[serde(default)]
+    delay_ms: u64,
+    /// Applied in order to every line forwarded, without its line ending.
+    #[serde(default)]
+    rewrite: Vec<RewriteConfig>,
+}
+
+#[derive(Deserialize)]
+struct RewriteConfig {
+    find: String,
+    /// `$1`, `${name}` and so on are replaced by what the regex captured.
+    replace: String,
+}
+
+/// What happens to the data going one way over the bridge.
+struct Filter {
+    drop: Option<Regex>,
+    rewrite: Vec<(Regex, String)>,
+    delay: Duration,
+    /// Start of a line that hasn't ended yet, and when it started.
+    line: Vec<u8>,
+    line_since: Option<Instant>,
+    /// Data waiting for its delay.
+    queued: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Filter {
+    fn new(config: FilterConfig, invalid: impl Fn(String) -> io::Error) -> io::Result<Filter> {
+        let regex = |pattern: &str| Regex::new(pattern).map_err(|err| invalid(err.to_string()));
+        Ok(Filter {
+            drop: config.drop.as_deref().map(regex).transpose()?,
+            rewrite: config
+                .rewrite
+                .iter()
+                .map(|rewrite| Ok((regex(&rewrite.find)?, rewrite.replace.clone())))
+                .collect::<io::Result<_>>()?,
+            delay: Duration::from_millis(config.delay_ms),
+            line: Vec::new(),
+            line_since: None,
+            queued: VecDeque::new(),
+        })
+    }
+
+    fn by_line(&self) -> bool {
+        self.drop.is_some() || !self.rewrite.is_empty()
+    }
+
+    /// Takes data coming in on one side. Lines that were dropped are returned for the notes.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let due = Instant::now() + self.delay;
+        if !self.by_line() {
+            if !bytes.is_empty() {
+                self.queued.push_back((due, bytes.to_vec()));
+            }
+            return Vec::new();
+        }
+        let mut dropped = Vec::new();
+        for &byte in bytes {
+            self.line_since.get_or_insert_with(Instant::now);
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.end_line(due, &mut dropped);
+            }
+        }
+        if self
+            .line_since
+            .is_some_and(|since| since.elapsed() >= LINE_IDLE)
+        {
+            self.end_line(due, &mut dropped);
+        }
+        dropped
+    }
+
+    fn end_line(&mut self, due: Instant, dropped: &mut Vec<String>) {
+        self.line_since = None;
+        let line = std::mem::take(&mut self.line);
+        let text = String::from_utf8_lossy(&line);
+        let body = text.trim_end_matches(['\r', '\n']);
+        let ending = &text[body.len()..];
+        if self.drop.as_ref().is_some_and(|drop| drop.is_match(body)) {
+            dropped.push(body.to_owned());
+            return;
+        }
+        if self.rewrite.is_empty() {
+            self.queued.push_back((due, line));
+            return;
+        }
+        let mut body = body.to_owned();
+        for (find, replace) in &self.rewrite {
+            body = find.replace_all(&body, replace.as_str()).into_owned();
+        }
+        body.push_str(ending);
+        self.queued.push_back((due, body.into_bytes()));
+    }
+
+    /// Data whose delay is over, ready to be forwarded.
+    fn take_due(&mut self) -> Vec<u8> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while let Some((at, bytes)) = self.queued.front() {
+            if *at > now {
+                break;
+            }
+            due.extend_from_slice(bytes);
+            self.queued.pop_front();
+        }
+        due
+    }
+}
+
+/// Two ports forwarding to each other. What `a` sends to `b` shows up as received data, what `b`
+/// sends to `a` as sent data. Writes go to `a`, and so do the control lines and BREAK.
+pub struct BridgeTransport {
+    a: Box<dyn Transport>,
+    b: Box<dyn Transport>,
+    a_name: String,
+    b_name: String,
+    a_to_b: Filter,
+    b_to_a: Filter,
+    /// Forwarded from `a` to `b` and not yet handed to the reader.
+    received: VecDeque<u8>,
+    sent: Vec<u8>,
+    notes: Vec<String>,
+}
+
+impl BridgeTransport {
+    /// Opens `name`, which is `bridge:<a>,<b>` or `bridge:<bridge file>.toml`.
+    pub fn open(name: &str, timeout: Duration) -> io::Result<BridgeTransport> {
+        let spec = name.strip_prefix(SCHEME).unwrap_or(name);
+        let config = if spec.ends_with(".toml") {
+            let path = Path::new(spec);
+            toml::from_str::<Config>(&fs::read_to_string(path)?).map_err(|err| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), err.message()),
+                )
+            })?
+        } else {
+            let (a, b) = spec.split_once(',').ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: expected bridge:<port>,<port>", name),
+                )
+            })?;
+            Config {
+                a: a.to_owned(),
+                b: b.to_owned(),
+                a_to_b: FilterConfig::default(),
+                b_to_a: FilterConfig::default(),
+            }
+        };
+        let invalid =
+            |err: String| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", spec, err));
+        let a_to_b = Filter::new(config.a_to_b, invalid)?;
+        let b_to_a = Filter::new(config.b_to_a, invalid)?;
+
+        // both sides are read on every read, together they take the timeout
+        let timeout = (timeout / 2).max(Duration::from_millis(1));
+        let a = transport::open(&config.a, timeout)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", config.a, err)))?;
+        let b = transport::open(&config.b, timeout)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", config.b, err)))?;
+        let notes = vec![format!(
+            "── bridging {0} and {1}: « is {0} talking, » is {1} ──",
+            config.a, config.b
+        )];
+        Ok(BridgeTransport {
+            a,
+            b,
+            a_name: config.a,
+            b_name: config.b,
+            a_to_b,
+            b_to_a,
+            received: VecDeque::new(),
+            sent: Vec::new(),
+            notes,
+        })
+    }
+
+    /// Reads what `from` has into `filter`, with the lines it dropped as notes.
+    fn take_in(
+        from: &mut dyn Transport,
+        filter: &mut Filter,
+        label: &str,
+        notes: &mut Vec<String>,
+    ) -> io::Result<()> {
+        let mut buf = [0_u8; 1024];
+        let read = match from.read(&mut buf) {
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::TimedOut => 0,
+            Err(err) => return Err(err),
+        };
+        for line in filter.push(&buf[..read]) {
+            notes.push(format!("── dropped {}: {} ──", label, line));
+        }
+        Ok(())
+    }
+
+    /// Writes what `filter` has ready to `to`. Returns what was written.
+    fn hand_over(
+        to: &mut dyn Transport,
+        filter: &mut Filter,
+        label: &str,
+        notes: &mut Vec<String>,
+    ) -> io::Result<Vec<u8>> {
+        let due = filter.take_due();
+        if due.is_empty() {
+            return Ok(due);
+        }
+        match to.write_all(&due) {
+            Ok(()) => Ok(due),
+            Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                notes.push(format!(
+                    "── {} is stuck, {} bytes lost ──",
+                    label,
+                    due.len()
+                ));
+                Ok(Vec::new())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Read for BridgeTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() {
+            let a_to_b = format!("{} → {}", self.a_name, self.b_name);
+            let b_to_a = format!("{} → {}", self.b_name, self.a_name);
+            Self::take_in(&mut *self.b, &mut self.b_to_a, &b_to_a, &mut self.notes)?;
+            let sent = Self::hand_over(&mut *self.a, &mut self.b_to_a, &b_to_a, &mut self.notes)?;
+            self.sent.extend_from_slice(&sent);
+            Self::take_in(&mut *self.a, &mut self.a_to_b, &a_to_b, &mut self.notes)?;
+            let received =
+                Self::hand_over(&mut *self.b, &mut self.a_to_b, &a_to_b, &mut self.notes)?;
+            self.received.extend(received);
+        }
+        if self.received.is_empty() {
+            return Err(timed_out());
+        }
+        let read = buf.len().min(self.received.len());
+        for (slot, byte) in buf.iter_mut().zip(self.received.drain(..read)) {
+            *slot = byte;
+        }
+        Ok(read)
+    }
+}
+
+impl Write for BridgeTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.a.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.a.flush()
+    }
+}
+
+impl Transport for BridgeTransport {
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        self.a.write_data_terminal_ready(level)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        self.a.write_request_to_send(level)
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        self.a.set_break()
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        self.a.clear_break()
+    }
+
+    fn line_errors(&self) -> Option<LineErrors> {
+        self.a.line_errors()
+    }
+
+    fn take_notes(&mut self) -> Vec<String> {
+        let mut notes = std::mem::take(&mut self.notes);
+        notes.extend(self.a.take_notes());
+        notes.extend(self.b.take_notes());
+        notes
+    }
+
+    fn take_sent(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sent)
+    }
+
+    fn relays(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    use super::{BridgeTransport, LINE_IDLE};
+    use crate::serial::{
+        mock::harness::{read_until, TempDir, TIMEOUT},
+        transport::Transport,
+    };
+
+    #[test]
+    fn filters_what_goes_over() {
+        let dir = TempDir::new("bridge");
+        let path = dir.write(
+            "filters.toml",
+            r#"
+a = "virtual:loopback"
+b = "virtual:loopback"
+
+[a_to_b]
+drop = '^DEBUG'
+delay_ms = 200
+
+[[a_to_b.rewrite]]
+find = '^OK$'
+replace = "ERROR"
+
+# what comes back is dropped, or the two loopbacks would bounce it forever
+[b_to_a]
+drop = ''
+"#,
+        );
+        let mut port = BridgeTransport::open(&format!("bridge:{}", path.display()), TIMEOUT)
+            .unwrap();
+        let started = Instant::now();
+        port.write_all(b"DEBUG noise\r\nOK\r\nlogin: ").unwrap();
+        let received = read_until(&mut port, Duration::from_secs(2), |received| {
+            received.ends_with(b"login: ")
+        });
+        // the prompt never ends, it goes over once it has been quiet for a while
+        assert_eq!(received, b"ERROR\r\nlogin: ");
+        assert!(started.elapsed() >= LINE_IDLE + Duration::from_millis(200));
+        let notes = port.take_notes();
+        assert!(notes.contains(
+            &"── dropped virtual:loopback → virtual:loopback: DEBUG noise ──".to_owned()
+        ));
+    }
+}
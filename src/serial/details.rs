@@ -0,0 +1,209 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::identity;
+
+/// A group of facts about a port, shown under a heading.
+pub struct Section {
+    pub title: &'static str,
+    pub rows: Vec<(String, String)>,
+}
+
+/// Everything we can find out about the local serial device `port_name` from udev, `/dev/serial`
+/// and `/proc`.
+pub fn gather(port_name: &str) -> Vec<Section> {
+    let Ok(node) = identity::device_path(port_name) else {
+        return vec![Section {
+            title: "port",
+            rows: vec![(
+                "device".to_owned(),
+                "not a local device, nothing more to tell".to_owned(),
+            )],
+        }];
+    };
+    let mut sections = Vec::new();
+    match udev_sections(port_name, &node) {
+        Ok(found) => sections.extend(found),
+        Err(err) => sections.push(Section {
+            title: "udev",
+            rows: vec![("error".to_owned(), err)],
+        }),
+    }
+    let mut links = ["by-id", "by-path"]
+        .into_iter()
+        .flat_map(|kind| {
+            symlinks_to(&node, &Path::new("/dev/serial").join(kind))
+                .into_iter()
+                .map(move |link| (kind.to_owned(), link.display().to_string()))
+        })
+        .collect::<Vec<_>>();
+    if links.is_empty() {
+        links.push(("-".to_owned(), "none in /dev/serial".to_owned()));
+    }
+    sections.push(Section {
+        title: "symlinks",
+        rows: links,
+    });
+    sections.push(Section {
+        title: "held open by",
+        rows: holders(&node),
+    });
+    sections
+}
+
+fn text(value: Option<&OsStr>) -> Option<String> {
+    value.map(|value| value.to_string_lossy().into_owned())
+}
+
+fn udev_sections(port_name: &str, node: &Path) -> Result<Vec<Section>, String> {
+    let sysname = node
+        .file_name()
+        .ok_or_else(|| format!("{}: no device name", node.display()))?;
+    let context = libudev::Context::new().map_err(|err| err.to_string())?;
+    let device =
+        libudev::Device::from_syspath(&context, &Path::new("/sys/class/tty").join(sysname))
+            .map_err(|err| format!("{} is not known to udev: {}", node.display(), err))?;
+
+    let mut usb = Vec::new();
+    let mut topology = Vec::new();
+    let mut driver = None;
+    let mut parent = device.parent();
+    while let Some(ancestor) = parent {
+        if driver.is_none() {
+            driver = text(ancestor.driver());
+        }
+        match ancestor.devtype().and_then(OsStr::to_str) {
+            Some("usb_interface") if usb.is_empty() => {
+                if let Some(number) = text(ancestor.attribute_value("bInterfaceNumber")) {
+                    usb.push(("interface".to_owned(), number));
+                }
+            }
+            Some("usb_device") => {
+                if topology.is_empty() {
+                    let attribute = |name: &str| text(ancestor.attribute_value(name));
+                    if let (Some(vid), Some(pid)) = (attribute("idVendor"), attribute("idProduct"))
+                    {
+                        usb.insert(0, ("vid:pid".to_owned(), format!("{}:{}", vid, pid)));
+                    }
+                    for (label, name) in [
+                        ("serial", "serial"),
+                        ("manufacturer", "manufacturer"),
+                        ("product", "product"),
+                        ("speed", "speed"),
+                    ] {
+                        if let Some(value) = attribute(name) {
+                            usb.push((label.to_owned(), value));
+                        }
+                    }
+                }
+                let name = text(ancestor.sysname()).unwrap_or_default();
+                topology.push(match text(ancestor.attribute_value("product")) {
+                    Some(product) => format!("{} ({})", name, product),
+                    None => name,
+                });
+            }
+            _ => {}
+        }
+        parent = ancestor.parent();
+    }
+    topology.reverse();
+    if !topology.is_empty() {
+        usb.push(("topology".to_owned(), topology.join(" › ")));
+    }
+
+    let mut system = vec![("device".to_owned(), node.display().to_string())];
+    if Path::new(port_name) != node {
+        system.push(("known as".to_owned(), port_name.to_owned()));
+    }
+    if let Some(driver) = driver {
+        system.push(("driver".to_owned(), driver));
+    }
+    if let Some(syspath) = device.syspath() {
+        system.push(("sysfs".to_owned(), syspath.display().to_string()));
+    }
+    let properties = device
+        .properties()
+        .map(|property| {
+            (
+                property.name().to_string_lossy().into_owned(),
+                property.value().to_string_lossy().into_owned(),
+            )
+        })
+        .collect();
+
+    let mut sections = Vec::new();
+    if !usb.is_empty() {
+        sections.push(Section {
+            title: "usb",
+            rows: usb,
+        });
+    }
+    sections.push(Section {
+        title: "system",
+        rows: system,
+    });
+    sections.push(Section {
+        title: "udev properties",
+        rows: properties,
+    });
+    Ok(sections)
+}
+
+/// The links in `dir` that point at `node`.
+pub fn symlinks_to(node: &Path, dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut links = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|link| fs::canonicalize(link).is_ok_and(|target| target == node))
+        .collect::<Vec<_>>();
+    links.sort();
+    links
+}
+
+/// The processes with `node` open, as far as `/proc` lets us see them.
+fn holders(node: &Path) -> Vec<(String, String)> {
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own = std::process::id().to_string();
+    let mut holders = Vec::new();
+    for process in processes.flatten() {
+        let pid = process.file_name().to_string_lossy().into_owned();
+        if !pid.bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(fds) = fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        let holds = fds
+            .flatten()
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == node));
+        if !holds {
+            continue;
+        }
+        let command = fs::read_to_string(process.path().join("comm"))
+            .map(|comm| comm.trim_end().to_owned())
+            .unwrap_or_default();
+        holders.push((
+            pid.clone(),
+            if pid == own {
+                format!("{} (this determ)", command)
+            } else {
+                command
+            },
+        ));
+    }
+    if holders.is_empty() {
+        holders.push((
+            "-".to_owned(),
+            "nobody, of the processes we may look at".to_owned(),
+        ));
+    }
+    holders
+}
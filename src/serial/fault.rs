@@ -0,0 +1,564 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    transport::{timed_out, Transport},
+    utils::LineErrors,
+};
+
+const BREAK_LENGTH: Duration = Duration::from_millis(50);
+const DISCONNECT_LENGTH: Duration = Duration::from_secs(2);
+
+/// SplitMix64, small and good enough to decide which bytes get hit, and the same seed always
+/// gives the same faults for the same traffic.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// `true` with the probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && (self.next_u64() >> 11) as f64 / ((1_u64 << 53) as f64) < p
+    }
+
+    fn percent(&mut self, percent: f64) -> bool {
+        self.chance(percent / 100.0)
+    }
+}
+
+/// What to do to the traffic of a port, written as for example
+/// `drop=1 dup=0.5 corrupt=0.1 delay=5:200 break=2 disconnect=1:3000 seed=42 only=rx`:
+/// - `drop`, `dup` and `corrupt` are the percentage of bytes lost, doubled or with a bit flipped,
+/// - `delay=P:MS` holds back P percent of the lines read or written, and what follows them,
+///   for MS milliseconds,
+/// - `break=N` sends N BREAKs a minute on average,
+/// - `disconnect=N:MS` cuts the link N times a minute on average, for MS milliseconds,
+/// - `seed` makes a run reproducible, it is picked at random when left out,
+/// - `only=rx` or `only=tx` limits the byte faults to one direction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FaultConfig {
+    /// Percentages, as given.
+    pub drop: f64,
+    pub duplicate: f64,
+    pub corrupt: f64,
+    pub delay: f64,
+    pub delay_for: Duration,
+    pub breaks_per_minute: f64,
+    pub disconnects_per_minute: f64,
+    pub disconnect_for: Duration,
+    pub seed: u64,
+    pub rx: bool,
+    pub tx: bool,
+}
+
+impl FromStr for FaultConfig {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<FaultConfig, String> {
+        let mut config = FaultConfig {
+            drop: 0.0,
+            duplicate: 0.0,
+            corrupt: 0.0,
+            delay: 0.0,
+            delay_for: Duration::ZERO,
+            breaks_per_minute: 0.0,
+            disconnects_per_minute: 0.0,
+            disconnect_for: DISCONNECT_LENGTH,
+            seed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            rx: true,
+            tx: true,
+        };
+        for word in spec.split_whitespace() {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected key=value", word))?;
+            let number = |text: &str| {
+                text.parse::<f64>()
+                    .ok()
+                    .filter(|number| *number >= 0.0)
+                    .ok_or_else(|| format!("{}: not a number", word))
+            };
+            let percent = |text: &str| Ok::<_, String>(number(text)?.min(100.0));
+            let with_millis = |text: &str| match text.split_once(':') {
+                Some((rate, millis)) => Ok((
+                    number(rate)?,
+                    Some(Duration::from_millis(number(millis)? as u64)),
+                )),
+                None => Ok::<_, String>((number(text)?, None)),
+            };
+            match key {
+                "drop" => config.drop = percent(value)?,
+                "dup" => config.duplicate = percent(value)?,
+                "corrupt" => config.corrupt = percent(value)?,
+                "delay" => {
+                    let (rate, millis) = with_millis(value)?;
+                    config.delay = rate.min(100.0);
+                    config.delay_for =
+                        millis.ok_or_else(|| format!("{}: expected delay=percent:ms", word))?;
+                }
+                "break" => config.breaks_per_minute = number(value)?,
+                "disconnect" => {
+                    let (rate, millis) = with_millis(value)?;
+                    config.disconnects_per_minute = rate;
+                    config.disconnect_for = millis.unwrap_or(DISCONNECT_LENGTH);
+                }
+                "seed" => {
+                    config.seed = value
+                        .parse()
+                        .map_err(|_| format!("{}: not a number", word))?
+                }
+                "only" => match value {
+                    "rx" => config.tx = false,
+                    "tx" => config.rx = false,
+                    _ => return Err(format!("{}: expected only=rx or only=tx", word)),
+                },
+                _ => return Err(format!("{}: unknown fault", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for FaultConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut words = Vec::new();
+        for (key, rate) in [
+            ("drop", self.drop),
+            ("dup", self.duplicate),
+            ("corrupt", self.corrupt),
+        ] {
+            if rate > 0.0 {
+                words.push(format!("{}={}", key, rate));
+            }
+        }
+        if self.delay > 0.0 {
+            words.push(format!(
+                "delay={}:{}",
+                self.delay,
+                self.delay_for.as_millis()
+            ));
+        }
+        if self.breaks_per_minute > 0.0 {
+            words.push(format!("break={}", self.breaks_per_minute));
+        }
+        if self.disconnects_per_minute > 0.0 {
+            words.push(format!(
+                "disconnect={}:{}",
+                self.disconnects_per_minute,
+                self.disconnect_for.as_millis()
+            ));
+        }
+        words.push(format!("seed={}", self.seed));
+        match (self.rx, self.tx) {
+            (true, false) => words.push("only=rx".to_owned()),
+            (false, true) => words.push("only=tx".to_owned()),
+            _ => {}
+        }
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// The faults of the traffic going one way. Each direction has dice of its own, and BREAKs and
+/// disconnects yet others, so a seed gives the same faults for the same bytes however they are
+/// chunked, interleaved or timed.
+struct Lane {
+    direction: &'static str,
+    rng: SplitMix64,
+    /// Whether the next byte starts a line, which is when `delay` is decided.
+    line_start: bool,
+    /// Bytes waiting to go on, in order, with when they may.
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Lane {
+    fn new(direction: &'static str, seed: u64) -> Lane {
+        Lane {
+            direction,
+            rng: SplitMix64(seed),
+            line_start: true,
+            delayed: VecDeque::new(),
+        }
+    }
+
+    /// Loses, doubles and garbles bytes, with a marker if anything happened.
+    fn mangle(&mut self, config: &FaultConfig, bytes: &[u8], notes: &mut Vec<String>) -> Vec<u8> {
+        let (mut dropped, mut duplicated, mut corrupted) = (0, 0, 0);
+        let mut mangled = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            if self.rng.percent(config.drop) {
+                dropped += 1;
+                continue;
+            }
+            let byte = if self.rng.percent(config.corrupt) {
+                corrupted += 1;
+                byte ^ (1 << (self.rng.next_u64() % 8))
+            } else {
+                byte
+            };
+            mangled.push(byte);
+            if self.rng.percent(config.duplicate) {
+                duplicated += 1;
+                mangled.push(byte);
+            }
+        }
+        let counts = [
+            (dropped, "dropped"),
+            (duplicated, "duplicated"),
+            (corrupted, "corrupted"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect::<Vec<_>>();
+        if !counts.is_empty() {
+            notes.push(format!("── fault: {} {} ──", self.direction, counts.join(", ")));
+        }
+        mangled
+    }
+
+    /// Queues `bytes`, holding back the lines the dice pick. Nothing overtakes a held line, a
+    /// slow link doesn't reorder what goes over it.
+    fn hold(
+        &mut self,
+        config: &FaultConfig,
+        bytes: &[u8],
+        now: Instant,
+        notes: &mut Vec<String>,
+    ) {
+        for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+            let mut at = now;
+            if self.line_start && self.rng.percent(config.delay) {
+                notes.push(format!(
+                    "── fault: {} held back {} ms ──",
+                    self.direction,
+                    config.delay_for.as_millis()
+                ));
+                at += config.delay_for;
+            }
+            self.line_start = line.ends_with(b"\n");
+            if let Some((last, _)) = self.delayed.back() {
+                at = at.max(*last);
+            }
+            self.delayed.push_back((at, line.to_vec()));
+        }
+    }
+
+    /// Takes what may go on by `now`, everything when `now` is `None`.
+    fn release(&mut self, now: Option<Instant>) -> Vec<u8> {
+        let mut due = Vec::new();
+        while self
+            .delayed
+            .front()
+            .is_some_and(|(at, _)| now.is_none_or(|now| *at <= now))
+        {
+            if let Some((_, bytes)) = self.delayed.pop_front() {
+                due.extend(bytes);
+            }
+        }
+        due
+    }
+}
+
+/// The injector's side of a port, shared with the serial thread so faults can be changed or
+/// switched off while the port stays open.
+pub struct FaultState {
+    config: Option<FaultConfig>,
+    /// The dice for BREAKs and disconnects.
+    rng: SplitMix64,
+    rx: Lane,
+    tx: Lane,
+    last_tick: Instant,
+    down_until: Option<Instant>,
+    /// Bytes that went nowhere while the link was down.
+    lost: usize,
+    rx_ready: VecDeque<u8>,
+    tx_pending: VecDeque<u8>,
+    notes: Vec<String>,
+}
+
+pub type FaultHandle = Arc<Mutex<FaultState>>;
+
+impl FaultState {
+    /// Switches to `config`, or off. Delayed data is let through rather than lost.
+    pub fn configure(&mut self, config: Option<FaultConfig>) {
+        self.notes.push(match &config {
+            Some(config) => format!("── faults on: {} ──", config),
+            None => "── faults off ──".to_owned(),
+        });
+        let rx = self.rx.release(None);
+        self.rx_ready.extend(rx);
+        let tx = self.tx.release(None);
+        self.tx_pending.extend(tx);
+        let mut seeds = SplitMix64(config.map_or(0, |config| config.seed));
+        self.rng = SplitMix64(seeds.next_u64());
+        self.rx = Lane::new("rx", seeds.next_u64());
+        self.tx = Lane::new("tx", seeds.next_u64());
+        self.config = config;
+        self.last_tick = Instant::now();
+        if self.down_until.take().is_some() {
+            self.link_up();
+        }
+    }
+
+    fn link_up(&mut self) {
+        self.notes.push(format!(
+            "── fault: link back up, {} bytes lost ──",
+            self.lost
+        ));
+        self.lost = 0;
+    }
+
+    /// Rolls the dice for BREAKs and disconnects over the time since the last call, and lets
+    /// delayed data through once it's due.
+    fn tick(&mut self, port: &mut dyn Transport) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick).as_secs_f64() / 60.0;
+        self.last_tick = now;
+        if self.down_until.is_some_and(|until| until <= now) {
+            self.down_until = None;
+            self.link_up();
+        }
+        if let Some(config) = self.config {
+            if self.down_until.is_none() && self.rng.chance(config.disconnects_per_minute * elapsed)
+            {
+                self.down_until = Some(now + config.disconnect_for);
+                self.notes.push(format!(
+                    "── fault: link down for {} ms ──",
+                    config.disconnect_for.as_millis()
+                ));
+            }
+            if self.down_until.is_none() && self.rng.chance(config.breaks_per_minute * elapsed) {
+                let result = port.set_break().and_then(|()| {
+                    thread::sleep(BREAK_LENGTH);
+                    port.clear_break()
+                });
+                self.notes.push(match result {
+                    Ok(()) => "── fault: BREAK sent ──".to_owned(),
+                    Err(err) => format!("── fault: BREAK failed: {} ──", err),
+                });
+            }
+        }
+        let rx = self.rx.release(Some(now));
+        self.rx_ready.extend(rx);
+        let tx = self.tx.release(Some(now));
+        self.tx_pending.extend(tx);
+    }
+
+    /// Writes out what's waiting to go, as far as the port takes it.
+    fn flush_tx(&mut self, port: &mut dyn Transport) -> io::Result<()> {
+        while !self.tx_pending.is_empty() {
+            let (front, _) = self.tx_pending.as_slices();
+            match port.write(front) {
+                Ok(0) => break,
+                Ok(written) => {
+                    self.tx_pending.drain(..written);
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                    ) =>
+                {
+                    break
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A port with a fault injector between it and the rest of the app.
+pub struct FaultTransport {
+    inner: Box<dyn Transport>,
+    state: FaultHandle,
+}
+
+/// Puts a fault injector, switched off, in front of `port`.
+pub fn wrap(port: Box<dyn Transport>) -> (Box<dyn Transport>, FaultHandle) {
+    let state = Arc::new(Mutex::new(FaultState {
+        config: None,
+        rng: SplitMix64(0),
+        rx: Lane::new("rx", 0),
+        tx: Lane::new("tx", 0),
+        last_tick: Instant::now(),
+        down_until: None,
+        lost: 0,
+        rx_ready: VecDeque::new(),
+        tx_pending: VecDeque::new(),
+        notes: Vec::new(),
+    }));
+    let port = FaultTransport {
+        inner: port,
+        state: state.clone(),
+    };
+    (Box::new(port), state)
+}
+
+impl Read for FaultTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.tick(&mut *self.inner);
+        state.flush_tx(&mut *self.inner)?;
+        if state.rx_ready.is_empty() {
+            let mut chunk = vec![0_u8; buf.len()];
+            let read = self.inner.read(&mut chunk)?;
+            if state.down_until.is_some() {
+                state.lost += read;
+                return Err(timed_out());
+            }
+            match state.config.filter(|config| config.rx) {
+                Some(config) => {
+                    let state = &mut *state;
+                    let mangled = state.rx.mangle(&config, &chunk[..read], &mut state.notes);
+                    let now = Instant::now();
+                    state.rx.hold(&config, &mangled, now, &mut state.notes);
+                    let due = state.rx.release(Some(now));
+                    state.rx_ready.extend(due);
+                }
+                None => state.rx_ready.extend(&chunk[..read]),
+            }
+        }
+        if state.rx_ready.is_empty() {
+            return Err(timed_out());
+        }
+        let read = buf.len().min(state.rx_ready.len());
+        for (slot, byte) in buf.iter_mut().zip(state.rx_ready.drain(..read)) {
+            *slot = byte;
+        }
+        Ok(read)
+    }
+}
+
+impl Write for FaultTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.flush_tx(&mut *self.inner)?;
+        if state.down_until.is_some() {
+            state.lost += buf.len();
+            return Ok(buf.len());
+        }
+        if !state.tx_pending.is_empty() {
+            // the port is still busy with what came before, the caller tries again
+            return Err(timed_out());
+        }
+        let Some(config) = state.config.filter(|config| config.tx) else {
+            return self.inner.write(buf);
+        };
+        let state = &mut *state;
+        let mangled = state.tx.mangle(&config, buf, &mut state.notes);
+        let now = Instant::now();
+        state.tx.hold(&config, &mangled, now, &mut state.notes);
+        let due = state.tx.release(Some(now));
+        state.tx_pending.extend(due);
+        state.flush_tx(&mut *self.inner)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for FaultTransport {
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        self.inner.write_data_terminal_ready(level)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        self.inner.write_request_to_send(level)
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        self.inner.set_break()
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        self.inner.clear_break()
+    }
+
+    fn line_errors(&self) -> Option<LineErrors> {
+        self.inner.line_errors()
+    }
+
+    fn take_notes(&mut self) -> Vec<String> {
+        let mut notes = self.inner.take_notes();
+        notes.append(&mut self.state.lock().unwrap().notes);
+        notes
+    }
+
+    fn take_sent(&mut self) -> Vec<u8> {
+        self.inner.take_sent()
+    }
+
+    fn relays(&self) -> bool {
+        self.inner.relays()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    use super::{super::mock, wrap};
+    use crate::serial::mock::harness::read_until;
+
+    const TEXT: &[u8] = b"the quick brown fox\njumps over\nthe lazy dog\n";
+
+    /// Writes `TEXT` in `chunk` byte pieces through faults `spec` and reads back what got through,
+    /// all of it or until `done`.
+    fn loop_back(spec: &str, chunk: usize, done: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+        let port = mock::open("virtual:loopback", Duration::from_millis(5)).unwrap();
+        let (mut port, faults) = wrap(port);
+        faults.lock().unwrap().configure(Some(spec.parse().unwrap()));
+        for piece in TEXT.chunks(chunk) {
+            port.write_all(piece).unwrap();
+        }
+        read_until(&mut *port, Duration::from_millis(500), done)
+    }
+
+    #[test]
+    fn a_seed_gives_the_same_faults_however_the_bytes_are_chunked() {
+        let spec = "drop=10 dup=10 corrupt=10 seed=7 only=tx";
+        let whole = loop_back(spec, TEXT.len(), |_| false);
+        assert_ne!(whole, TEXT);
+        assert_eq!(loop_back(spec, 1, |_| false), whole);
+        assert_eq!(loop_back(spec, 5, |_| false), whole);
+    }
+
+    #[test]
+    fn breaks_leave_the_byte_faults_alone() {
+        let spec = "drop=10 dup=10 corrupt=10 seed=7 only=tx";
+        let with_breaks = loop_back(&format!("{} break=6000", spec), 3, |_| false);
+        assert_eq!(with_breaks, loop_back(spec, 3, |_| false));
+    }
+
+    #[test]
+    fn held_lines_are_not_overtaken() {
+        let started = Instant::now();
+        let received = loop_back("delay=50:200 seed=1 only=tx", 4, |received| {
+            received.len() == TEXT.len()
+        });
+        assert_eq!(received, TEXT);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glob::Pattern;
+use serialport::{SerialPortInfo, SerialPortType};
+
+use super::details::symlinks_to;
+
+/// Where udev links USB serial devices by what they are rather than the order they came in.
+pub const BY_ID: &str = "/dev/serial/by-id/";
+
+/// Names for USB devices with a serial number but no `/dev/serial/by-id` link, like
+/// `usb:0403:6001:A10K5LXY:0`, opened wherever the device shows up.
+pub const SCHEME: &str = "usb:";
+
+/// The name determ knows a port by, for profiles, sessions and the like. USB devices keep theirs
+/// whichever order they are plugged in: their `/dev/serial/by-id` link, or failing that one made
+/// from their serial number. Other ports go by their kernel name.
+pub fn stable_name(info: &SerialPortInfo) -> String {
+    if !matches!(info.port_type, SerialPortType::UsbPort(_)) {
+        return info.port_name.clone();
+    }
+    if let Some(link) = fs::canonicalize(&info.port_name)
+        .ok()
+        .and_then(|node| symlinks_to(&node, Path::new(BY_ID)).into_iter().next())
+    {
+        return link.display().to_string();
+    }
+    usb_name(info).unwrap_or_else(|| info.port_name.clone())
+}
+
+/// Whether a port glob from a profile, trigger or script matches the port called `name`. A USB
+/// device goes by its stable name but also answers to `kernel_name`, the tty it is on right now,
+/// so `/dev/ttyUSB*` keeps matching it as well as a glob for its `/dev/serial/by-id` link does.
+pub fn glob_matches(pattern: &Pattern, name: &str, kernel_name: Option<&str>) -> bool {
+    pattern.matches(name) || kernel_name.is_some_and(|kernel_name| pattern.matches(kernel_name))
+}
+
+/// `usb:vid:pid:serial:interface`, for USB devices that have a serial number.
+fn usb_name(info: &SerialPortInfo) -> Option<String> {
+    let SerialPortType::UsbPort(usb) = &info.port_type else {
+        return None;
+    };
+    let serial = usb.serial_number.as_ref()?;
+    Some(format!(
+        "{}{:04x}:{:04x}:{}:{}",
+        SCHEME,
+        usb.vid,
+        usb.pid,
+        serial,
+        interface(&info.port_name).unwrap_or(0)
+    ))
+}
+
+/// The USB interface a tty belongs to, which tells the ports of multi-port adapters apart. It
+/// sits on the tty's device for CDC-ACM and one level up for USB serial converters.
+fn interface(port_name: &str) -> Option<u8> {
+    let sysname = Path::new(port_name).file_name()?;
+    let device = Path::new("/sys/class/tty").join(sysname).join("device");
+    [
+        device.join("bInterfaceNumber"),
+        device.join("../bInterfaceNumber"),
+    ]
+    .iter()
+    .find_map(|path| fs::read_to_string(path).ok())
+    .and_then(|number| u8::from_str_radix(number.trim(), 16).ok())
+}
+
+/// Whether the port is a local device, which may come and go.
+pub fn is_device(name: &str) -> bool {
+    name.starts_with("/dev/") || name.starts_with(SCHEME)
+}
+
+/// The name to show for a port that has no alias, `/dev/serial/by-id/` says nothing.
+pub fn short_name(name: &str) -> &str {
+    name.strip_prefix(BY_ID).unwrap_or(name)
+}
+
+/// The device node behind a port's name as it is plugged in now, `/dev/ttyUSB0` for example.
+pub fn device_path(name: &str) -> io::Result<PathBuf> {
+    if name.starts_with(SCHEME) {
+        return serialport::available_ports()?
+            .into_iter()
+            .find(|info| usb_name(info).as_deref() == Some(name))
+            .map(|info| PathBuf::from(info.port_name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not plugged in", name),
+                )
+            });
+    }
+    fs::canonicalize(name)
+}
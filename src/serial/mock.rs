@@ -0,0 +1,459 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::transport::{timed_out, PtyTransport, Transport};
+
+/// Prefix of the built-in virtual ports.
+pub const SCHEME: &str = "virtual:";
+
+/// The virtual ports offered in the port list.
+pub const PORTS: [&str; 3] = ["virtual:loopback", "virtual:echo", "virtual:pattern"];
+
+const ECHO_DELAY: Duration = Duration::from_millis(100);
+const PATTERN_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Opens a virtual port:
+/// - `virtual:loopback` hands back whatever is written to it,
+/// - `virtual:echo[:ms]` does the same after a delay, like a device echoing what it is sent,
+/// - `virtual:pattern[:ms]` prints a numbered test line at a fixed interval and ignores input.
+pub fn open(name: &str, timeout: Duration) -> io::Result<Box<dyn Transport>> {
+    let spec = name.strip_prefix(SCHEME).unwrap_or(name);
+    let (kind, millis) = match spec.split_once(':') {
+        Some((kind, millis)) => {
+            let millis = millis.parse::<u64>().map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: not a number of milliseconds", millis),
+                )
+            })?;
+            (kind, Some(Duration::from_millis(millis)))
+        }
+        None => (spec, None),
+    };
+    match kind {
+        "loopback" => Ok(Box::new(Echo::new(Duration::ZERO, timeout))),
+        "echo" => Ok(Box::new(Echo::new(millis.unwrap_or(ECHO_DELAY), timeout))),
+        "pattern" => Ok(Box::new(Pattern::new(
+            millis.unwrap_or(PATTERN_INTERVAL),
+            timeout,
+        ))),
+        _ => Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("no virtual port called {}", name),
+        )),
+    }
+}
+
+/// Runs the virtual port `name` on the far side of a new pty, so programs (and determ itself)
+/// can open it like a real device. Returns the path to open.
+pub fn serve_on_pty(name: &str) -> io::Result<(PathBuf, JoinHandle<()>)> {
+    let device = open(name, Duration::from_millis(5))?;
+    serve(device, Arc::new(AtomicBool::new(false)))
+}
+
+/// Runs `device` on the far side of a new pty until `stop` is set or either side breaks.
+fn serve(
+    mut device: Box<dyn Transport>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<(PathBuf, JoinHandle<()>)> {
+    let mut pty = PtyTransport::open(Duration::from_millis(5))?;
+    let path = pty.path().to_owned();
+    let handle = thread::spawn(move || {
+        let mut buf = [0_u8; 1024];
+        while !stop.load(Ordering::Relaxed)
+            && pump(&mut pty, &mut *device, &mut buf)
+            && pump(&mut *device, &mut pty, &mut buf)
+        {}
+    });
+    Ok((path, handle))
+}
+
+/// Moves whatever `from` has to `to`. Returns `false` once either side is broken.
+fn pump(from: &mut dyn Transport, to: &mut dyn Transport, buf: &mut [u8]) -> bool {
+    let mut rest = match from.read(buf) {
+        Ok(read) => &buf[..read],
+        Err(err) => return err.kind() == ErrorKind::TimedOut,
+    };
+    while !rest.is_empty() {
+        match to.write(rest) {
+            Ok(written) => rest = &rest[written..],
+            // nobody is draining the pty right now
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Loopback, or an echoing device when there is a delay.
+struct Echo {
+    delay: Duration,
+    timeout: Duration,
+    /// Bytes written and when they are due back.
+    queued: VecDeque<(Instant, u8)>,
+}
+
+impl Echo {
+    fn new(delay: Duration, timeout: Duration) -> Echo {
+        Echo {
+            delay,
+            timeout,
+            queued: VecDeque::new(),
+        }
+    }
+}
+
+impl Read for Echo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let due = match self.queued.front() {
+            Some(&(due, _)) => due,
+            None => {
+                thread::sleep(self.timeout);
+                return Err(timed_out());
+            }
+        };
+        if due > deadline {
+            thread::sleep(self.timeout);
+            return Err(timed_out());
+        }
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        let now = Instant::now();
+        let mut read = 0;
+        while read < buf.len() {
+            match self.queued.front() {
+                Some(&(due, byte)) if due <= now => {
+                    buf[read] = byte;
+                    read += 1;
+                    self.queued.pop_front();
+                }
+                _ => break,
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Echo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let due = Instant::now() + self.delay;
+        self.queued.extend(buf.iter().map(|&byte| (due, byte)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Echo {
+    fn write_data_terminal_ready(&mut self, _level: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Prints `pattern 000001 ABC...789` lines, for checking that nothing gets lost or reordered.
+struct Pattern {
+    interval: Duration,
+    timeout: Duration,
+    next_at: Instant,
+    count: u64,
+    pending: Vec<u8>,
+}
+
+impl Pattern {
+    fn new(interval: Duration, timeout: Duration) -> Pattern {
+        Pattern {
+            interval,
+            timeout,
+            next_at: Instant::now(),
+            count: 0,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Read for Pattern {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let wait = self.next_at.saturating_duration_since(Instant::now());
+            if wait > self.timeout {
+                thread::sleep(self.timeout);
+                return Err(timed_out());
+            }
+            thread::sleep(wait);
+            self.count += 1;
+            // nobody reading for a while doesn't make for a burst of lines afterwards
+            self.next_at = (self.next_at + self.interval).max(Instant::now());
+            self.pending = format!(
+                "pattern {:06} ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789\r\n",
+                self.count
+            )
+            .into_bytes();
+        }
+        let read = buf.len().min(self.pending.len());
+        buf[..read].copy_from_slice(&self.pending[..read]);
+        self.pending.drain(..read);
+        Ok(read)
+    }
+}
+
+impl Write for Pattern {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Pattern {}
+
+/// Devices on ptys for tests, so they go through a tty like the devices determ talks to.
+#[cfg(test)]
+pub mod harness {
+    use std::{
+        fs,
+        io::{self, ErrorKind, Read},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use crate::serial::{
+        sim::{self, SimTransport},
+        transport::{self, PtyTransport, Transport},
+    };
+
+    /// How long reads wait in tests.
+    pub const TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// A device served on a pty until it is dropped.
+    pub struct PtyDevice {
+        path: PathBuf,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl PtyDevice {
+        /// Serves one of the virtual ports, like `--mock`.
+        pub fn mock(name: &str) -> io::Result<PtyDevice> {
+            let stop = Arc::new(AtomicBool::new(false));
+            let (path, thread) = super::serve(super::open(name, TIMEOUT)?, stop.clone())?;
+            Ok(PtyDevice {
+                path,
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        /// Runs a device simulated from a rules file, which answers as long as it is read.
+        pub fn simulate(rules: &Path) -> io::Result<PtyDevice> {
+            let name = format!("{}{}", sim::SCHEME, rules.display());
+            let mut sim = SimTransport::open(&name, TIMEOUT)?;
+            let path = sim.path().to_owned();
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = thread::spawn({
+                let stop = stop.clone();
+                move || {
+                    let mut buf = [0_u8; 1024];
+                    while !stop.load(Ordering::Relaxed) {
+                        if let Err(err) = sim.read(&mut buf) {
+                            if err.kind() != ErrorKind::TimedOut {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+            Ok(PtyDevice {
+                path,
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        /// Where programs open the device.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Opens the device the way the serial thread opens a port.
+        pub fn open(&self) -> io::Result<Box<dyn Transport>> {
+            transport::open(&self.path.display().to_string(), TIMEOUT)
+        }
+    }
+
+    impl Drop for PtyDevice {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// Both ends of a new pty: the master, and the slave opened like a serial device.
+    pub fn pty_pair() -> io::Result<(PtyTransport, Box<dyn Transport>)> {
+        let master = PtyTransport::open(TIMEOUT)?;
+        let slave = transport::open(&master.path().display().to_string(), TIMEOUT)?;
+        Ok((master, slave))
+    }
+
+    /// Reads from `port` until `done` is happy with everything read so far, or `timeout`.
+    pub fn read_until(
+        port: &mut dyn Transport,
+        timeout: Duration,
+        done: impl Fn(&[u8]) -> bool,
+    ) -> Vec<u8> {
+        let deadline = Instant::now() + timeout;
+        let mut received = Vec::new();
+        let mut buf = [0_u8; 1024];
+        while !done(&received) && Instant::now() < deadline {
+            match port.read(&mut buf) {
+                Ok(read) => received.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                Err(err) => panic!("reading failed: {}", err),
+            }
+        }
+        received
+    }
+
+    /// A directory of its own for a test, removed again when dropped.
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            let path =
+                std::env::temp_dir().join(format!("determ-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).expect("creating a scratch directory");
+            TempDir(path)
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.0
+        }
+
+        /// Writes `contents` to `name` in the directory and returns its path.
+        pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, contents).expect("writing a scratch file");
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Waits for a command to write a pid into `path`.
+    pub fn read_pid(path: &Path) -> String {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let pid = fs::read_to_string(path).unwrap_or_default().trim().to_owned();
+            if !pid.is_empty() || Instant::now() >= deadline {
+                return pid;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Whether `pid` is still around, and not only waiting to be reaped.
+    pub fn running(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| !stat.rsplit(')').next().unwrap_or("").trim_start().starts_with('Z'))
+    }
+
+    /// Waits a moment for `pid` to be gone.
+    pub fn stops(pid: &str) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while running(pid) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use super::harness::{read_until, PtyDevice};
+
+    #[test]
+    fn loopback_hands_back_what_is_written() {
+        let device = PtyDevice::mock("virtual:loopback").unwrap();
+        let mut port = device.open().unwrap();
+        port.write_all(b"hello\r\n").unwrap();
+        let received = read_until(&mut *port, Duration::from_secs(2), |received| {
+            received.ends_with(b"\r\n")
+        });
+        assert_eq!(received, b"hello\r\n");
+    }
+
+    #[test]
+    fn echo_answers_after_its_delay() {
+        let device = PtyDevice::mock("virtual:echo:200").unwrap();
+        let mut port = device.open().unwrap();
+        port.write_all(b"ping\n").unwrap();
+        let early = read_until(&mut *port, Duration::from_millis(100), |received| {
+            !received.is_empty()
+        });
+        assert!(early.is_empty());
+        let received = read_until(&mut *port, Duration::from_secs(2), |received| {
+            received.ends_with(b"\n")
+        });
+        assert_eq!(received, b"ping\n");
+    }
+
+    #[test]
+    fn pattern_lines_arrive_in_order() {
+        let device = PtyDevice::mock("virtual:pattern:20").unwrap();
+        let mut port = device.open().unwrap();
+        let received = read_until(&mut *port, Duration::from_secs(2), |received| {
+            received.iter().filter(|&&byte| byte == b'\n').count() >= 3
+        });
+        let received = String::from_utf8(received).unwrap();
+        let numbers = received
+            .lines()
+            .take(3)
+            .map(|line| line.split(' ').nth(1).unwrap().parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers.len(), 3);
+        assert!(numbers.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    }
+}
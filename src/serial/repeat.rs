@@ -0,0 +1,216 @@
+use std::time::{Duration, Instant};
+
+/// Data sent to a port over and over at a fixed interval.
+struct Schedule {
+    info: ScheduleInfo,
+    data: Vec<u8>,
+    next_at: Instant,
+}
+
+/// A schedule as the UI lists it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScheduleInfo {
+    pub id: u64,
+    pub port: String,
+    /// The data as it was entered, escapes and all.
+    pub text: String,
+    pub every: Duration,
+    /// How many times to send before the schedule ends, forever when `None`.
+    pub count: Option<u64>,
+    pub sent: u64,
+    pub paused: bool,
+}
+
+pub enum RepeatCommand {
+    /// Send data to the current port every so often, optionally only so many times.
+    Add(String, Duration, Option<u64>),
+    Pause(u64, bool),
+    Remove(u64),
+}
+
+/// The schedules of all ports. They are kept while their port is paused and skip the sends that
+/// fall due in the meantime.
+#[derive(Default)]
+pub struct Schedules {
+    list: Vec<Schedule>,
+    next_id: u64,
+}
+
+impl Schedules {
+    /// Applies `command`, with `port_name` being the current port. Returns a note for the port
+    /// when the command was refused.
+    pub fn apply(&mut self, command: RepeatCommand, port_name: &str) -> Option<String> {
+        match command {
+            RepeatCommand::Add(text, every, count) => {
+                let data = match unescape(&text) {
+                    Ok(data) if !data.is_empty() => data,
+                    Ok(_) => return Some("── nothing to repeat ──".to_owned()),
+                    Err(err) => return Some(format!("── {} ──", err)),
+                };
+                if every.is_zero() {
+                    return Some("── a repeat needs an interval ──".to_owned());
+                }
+                self.next_id += 1;
+                self.list.push(Schedule {
+                    info: ScheduleInfo {
+                        id: self.next_id,
+                        port: port_name.to_owned(),
+                        text,
+                        every,
+                        count,
+                        sent: 0,
+                        paused: false,
+                    },
+                    data,
+                    next_at: Instant::now(),
+                });
+            }
+            RepeatCommand::Pause(id, paused) => {
+                if let Some(schedule) = self.list.iter_mut().find(|s| s.info.id == id) {
+                    schedule.info.paused = paused;
+                    // resuming starts a fresh interval rather than sending what was missed
+                    schedule.next_at = Instant::now();
+                }
+            }
+            RepeatCommand::Remove(id) => self.list.retain(|schedule| schedule.info.id != id),
+        }
+        None
+    }
+
+    /// When the serial thread has to look at the schedules next.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.list
+            .iter()
+            .filter(|schedule| !schedule.info.paused)
+            .map(|schedule| schedule.next_at)
+            .min()
+    }
+
+    /// Takes the data that is due now, by port. Schedules that reached their count are dropped.
+    pub fn take_due(&mut self, is_open: impl Fn(&str) -> bool) -> Vec<(String, Vec<u8>)> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for schedule in self.list.iter_mut() {
+            if schedule.info.paused || schedule.next_at > now {
+                continue;
+            }
+            // later than a whole interval, the missed sends are skipped
+            schedule.next_at += schedule.info.every;
+            if schedule.next_at <= now {
+                schedule.next_at = now + schedule.info.every;
+            }
+            if is_open(&schedule.info.port) {
+                schedule.info.sent += 1;
+                due.push((schedule.info.port.clone(), schedule.data.clone()));
+            }
+        }
+        self.list.retain(|schedule| {
+            schedule
+                .info
+                .count
+                .is_none_or(|count| schedule.info.sent < count)
+        });
+        due
+    }
+
+    pub fn info(&self) -> Vec<ScheduleInfo> {
+        self.list
+            .iter()
+            .map(|schedule| schedule.info.clone())
+            .collect()
+    }
+}
+
+/// Turns `\r`, `\n`, `\t`, `\\` and `\xNN` into the bytes they stand for.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0_u8; 4];
+            data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => data.push(b'\r'),
+            Some('n') => data.push(b'\n'),
+            Some('t') => data.push(b'\t'),
+            Some('\\') => data.push(b'\\'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("\\x{}: expected two hex digits", hex))?;
+                data.push(byte);
+            }
+            Some(other) => return Err(format!("\\{}: unknown escape", other)),
+            None => return Err("a lone \\ at the end".to_owned()),
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedules(every: Duration, count: Option<u64>) -> Schedules {
+        let mut schedules = Schedules::default();
+        let add = RepeatCommand::Add("AT\\r\\n".to_owned(), every, count);
+        assert_eq!(schedules.apply(add, "virtual:loopback"), None);
+        schedules
+    }
+
+    #[test]
+    fn unescape_takes_escapes_and_any_text() {
+        assert_eq!(unescape("AT\\r\\n").unwrap(), b"AT\r\n");
+        assert_eq!(unescape("\\x1b[0m\\t\\\\").unwrap(), b"\x1b[0m\t\\");
+        assert_eq!(unescape("grüße").unwrap(), "grüße".as_bytes());
+    }
+
+    #[test]
+    fn unescape_refuses_broken_escapes() {
+        assert!(unescape("\\x4").is_err());
+        assert!(unescape("\\x4g").is_err());
+        assert!(unescape("\\xé1").is_err());
+        assert!(unescape("AT\\").is_err());
+        assert!(unescape("\\q").is_err());
+    }
+
+    #[test]
+    fn missed_sends_are_skipped() {
+        let every = Duration::from_millis(100);
+        let mut schedules = schedules(every, None);
+        schedules.list[0].next_at -= every * 5;
+        let due = schedules.take_due(|_| true);
+        assert_eq!(due, vec![("virtual:loopback".to_owned(), b"AT\r\n".to_vec())]);
+        assert!(schedules.list[0].next_at > Instant::now());
+        assert!(schedules.take_due(|_| true).is_empty());
+        assert_eq!(schedules.info()[0].sent, 1);
+    }
+
+    #[test]
+    fn a_schedule_ends_after_its_count() {
+        let every = Duration::from_millis(100);
+        let mut schedules = schedules(every, Some(2));
+        for _ in 0..2 {
+            schedules.list[0].next_at -= every;
+            assert_eq!(schedules.take_due(|_| true).len(), 1);
+        }
+        assert!(schedules.info().is_empty());
+        assert_eq!(schedules.next_due(), None);
+    }
+
+    #[test]
+    fn nothing_is_sent_to_a_port_that_is_not_open() {
+        let every = Duration::from_millis(100);
+        let mut schedules = schedules(every, Some(1));
+        assert!(schedules.take_due(|_| false).is_empty());
+        // the send is skipped rather than saved up, and doesn't count
+        assert!(schedules.list[0].next_at > Instant::now());
+        assert_eq!(schedules.info()[0].sent, 0);
+        schedules.list[0].next_at -= every;
+        assert_eq!(schedules.take_due(|_| true).len(), 1);
+    }
+}
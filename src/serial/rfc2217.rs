@@ -0,0 +1,675 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::io::{AsRawFd, RawFd},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, Result, SerialPort, StopBits,
+};
+
+use super::{settings::PortSettings, transport::timed_out};
+
+/// Prefix of port names that are opened on a serial server rather than locally.
+pub const SCHEME: &str = "rfc2217://";
+/// How long a server gets to accept the connection, the serial thread waits meanwhile.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Telnet
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT: u8 = 44;
+
+// COM-PORT-OPTION commands, the server answers with the same code plus 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+// SET-CONTROL values
+const FLOW_NONE: u8 = 1;
+const FLOW_XON_XOFF: u8 = 2;
+const FLOW_HARDWARE: u8 = 3;
+const BREAK_ON: u8 = 5;
+const BREAK_OFF: u8 = 6;
+const DTR_ON: u8 = 8;
+const DTR_OFF: u8 = 9;
+const RTS_ON: u8 = 11;
+const RTS_OFF: u8 = 12;
+
+// NOTIFY-MODEMSTATE bits
+const CTS: u8 = 0x10;
+const DSR: u8 = 0x20;
+const RI: u8 = 0x40;
+const CD: u8 = 0x80;
+
+/// Connects to `addr`, `host:port`, trying each address it resolves to for `CONNECT_TIMEOUT`.
+pub fn connect(addr: &str) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} resolves to no address", addr),
+        )
+    }))
+}
+
+#[derive(Clone, Copy)]
+enum Telnet {
+    Data,
+    Iac,
+    /// Got IAC and one of WILL/WONT/DO/DONT, waiting for the option.
+    Option(u8),
+    Sub,
+    SubIac,
+}
+
+/// A port on a serial server, driven over Telnet with the RFC 2217 COM port control option.
+pub struct Rfc2217Port {
+    stream: TcpStream,
+    name: String,
+    timeout: Duration,
+    telnet: Telnet,
+    subnegotiation: Vec<u8>,
+    /// Port data received and not read yet.
+    pending: Mutex<Vec<u8>>,
+    baud_rate: u32,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+    modem_state: u8,
+    /// The server said it won't do COM-PORT-OPTION, it is a plain Telnet connection then.
+    refused: bool,
+}
+
+impl Rfc2217Port {
+    /// Connects to `name`, an `rfc2217://host:port` address, and sets the port up with
+    /// `settings`.
+    pub fn open(name: &str, settings: &PortSettings, timeout: Duration) -> Result<Rfc2217Port> {
+        let addr = name.strip_prefix(SCHEME).unwrap_or(name).trim_end_matches('/');
+        let stream = connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut port = Rfc2217Port {
+            stream,
+            name: name.to_owned(),
+            timeout,
+            telnet: Telnet::Data,
+            subnegotiation: Vec::new(),
+            pending: Mutex::new(Vec::new()),
+            baud_rate: settings.baud_rate,
+            data_bits: settings.data_bits,
+            parity: settings.parity,
+            stop_bits: settings.stop_bits,
+            flow_control: settings.flow_control,
+            modem_state: 0,
+            refused: false,
+        };
+        port.send_raw(&[
+            IAC, WILL, COM_PORT, IAC, WILL, BINARY, IAC, DO, BINARY, IAC, WILL, SGA, IAC, DO, SGA,
+        ])?;
+        port.set_baud_rate(settings.baud_rate)?;
+        port.set_data_bits(settings.data_bits)?;
+        port.set_parity(settings.parity)?;
+        port.set_stop_bits(settings.stop_bits)?;
+        port.set_flow_control(settings.flow_control)?;
+        port.command(SET_MODEMSTATE_MASK, &[CTS | DSR | RI | CD])?;
+        Ok(port)
+    }
+
+    fn send_raw(&self, bytes: &[u8]) -> io::Result<()> {
+        (&self.stream).write_all(bytes)
+    }
+
+    fn command(&self, command: u8, value: &[u8]) -> Result<()> {
+        if self.refused {
+            return Err(Error::new(
+                ErrorKind::Unknown,
+                "the serial server does not support RFC 2217 port control",
+            ));
+        }
+        let mut message = vec![IAC, SB, COM_PORT, command];
+        for &byte in value {
+            message.push(byte);
+            if byte == IAC {
+                message.push(IAC);
+            }
+        }
+        message.extend_from_slice(&[IAC, SE]);
+        Ok(self.send_raw(&message)?)
+    }
+
+    fn control(&self, value: u8) -> Result<()> {
+        self.command(SET_CONTROL, &[value])
+    }
+
+    /// Splits what came off the socket into port data and Telnet commands.
+    fn feed(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            self.telnet = match (self.telnet, byte) {
+                (Telnet::Data, IAC) => Telnet::Iac,
+                (Telnet::Data, _) => {
+                    data.push(byte);
+                    Telnet::Data
+                }
+                (Telnet::Iac, IAC) => {
+                    data.push(IAC);
+                    Telnet::Data
+                }
+                (Telnet::Iac, WILL | WONT | DO | DONT) => Telnet::Option(byte),
+                (Telnet::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    Telnet::Sub
+                }
+                // NOP, GA and the like
+                (Telnet::Iac, _) => Telnet::Data,
+                (Telnet::Option(verb), option) => {
+                    self.negotiate(verb, option)?;
+                    Telnet::Data
+                }
+                (Telnet::Sub, IAC) => Telnet::SubIac,
+                (Telnet::Sub, _) => {
+                    self.subnegotiation.push(byte);
+                    Telnet::Sub
+                }
+                (Telnet::SubIac, SE) => {
+                    self.on_subnegotiation();
+                    Telnet::Data
+                }
+                (Telnet::SubIac, _) => {
+                    self.subnegotiation.push(byte);
+                    Telnet::Sub
+                }
+            };
+        }
+        if !data.is_empty() {
+            self.pending
+                .get_mut()
+                .unwrap_or_else(|err| err.into_inner())
+                .extend_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8) -> io::Result<()> {
+        let supported = matches!(option, BINARY | SGA | COM_PORT);
+        match verb {
+            // the ones we support were asked for when connecting, this is the answer
+            WILL if !supported => self.send_raw(&[IAC, DONT, option]),
+            DO if !supported => self.send_raw(&[IAC, WONT, option]),
+            WONT | DONT if option == COM_PORT => {
+                self.refused = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_subnegotiation(&mut self) {
+        let [COM_PORT, command, value @ ..] = self.subnegotiation.as_slice() else {
+            return;
+        };
+        // the server answers a setting with the value it actually applied
+        match (command.wrapping_sub(SERVER_OFFSET), value) {
+            (SET_BAUDRATE, &[a, b, c, d]) => {
+                let baud_rate = u32::from_be_bytes([a, b, c, d]);
+                if baud_rate != 0 {
+                    self.baud_rate = baud_rate;
+                }
+            }
+            (SET_DATASIZE, &[size]) => {
+                self.data_bits = match size {
+                    5 => DataBits::Five,
+                    6 => DataBits::Six,
+                    7 => DataBits::Seven,
+                    8 => DataBits::Eight,
+                    _ => self.data_bits,
+                }
+            }
+            (SET_PARITY, &[parity]) => {
+                self.parity = match parity {
+                    1 => Parity::None,
+                    2 => Parity::Odd,
+                    3 => Parity::Even,
+                    _ => self.parity,
+                }
+            }
+            (SET_STOPSIZE, &[stop_bits]) => {
+                self.stop_bits = match stop_bits {
+                    1 => StopBits::One,
+                    2 => StopBits::Two,
+                    _ => self.stop_bits,
+                }
+            }
+            (NOTIFY_MODEMSTATE, &[state]) => self.modem_state = state,
+            _ => {}
+        }
+    }
+}
+
+impl AsRawFd for Rfc2217Port {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl Read for Rfc2217Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let mut raw = [0_u8; 1024];
+        loop {
+            let pending = self.pending.get_mut().unwrap_or_else(|err| err.into_inner());
+            if !pending.is_empty() {
+                let read = buf.len().min(pending.len());
+                buf[..read].copy_from_slice(&pending[..read]);
+                pending.drain(..read);
+                return Ok(read);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            match self.stream.read(&mut raw) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "the serial server closed the connection",
+                    ))
+                }
+                Ok(read) => self.feed(&raw[..read])?,
+                Err(err)
+                    if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+                {
+                    return Err(timed_out());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Write for Rfc2217Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            escaped.push(byte);
+            if byte == IAC {
+                escaped.push(IAC);
+            }
+        }
+        // all or nothing, an escape sequence cut in half can't be resumed by the caller
+        self.stream.write_all(&escaped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl SerialPort for Rfc2217Port {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> Result<DataBits> {
+        Ok(self.data_bits)
+    }
+
+    fn flow_control(&self) -> Result<FlowControl> {
+        Ok(self.flow_control)
+    }
+
+    fn parity(&self) -> Result<Parity> {
+        Ok(self.parity)
+    }
+
+    fn stop_bits(&self) -> Result<StopBits> {
+        Ok(self.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.command(SET_BAUDRATE, &baud_rate.to_be_bytes())?;
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        self.command(
+            SET_DATASIZE,
+            &[match data_bits {
+                DataBits::Five => 5,
+                DataBits::Six => 6,
+                DataBits::Seven => 7,
+                DataBits::Eight => 8,
+            }],
+        )?;
+        self.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
+        self.control(match flow_control {
+            FlowControl::None => FLOW_NONE,
+            FlowControl::Software => FLOW_XON_XOFF,
+            FlowControl::Hardware => FLOW_HARDWARE,
+        })?;
+        self.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> Result<()> {
+        self.command(
+            SET_PARITY,
+            &[match parity {
+                Parity::None => 1,
+                Parity::Odd => 2,
+                Parity::Even => 3,
+            }],
+        )?;
+        self.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
+        self.command(
+            SET_STOPSIZE,
+            &[match stop_bits {
+                StopBits::One => 1,
+                StopBits::Two => 2,
+            }],
+        )?;
+        self.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<()> {
+        self.control(if level { RTS_ON } else { RTS_OFF })
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<()> {
+        self.control(if level { DTR_ON } else { DTR_OFF })
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        Ok(self.modem_state & CTS != 0)
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        Ok(self.modem_state & DSR != 0)
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        Ok(self.modem_state & RI != 0)
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        Ok(self.modem_state & CD != 0)
+    }
+
+    fn bytes_to_read(&self) -> Result<u32> {
+        let pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(pending.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
+        let purge = match buffer_to_clear {
+            ClearBuffer::Input => 1,
+            ClearBuffer::Output => 2,
+            ClearBuffer::All => 3,
+        };
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            self.pending
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clear();
+        }
+        self.command(PURGE_DATA, &[purge])
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
+        Err(Error::new(
+            ErrorKind::Unknown,
+            "an RFC 2217 connection can't be cloned",
+        ))
+    }
+
+    fn set_break(&self) -> Result<()> {
+        self.control(BREAK_ON)
+    }
+
+    fn clear_break(&self) -> Result<()> {
+        self.control(BREAK_OFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+    use crate::serial::{mock, transport, utils::parse_flow};
+
+    /// What the stand-in server was told to set, in order.
+    type Commands = Arc<Mutex<Vec<(u8, Vec<u8>)>>>;
+
+    /// A stand-in for a serial server: it answers COM-PORT-OPTION like a real one, with baud
+    /// rates above 57600 turned down, and puts the data through one of the virtual ports.
+    fn serve(device: &str) -> (String, Commands) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let name = format!("{}{}", SCHEME, listener.local_addr().unwrap());
+        let commands = Commands::default();
+        let mut device = mock::open(device, Duration::from_millis(5)).unwrap();
+        let seen = commands.clone();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(5)))
+                .unwrap();
+            let mut telnet = Telnet::Data;
+            let mut sub = Vec::new();
+            let mut buf = [0_u8; 1024];
+            loop {
+                let read = match stream.read(&mut buf) {
+                    Ok(0) => return,
+                    Ok(read) => read,
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        0
+                    }
+                    Err(_) => return,
+                };
+                let mut data = Vec::new();
+                let mut answers = Vec::new();
+                for &byte in &buf[..read] {
+                    telnet = match (telnet, byte) {
+                        (Telnet::Data, IAC) => Telnet::Iac,
+                        (Telnet::Data, _) | (Telnet::Iac, IAC) => {
+                            data.push(byte);
+                            Telnet::Data
+                        }
+                        (Telnet::Iac, WILL | WONT | DO | DONT) => Telnet::Option(byte),
+                        (Telnet::Iac, SB) => {
+                            sub.clear();
+                            Telnet::Sub
+                        }
+                        (Telnet::Iac, _) => Telnet::Data,
+                        (Telnet::Option(WILL), COM_PORT) => {
+                            answers.extend_from_slice(&[IAC, DO, COM_PORT]);
+                            Telnet::Data
+                        }
+                        (Telnet::Option(_), _) => Telnet::Data,
+                        (Telnet::Sub, IAC) => Telnet::SubIac,
+                        (Telnet::Sub, _) | (Telnet::SubIac, IAC) => {
+                            sub.push(byte);
+                            Telnet::Sub
+                        }
+                        (Telnet::SubIac, _) => {
+                            if let [COM_PORT, command, value @ ..] = sub.as_slice() {
+                                let mut value = value.to_vec();
+                                if *command == SET_BAUDRATE {
+                                    let baud_rate =
+                                        u32::from_be_bytes(value[..4].try_into().unwrap());
+                                    value = baud_rate.min(57_600).to_be_bytes().to_vec();
+                                }
+                                seen.lock().unwrap().push((*command, value.clone()));
+                                answers.extend_from_slice(&[
+                                    IAC,
+                                    SB,
+                                    COM_PORT,
+                                    command + SERVER_OFFSET,
+                                ]);
+                                answers.extend_from_slice(&value);
+                                answers.extend_from_slice(&[IAC, SE]);
+                            }
+                            Telnet::Data
+                        }
+                    };
+                }
+                if device.write_all(&data).is_err() || stream.write_all(&answers).is_err() {
+                    return;
+                }
+                if let Ok(read) = device.read(&mut buf) {
+                    let mut escaped = Vec::new();
+                    for &byte in &buf[..read] {
+                        escaped.push(byte);
+                        if byte == IAC {
+                            escaped.push(IAC);
+                        }
+                    }
+                    if stream.write_all(&escaped).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        (name, commands)
+    }
+
+    fn read_until(port: &mut dyn Read, len: usize) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut received = Vec::new();
+        let mut buf = [0_u8; 64];
+        while received.len() < len && Instant::now() < deadline {
+            match port.read(&mut buf) {
+                Ok(read) => received.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => panic!("reading failed: {}", err),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn negotiates_the_port_and_takes_the_servers_word() {
+        let (name, commands) = serve("virtual:loopback");
+        let settings = PortSettings {
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+            ..PortSettings::default()
+        };
+        let mut port = Rfc2217Port::open(&name, &settings, Duration::from_millis(20)).unwrap();
+        // the settings went out before the data, so they are answered by the time it is back
+        port.write_all(b"x").unwrap();
+        assert_eq!(read_until(&mut port, 1), b"x");
+        // each asked for once, without the usual framing first
+        assert_eq!(
+            commands.lock().unwrap()[..],
+            [
+                (SET_BAUDRATE, 57_600_u32.to_be_bytes().to_vec()),
+                (SET_DATASIZE, vec![7]),
+                (SET_PARITY, vec![3]),
+                (SET_STOPSIZE, vec![2]),
+                (SET_CONTROL, vec![FLOW_HARDWARE]),
+                (SET_MODEMSTATE_MASK, vec![CTS | DSR | RI | CD]),
+            ]
+        );
+        assert_eq!(port.parity().unwrap(), Parity::Even);
+        assert_eq!(port.baud_rate().unwrap(), 57_600);
+    }
+
+    #[test]
+    fn data_round_trips_with_iac_escaped() {
+        let (name, _) = serve("virtual:loopback");
+        let settings = PortSettings {
+            baud_rate: 9600,
+            ..PortSettings::default()
+        };
+        let mut port = Rfc2217Port::open(&name, &settings, Duration::from_millis(20)).unwrap();
+        let data = (0..=255).collect::<Vec<u8>>();
+        port.write_all(&data).unwrap();
+        assert_eq!(read_until(&mut port, data.len()), data);
+    }
+
+    #[test]
+    fn control_line_sequences_go_to_the_server() {
+        let (name, commands) = serve("virtual:loopback");
+        let mut port = transport::open(&name, Duration::from_millis(20)).unwrap();
+        parse_flow(&mut port, "d1:r1:s10:d0:r0".to_owned()).unwrap();
+        port.set_break().unwrap();
+        port.clear_break().unwrap();
+        port.write_all(b"x").unwrap();
+        assert_eq!(read_until(&mut *port, 1), b"x");
+        let controls = commands
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(command, _)| *command == SET_CONTROL)
+            .map(|(_, value)| value[0])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            controls,
+            [FLOW_NONE, DTR_ON, RTS_ON, DTR_OFF, RTS_OFF, BREAK_ON, BREAK_OFF]
+        );
+    }
+}
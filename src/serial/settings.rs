@@ -0,0 +1,99 @@
+use std::{fmt, str::FromStr};
+
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+/// How received bytes are turned into text, and text into bytes to send.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// Every byte is a character, for devices that print 8-bit code pages.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&byte| char::from(byte)).collect(),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Encoding, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(format!("{}: expected utf-8 or latin1", name)),
+        }
+    }
+}
+
+/// What a port is opened with. Only local serial ports and those on RFC 2217 servers use the
+/// line settings.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PortSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub encoding: Encoding,
+}
+
+impl Default for PortSettings {
+    fn default() -> PortSettings {
+        PortSettings {
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            encoding: Encoding::Utf8,
+        }
+    }
+}
+
+/// Shown as the usual `115200 8N1`.
+impl fmt::Display for PortSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(f, "{} {}{}{}", self.baud_rate, data_bits, parity, stop_bits)?;
+        match self.flow_control {
+            FlowControl::None => {}
+            FlowControl::Software => write!(f, " XON/XOFF")?,
+            FlowControl::Hardware => write!(f, " RTS/CTS")?,
+        }
+        if self.encoding == Encoding::Latin1 {
+            write!(f, " latin1")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+/// Output a client may fall behind by before it gets disconnected.
+const MAX_BACKLOG: usize = 64 * 1024;
+
+/// What the UI is told about a shared port.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShareInfo {
+    pub addr: SocketAddr,
+    pub read_only: bool,
+    pub clients: Vec<SocketAddr>,
+}
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    /// Output the client has not taken yet.
+    backlog: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct SharePoll {
+    /// Data written by the clients, for the port.
+    pub data: Vec<u8>,
+    pub connected: Vec<SocketAddr>,
+    pub disconnected: Vec<SocketAddr>,
+}
+
+/// An open port exposed on a TCP listener: whatever the port receives goes to every client and,
+/// unless read-only, whatever the clients write goes to the port.
+pub struct Share {
+    listener: TcpListener,
+    read_only: bool,
+    clients: Vec<Client>,
+}
+
+impl Share {
+    pub fn bind(addr: &str, read_only: bool) -> io::Result<Share> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Share {
+            listener,
+            read_only,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn info(&self) -> ShareInfo {
+        ShareInfo {
+            addr: self
+                .listener
+                .local_addr()
+                .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0))),
+            read_only: self.read_only,
+            clients: self.clients.iter().map(|client| client.addr).collect(),
+        }
+    }
+
+    pub fn has_clients(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Accepts new clients and collects what the connected ones wrote.
+    pub fn poll(&mut self) -> SharePoll {
+        let mut poll = SharePoll::default();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        let _ = stream.set_nodelay(true);
+                        poll.connected.push(addr);
+                        self.clients.push(Client {
+                            stream,
+                            addr,
+                            backlog: Vec::new(),
+                        });
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                // WouldBlock means nobody else is waiting, anything else is retried next time
+                Err(_) => break,
+            }
+        }
+
+        let mut buf = [0_u8; 1024];
+        let read_only = self.read_only;
+        self.clients.retain_mut(|client| loop {
+            match client.stream.read(&mut buf) {
+                Ok(0) => {
+                    poll.disconnected.push(client.addr);
+                    return false;
+                }
+                Ok(read) => {
+                    // a read-only client is still read from, to notice when it goes away
+                    if !read_only {
+                        poll.data.extend_from_slice(&buf[..read]);
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => {
+                    poll.disconnected.push(client.addr);
+                    return false;
+                }
+            }
+        });
+        poll
+    }
+
+    /// Sends `bytes` to every client. Returns the clients dropped for falling too far behind or
+    /// for a broken connection.
+    pub fn broadcast(&mut self, bytes: &[u8]) -> Vec<SocketAddr> {
+        let mut dropped = Vec::new();
+        self.clients.retain_mut(|client| {
+            client.backlog.extend_from_slice(bytes);
+            while !client.backlog.is_empty() {
+                match client.stream.write(&client.backlog) {
+                    Ok(0) => break,
+                    Ok(written) => {
+                        client.backlog.drain(..written);
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        dropped.push(client.addr);
+                        return false;
+                    }
+                }
+            }
+            if client.backlog.len() > MAX_BACKLOG {
+                dropped.push(client.addr);
+                return false;
+            }
+            true
+        });
+        dropped
+    }
+}
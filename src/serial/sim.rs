@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use super::transport::{PtyTransport, Transport};
+
+/// Prefix of port names that run a simulated device from a rules file.
+pub const SCHEME: &str = "sim:";
+
+/// A rules file, for example
+///
+/// ```toml
+/// [[rule]]
+/// on = '^AT\+GMR$'
+/// reply = "version 1.2.3\r\nOK\r\n"
+/// delay_ms = 20
+///
+/// [[rule]]
+/// on = '^echo (.*)$'
+/// reply = "$1\r\n"
+///
+/// [[periodic]]
+/// every_ms = 1000
+/// send = "+HEARTBEAT {n}\r\n"
+/// ```
+#[derive(Deserialize)]
+struct Rules {
+    #[serde(default)]
+    rule: Vec<RuleConfig>,
+    #[serde(default)]
+    periodic: Vec<PeriodicConfig>,
+}
+
+#[derive(Deserialize)]
+struct RuleConfig {
+    /// Matched against every line received, without its line ending.
+    on: String,
+    /// `$1`, `${name}` and so on are replaced by what the regex captured.
+    reply: String,
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct PeriodicConfig {
+    every_ms: u64,
+    /// `{n}` is replaced by how many times the message has been sent.
+    send: String,
+}
+
+struct Rule {
+    on: Regex,
+    reply: String,
+    delay: Duration,
+}
+
+struct Periodic {
+    every: Duration,
+    send: String,
+    next_at: Instant,
+    count: u64,
+}
+
+/// A device simulated on a pty: programs talk to the other end of the pty, what they send shows
+/// up as received data and the simulator's replies as sent data.
+pub struct SimTransport {
+    pty: PtyTransport,
+    rules: Vec<Rule>,
+    periodic: Vec<Periodic>,
+    line: Vec<u8>,
+    /// Replies waiting for their delay, in the order they were triggered.
+    replies: Vec<(Instant, Vec<u8>)>,
+    /// Replies that are due but didn't fit into the pty yet.
+    unsent: Vec<u8>,
+    sent: Vec<u8>,
+    notes: Vec<String>,
+}
+
+impl SimTransport {
+    /// Opens `name`, `sim:<rules file>`.
+    pub fn open(name: &str, timeout: Duration) -> io::Result<SimTransport> {
+        let path = Path::new(name.strip_prefix(SCHEME).unwrap_or(name));
+        let invalid = |err: String| {
+            io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err))
+        };
+        let rules: Rules = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| invalid(err.message().to_owned()))?;
+        let now = Instant::now();
+        let mut pty = PtyTransport::open(timeout)?;
+        let mut notes = pty.take_notes();
+        notes.push(format!(
+            "── simulating {} with {} rules and {} periodic messages ──",
+            path.display(),
+            rules.rule.len(),
+            rules.periodic.len()
+        ));
+        Ok(SimTransport {
+            pty,
+            rules: rules
+                .rule
+                .into_iter()
+                .map(|rule| {
+                    Ok(Rule {
+                        on: Regex::new(&rule.on).map_err(|err| invalid(err.to_string()))?,
+                        reply: rule.reply,
+                        delay: Duration::from_millis(rule.delay_ms),
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+            periodic: rules
+                .periodic
+                .into_iter()
+                .map(|periodic| Periodic {
+                    every: Duration::from_millis(periodic.every_ms.max(1)),
+                    send: periodic.send,
+                    next_at: now + Duration::from_millis(periodic.every_ms.max(1)),
+                    count: 0,
+                })
+                .collect(),
+            line: Vec::new(),
+            replies: Vec::new(),
+            unsent: Vec::new(),
+            sent: Vec::new(),
+            notes,
+        })
+    }
+
+    /// Where the programs talking to the simulated device open it.
+    #[cfg(test)]
+    pub fn path(&self) -> &Path {
+        self.pty.path()
+    }
+
+    fn on_received(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte != b'\n' && byte != b'\r' {
+                self.line.push(byte);
+                continue;
+            }
+            if self.line.is_empty() {
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            if let Some((rule, captures)) = self
+                .rules
+                .iter()
+                .find_map(|rule| rule.on.captures(&line).map(|captures| (rule, captures)))
+            {
+                let mut reply = String::new();
+                captures.expand(&rule.reply, &mut reply);
+                self.replies
+                    .push((Instant::now() + rule.delay, reply.into_bytes()));
+            }
+        }
+    }
+
+    /// Writes the replies and periodic messages that are due, as far as the pty takes them.
+    fn send_due(&mut self) {
+        let now = Instant::now();
+        let due = &mut self.unsent;
+        self.replies.retain(|(at, reply)| {
+            if *at <= now {
+                due.extend_from_slice(reply);
+                false
+            } else {
+                true
+            }
+        });
+        for periodic in self.periodic.iter_mut().filter(|periodic| periodic.next_at <= now) {
+            periodic.count += 1;
+            periodic.next_at = (periodic.next_at + periodic.every).max(now);
+            due.extend_from_slice(
+                periodic
+                    .send
+                    .replace("{n}", &periodic.count.to_string())
+                    .as_bytes(),
+            );
+        }
+        while !self.unsent.is_empty() {
+            match self.pty.write(&self.unsent) {
+                Ok(0) => break,
+                Ok(written) => self.sent.extend(self.unsent.drain(..written)),
+                // nobody is reading the other end right now, the rest goes out later
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) =>
+                {
+                    break
+                }
+                Err(err) => {
+                    self.notes.push(format!("── simulator reply dropped: {} ──", err));
+                    self.unsent.clear();
+                }
+            }
+        }
+    }
+}
+
+impl Read for SimTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.send_due();
+        let read = self.pty.read(buf)?;
+        self.on_received(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl Write for SimTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pty.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pty.flush()
+    }
+}
+
+impl Transport for SimTransport {
+    fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+
+    fn take_sent(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sent)
+    }
+
+    /// Replies are only written while the simulator is read.
+    fn relays(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    os::unix::io::AsRawFd,
+    time::Duration,
+};
+
+use serialport::{FlowControl, SerialPort, StopBits, TTYPort};
+
+use super::{
+    transport::{timed_out, PtyTransport, Transport},
+    utils::{read_line_errors, LineErrors},
+};
+
+/// Prefix of port names that put a sniffing proxy in front of a real port.
+pub const SCHEME: &str = "sniff:";
+
+const BAUD_RATE: u32 = 115_200;
+
+const SPEEDS: [(libc::speed_t, u32); 19] = [
+    (libc::B300, 300),
+    (libc::B600, 600),
+    (libc::B1200, 1200),
+    (libc::B2400, 2400),
+    (libc::B4800, 4800),
+    (libc::B9600, 9600),
+    (libc::B19200, 19200),
+    (libc::B38400, 38400),
+    (libc::B57600, 57600),
+    (libc::B115200, 115_200),
+    (libc::B230400, 230_400),
+    (libc::B460800, 460_800),
+    (libc::B500000, 500_000),
+    (libc::B576000, 576_000),
+    (libc::B921600, 921_600),
+    (libc::B1000000, 1_000_000),
+    (libc::B1500000, 1_500_000),
+    (libc::B2000000, 2_000_000),
+    (libc::B3000000, 3_000_000),
+];
+
+/// Line settings a client made on the pty that can be applied to the real port. Linux ptys always
+/// report 8 data bits without parity, so those can't be followed.
+#[derive(Clone, Copy, PartialEq)]
+struct LineSettings {
+    baud_rate: Option<u32>,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+}
+
+/// A pty for another program, relayed to a real port. What the program sends shows up as sent
+/// data and what the device answers as received data; line settings the program makes on the
+/// pty are copied to the real port.
+pub struct SniffTransport {
+    port: TTYPort,
+    pty: PtyTransport,
+    timeout: Duration,
+    settings: Option<LineSettings>,
+    sent: Vec<u8>,
+    notes: Vec<String>,
+    /// The client stopped reading and device data is being thrown away.
+    dropping: bool,
+}
+
+impl SniffTransport {
+    /// Opens `name`, `sniff:<device>`.
+    pub fn open(name: &str, timeout: Duration) -> io::Result<SniffTransport> {
+        let device = name.strip_prefix(SCHEME).unwrap_or(name);
+        let port = serialport::new(device, BAUD_RATE)
+            .timeout(timeout)
+            .open_native()?;
+        let mut pty = PtyTransport::open(timeout)?;
+        let mut notes = pty.take_notes();
+        notes.push(format!("── relaying to {} ──", device));
+        Ok(SniffTransport {
+            port,
+            pty,
+            timeout,
+            settings: None,
+            sent: Vec::new(),
+            notes,
+            dropping: false,
+        })
+    }
+
+    /// Copies changes the client made to the pty's termios over to the real port.
+    fn mirror_settings(&mut self) {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(self.pty.as_raw_fd(), &mut termios) } != 0 {
+            return;
+        }
+        let speed = unsafe { libc::cfgetospeed(&termios) };
+        let cflag = termios.c_cflag;
+        let settings = LineSettings {
+            baud_rate: SPEEDS
+                .iter()
+                .find(|(constant, _)| *constant == speed)
+                .map(|&(_, baud_rate)| baud_rate),
+            stop_bits: if cflag & libc::CSTOPB != 0 {
+                StopBits::Two
+            } else {
+                StopBits::One
+            },
+            flow_control: if cflag & libc::CRTSCTS != 0 {
+                FlowControl::Hardware
+            } else if termios.c_iflag & libc::IXON != 0 {
+                FlowControl::Software
+            } else {
+                FlowControl::None
+            },
+        };
+        let previous = self.settings.replace(settings);
+        // the pty starts out in raw mode, only what the client changes afterwards counts
+        let Some(previous) = previous else {
+            return;
+        };
+        if previous == settings {
+            return;
+        }
+        let mut applied = Ok(());
+        if settings.baud_rate != previous.baud_rate {
+            if let Some(baud_rate) = settings.baud_rate {
+                applied = applied.and(self.port.set_baud_rate(baud_rate));
+            }
+        }
+        applied = applied
+            .and(self.port.set_stop_bits(settings.stop_bits))
+            .and(self.port.set_flow_control(settings.flow_control));
+        let baud_rate = settings.baud_rate.map_or_else(
+            || "an unusual speed".to_owned(),
+            |baud| format!("{} baud", baud),
+        );
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => "1 stop bit",
+            StopBits::Two => "2 stop bits",
+        };
+        let flow_control = match settings.flow_control {
+            FlowControl::None => "no flow control",
+            FlowControl::Software => "XON/XOFF",
+            FlowControl::Hardware => "RTS/CTS",
+        };
+        self.notes.push(match applied {
+            Ok(()) => format!(
+                "── client set {}, {}, {} ──",
+                baud_rate, stop_bits, flow_control
+            ),
+            Err(err) => format!(
+                "── client set {}, {}, {}, but the device refused: {} ──",
+                baud_rate, stop_bits, flow_control, err
+            ),
+        });
+    }
+
+    /// Forwards what the client wrote on the pty to the device.
+    fn relay_client(&mut self) -> io::Result<()> {
+        let mut buf = [0_u8; 1024];
+        match self.pty.read(&mut buf) {
+            Ok(read) => match self.port.write_all(&buf[..read]) {
+                Ok(()) => {
+                    self.sent.extend_from_slice(&buf[..read]);
+                    Ok(())
+                }
+                Err(err) if err.kind() == ErrorKind::TimedOut => {
+                    self.notes.push(
+                        "── the device isn't taking data, client data is dropped ──".to_owned(),
+                    );
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            },
+            Err(err) if err.kind() == ErrorKind::TimedOut => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Read for SniffTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.mirror_settings();
+        let mut fds = [
+            libc::pollfd {
+                fd: self.pty.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.port.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let timeout = self.timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if fds[0].revents & libc::POLLIN != 0 {
+            self.relay_client()?;
+        }
+        if fds[1].revents & libc::POLLIN == 0 {
+            return Err(timed_out());
+        }
+
+        let read = self.port.read(buf)?;
+        match self.pty.write_all(&buf[..read]) {
+            Ok(()) => self.dropping = false,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                if !self.dropping {
+                    self.dropping = true;
+                    self.notes
+                        .push("── the client isn't reading, device data is dropped ──".to_owned());
+                }
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(read)
+    }
+}
+
+impl Write for SniffTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SniffTransport {
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_data_terminal_ready(level)?)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_request_to_send(level)?)
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        Ok(self.port.set_break()?)
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Ok(self.port.clear_break()?)
+    }
+
+    fn line_errors(&self) -> Option<LineErrors> {
+        read_line_errors(self.port.as_raw_fd())
+    }
+
+    fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+
+    fn take_sent(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sent)
+    }
+
+    fn relays(&self) -> bool {
+        true
+    }
+}
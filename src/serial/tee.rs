@@ -0,0 +1,213 @@
+use std::{
+    io::{self, ErrorKind, Write},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError},
+    thread,
+};
+
+use super::transport::{forward_output, stop_group};
+
+/// Chunks of received data that may wait for a slow command before more is dropped.
+const MAX_BACKLOG: usize = 256;
+
+/// A command attached to a port, like socat's EXEC: what the port receives goes to its stdin,
+/// its stdout is either written to the port or shown, and its stderr is shown.
+pub struct Tee {
+    command: String,
+    feed_back: bool,
+    child: Child,
+    input: SyncSender<Vec<u8>>,
+    stdout: Receiver<Vec<u8>>,
+    stderr: Receiver<Vec<u8>>,
+    /// Output lines that haven't ended yet.
+    stdout_line: Vec<u8>,
+    stderr_line: Vec<u8>,
+    /// The command stopped reading and received data is being thrown away.
+    dropping: bool,
+}
+
+#[derive(Default)]
+pub struct TeePoll {
+    /// Output to write to the port.
+    pub data: Vec<u8>,
+    /// Output to show, line by line.
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    /// Set once the command is gone, with how it ended.
+    pub exited: Option<String>,
+}
+
+impl Tee {
+    /// Runs `command` with `sh`, in a process group of its own so the whole pipeline can be
+    /// stopped. With `feed_back` its stdout goes to the port.
+    pub fn spawn(command: &str, feed_back: bool) -> io::Result<Tee> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (stdout_tx, stdout) = mpsc::channel();
+        if let Some(pipe) = child.stdout.take() {
+            forward_output(pipe, stdout_tx);
+        }
+        let (stderr_tx, stderr) = mpsc::channel();
+        if let Some(pipe) = child.stderr.take() {
+            forward_output(pipe, stderr_tx);
+        }
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "no stdin"))?;
+        // a command that is slow to read must not hold up the serial thread
+        let (input, input_rx) = mpsc::sync_channel::<Vec<u8>>(MAX_BACKLOG);
+        thread::spawn(move || {
+            for chunk in input_rx {
+                if stdin
+                    .write_all(&chunk)
+                    .and_then(|()| stdin.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(Tee {
+            command: command.to_owned(),
+            feed_back,
+            child,
+            input,
+            stdout,
+            stderr,
+            stdout_line: Vec::new(),
+            stderr_line: Vec::new(),
+            dropping: false,
+        })
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Hands received data to the command.
+    pub fn received(&mut self, bytes: &[u8]) -> Option<String> {
+        if bytes.is_empty() {
+            return None;
+        }
+        match self.input.try_send(bytes.to_vec()) {
+            Ok(()) => {
+                self.dropping = false;
+                None
+            }
+            Err(TrySendError::Full(_)) if !self.dropping => {
+                self.dropping = true;
+                Some(format!(
+                    "── {} isn't keeping up, received data is dropped ──",
+                    self.command
+                ))
+            }
+            // once the command is gone its end is reported by `poll`
+            Err(_) => None,
+        }
+    }
+
+    /// Collects the command's output and notices when it ends.
+    pub fn poll(&mut self) -> TeePoll {
+        let mut poll = TeePoll::default();
+        let stdout_open = drain(&self.stdout, |chunk| {
+            if self.feed_back {
+                poll.data.extend_from_slice(&chunk);
+            } else {
+                split_lines(&mut self.stdout_line, &chunk, &mut poll.stdout);
+            }
+        });
+        let stderr_open = drain(&self.stderr, |chunk| {
+            split_lines(&mut self.stderr_line, &chunk, &mut poll.stderr);
+        });
+        if !stdout_open && !stderr_open {
+            poll.stdout.extend(take_line(&mut self.stdout_line));
+            poll.stderr.extend(take_line(&mut self.stderr_line));
+            // a command can close its output and still go on for a while
+            poll.exited = match self.child.try_wait() {
+                Ok(Some(status)) => Some(status.to_string()),
+                Ok(None) => None,
+                Err(err) => Some(err.to_string()),
+            };
+        }
+        poll
+    }
+}
+
+/// Passes everything waiting on `output` to `each`. Returns `false` once the pipe is closed.
+fn drain(output: &Receiver<Vec<u8>>, mut each: impl FnMut(Vec<u8>)) -> bool {
+    loop {
+        match output.try_recv() {
+            Ok(chunk) => each(chunk),
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Disconnected) => return false,
+        }
+    }
+}
+
+fn split_lines(line: &mut Vec<u8>, chunk: &[u8], lines: &mut Vec<String>) {
+    for &byte in chunk {
+        if byte == b'\n' {
+            lines.extend(take_line(line));
+        } else {
+            line.push(byte);
+        }
+    }
+}
+
+fn take_line(line: &mut Vec<u8>) -> Option<String> {
+    let text = String::from_utf8_lossy(line)
+        .trim_end_matches('\r')
+        .to_owned();
+    line.clear();
+    (!text.is_empty()).then_some(text)
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        stop_group(&mut self.child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::Tee;
+    use crate::serial::mock::harness::{read_pid, running, stops, TempDir};
+
+    #[test]
+    fn dropping_stops_the_whole_pipeline() {
+        let dir = TempDir::new("tee");
+        let pid_file = dir.path().join("pid");
+        let command = format!("trap '' TERM; sleep 60 & echo $! > {}; wait", pid_file.display());
+        let tee = Tee::spawn(&command, false).unwrap();
+        let pid = read_pid(&pid_file);
+        assert!(running(&pid));
+        drop(tee);
+        assert!(stops(&pid), "sleep {} outlived its tee", pid);
+    }
+
+    #[test]
+    fn dropping_stops_what_an_ended_command_left_behind() {
+        let dir = TempDir::new("tee-ended");
+        let pid_file = dir.path().join("pid");
+        let command = format!("trap '' TERM; sleep 60 & echo $! > {}", pid_file.display());
+        let mut tee = Tee::spawn(&command, false).unwrap();
+        let pid = read_pid(&pid_file);
+        // `sh` is gone and reaped, the sleep it started is not
+        while matches!(tee.child.try_wait(), Ok(None)) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(running(&pid));
+        drop(tee);
+        assert!(stops(&pid), "sleep {} outlived its tee", pid);
+    }
+}
@@ -0,0 +1,546 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixStream,
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    ptr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use super::{
+    bridge::{self, BridgeTransport},
+    identity, mock,
+    rfc2217::{self, Rfc2217Port},
+    settings::PortSettings,
+    sim::{self, SimTransport},
+    sniff::{self, SniffTransport},
+    utils::{read_line_errors, LineErrors},
+};
+
+/// Something that can be used as a port. Only `Read` and `Write` are required, the control lines,
+/// BREAK and the error counters are there for transports that have them.
+pub trait Transport: Read + Write + Send {
+    fn write_data_terminal_ready(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported("DTR"))
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported("RTS"))
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        Err(unsupported("BREAK"))
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Err(unsupported("BREAK"))
+    }
+
+    /// Error counters kept by the kernel since the device was first opened.
+    fn line_errors(&self) -> Option<LineErrors> {
+        None
+    }
+
+    /// Messages for the user that came up since the last call, like where to find the other
+    /// end of a pty.
+    fn take_notes(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Data the transport wrote to the other end by itself, like a simulator's replies, for
+    /// the local echo.
+    fn take_sent(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Whether the transport forwards data between other parties, so it has to keep being read
+    /// while another port is shown.
+    fn relays(&self) -> bool {
+        false
+    }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        format!("{} is not supported by this port", what),
+    )
+}
+
+/// What a read that got nothing in time fails with, like serialport's.
+pub(super) fn timed_out() -> io::Error {
+    io::Error::new(ErrorKind::TimedOut, "Operation timed out")
+}
+
+/// Opens `name`, which is one of
+/// - a serial device path,
+/// - `rfc2217://host:port` for a port on a serial server,
+/// - `tcp:host:port` or `unix:path` for a raw socket, like QEMU's `-serial tcp:`/`unix:`,
+/// - `exec:command` for the stdio of a command run by `sh`,
+/// - `pty` (or `pty:label`) for a new pseudo terminal other programs can open,
+/// - `virtual:...` for one of the built-in mock devices, see `mock::open`,
+/// - `sim:<rules file>` for a device simulated on a pty, see `sim::SimTransport`,
+/// - `sniff:<device>` for a pty relayed to a serial device, see `sniff::SniffTransport`,
+/// - `bridge:<port>,<port>` or `bridge:<bridge file>` for two ports forwarding to each other,
+///   see `bridge::BridgeTransport`.
+///
+/// Reads give up with `ErrorKind::TimedOut` after `timeout`.
+pub fn open(name: &str, timeout: Duration) -> io::Result<Box<dyn Transport>> {
+    open_with(name, &PortSettings::default(), timeout)
+}
+
+/// Opens `name` like `open`, with `settings` for the ports that have line settings.
+pub fn open_with(
+    name: &str,
+    settings: &PortSettings,
+    timeout: Duration,
+) -> io::Result<Box<dyn Transport>> {
+    if name.starts_with(rfc2217::SCHEME) {
+        let port = Rfc2217Port::open(name, settings, timeout)?;
+        return Ok(Box::new(SerialTransport {
+            port: Box::new(port),
+            fd: None,
+        }));
+    }
+    if let Some(addr) = name.strip_prefix("tcp:") {
+        let stream = rfc2217::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        return Ok(Box::new(StreamTransport::new(stream, timeout)));
+    }
+    if let Some(path) = name.strip_prefix("unix:") {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        return Ok(Box::new(StreamTransport::new(stream, timeout)));
+    }
+    if let Some(command) = name.strip_prefix("exec:") {
+        return Ok(Box::new(ExecTransport::spawn(command, timeout)?));
+    }
+    if name.starts_with(sim::SCHEME) {
+        return Ok(Box::new(SimTransport::open(name, timeout)?));
+    }
+    if name.starts_with(bridge::SCHEME) {
+        return Ok(Box::new(BridgeTransport::open(name, timeout)?));
+    }
+    if name.starts_with(sniff::SCHEME) {
+        return Ok(Box::new(SniffTransport::open(name, timeout)?));
+    }
+    if name.starts_with(mock::SCHEME) {
+        return mock::open(name, timeout);
+    }
+    if name == "pty" || name.starts_with("pty:") {
+        return Ok(Box::new(PtyTransport::open(timeout)?));
+    }
+    if name.starts_with(identity::SCHEME) {
+        let path = identity::device_path(name)?;
+        return open_with(&path.to_string_lossy(), settings, timeout);
+    }
+
+    let port = serialport::new(name, settings.baud_rate)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control)
+        .timeout(timeout)
+        .open_native()?;
+    let fd = port.as_raw_fd();
+    Ok(Box::new(SerialTransport {
+        port: Box::new(port),
+        fd: Some(fd),
+    }))
+}
+
+/// A local serial device or anything else behind the `serialport` API.
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+    /// Descriptor of a local tty, for its error counters.
+    fd: Option<RawFd>,
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_data_terminal_ready(level)?)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_request_to_send(level)?)
+    }
+
+    fn set_break(&mut self) -> io::Result<()> {
+        Ok(self.port.set_break()?)
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Ok(self.port.clear_break()?)
+    }
+
+    fn line_errors(&self) -> Option<LineErrors> {
+        self.fd.and_then(read_line_errors)
+    }
+}
+
+/// A connected TCP or Unix socket, with a read timeout set.
+pub struct StreamTransport<S> {
+    stream: S,
+    timeout: Duration,
+    closed: bool,
+    notes: Vec<String>,
+}
+
+impl<S> StreamTransport<S> {
+    fn new(stream: S, timeout: Duration) -> StreamTransport<S> {
+        StreamTransport {
+            stream,
+            timeout,
+            closed: false,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl<S: Read> Read for StreamTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.closed {
+            thread::sleep(self.timeout);
+            return Err(timed_out());
+        }
+        match self.stream.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                self.closed = true;
+                self.notes
+                    .push("── the other end closed the connection ──".to_owned());
+                Err(timed_out())
+            }
+            // a socket read timeout surfaces as WouldBlock
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Err(timed_out()),
+            result => result,
+        }
+    }
+}
+
+impl<S: Write> Write for StreamTransport<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + Write + Send> Transport for StreamTransport<S> {
+    fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+}
+
+/// Chunks written to a command that may wait for it to read them before writes block.
+const EXEC_BACKLOG: usize = 256;
+/// How long a command gets to go after SIGTERM before it is killed.
+const STOP_GRACE: Duration = Duration::from_millis(500);
+
+/// A command run by `sh`, with its stdin as TX and its stdout and stderr as RX.
+pub struct ExecTransport {
+    command: String,
+    child: Child,
+    input: SyncSender<Vec<u8>>,
+    output: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    timeout: Duration,
+    exited: bool,
+    notes: Vec<String>,
+}
+
+impl ExecTransport {
+    /// Runs `command` in a process group of its own, so that closing the port stops the whole
+    /// pipeline.
+    fn spawn(command: &str, timeout: Duration) -> io::Result<ExecTransport> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (output_tx, output) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward_output(stdout, output_tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_output(stderr, output_tx);
+        }
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "no stdin"))?;
+        // a command that is slow to read must not hold up the serial thread
+        let (input, input_rx) = mpsc::sync_channel::<Vec<u8>>(EXEC_BACKLOG);
+        thread::spawn(move || {
+            for chunk in input_rx {
+                if stdin
+                    .write_all(&chunk)
+                    .and_then(|()| stdin.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(ExecTransport {
+            command: command.to_owned(),
+            child,
+            input,
+            output,
+            pending: Vec::new(),
+            timeout,
+            exited: false,
+            notes: Vec::new(),
+        })
+    }
+}
+
+/// Stops a command spawned in a process group of its own, and everything it started: SIGTERM to
+/// the group, then SIGKILL to what is left of it after a grace period. The group is signalled
+/// even when the command itself is gone already, what it started may not be.
+pub(super) fn stop_group(child: &mut Child) {
+    let group = -(child.id() as libc::pid_t);
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    let deadline = Instant::now() + STOP_GRACE;
+    // reaping the command is what lets the group be empty, a zombie still counts
+    while (matches!(child.try_wait(), Ok(None)) || unsafe { libc::kill(group, 0) } == 0)
+        && Instant::now() < deadline
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    // whatever ignored SIGTERM, and the children of a `sh` that didn't
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    if matches!(child.try_wait(), Ok(None)) {
+        let _ = child.wait();
+    }
+}
+
+/// Pipes can't be read with a timeout, so each one gets a thread of its own.
+pub(super) fn forward_output<R: Read + Send + 'static>(mut pipe: R, output_tx: Sender<Vec<u8>>) {
+    thread::spawn(move || {
+        let mut buf = [0_u8; 1024];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => {
+                    if output_tx.send(buf[..read].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+impl Read for ExecTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.output.recv_timeout(self.timeout) {
+                Ok(chunk) => self.pending = chunk,
+                Err(RecvTimeoutError::Timeout) => return Err(timed_out()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    // a command can close its output and still go on for a while
+                    if !self.exited {
+                        let status = match self.child.try_wait() {
+                            Ok(status) => status.map(|status| status.to_string()),
+                            Err(err) => Some(err.to_string()),
+                        };
+                        if let Some(status) = status {
+                            self.exited = true;
+                            self.notes.push(format!("── {} ended: {} ──", self.command, status));
+                        }
+                    }
+                    // nothing is coming anymore, don't let the caller spin on it
+                    thread::sleep(self.timeout);
+                    return Err(timed_out());
+                }
+            }
+        }
+        let read = buf.len().min(self.pending.len());
+        buf[..read].copy_from_slice(&self.pending[..read]);
+        self.pending.drain(..read);
+        Ok(read)
+    }
+}
+
+impl Write for ExecTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.input.try_send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(TrySendError::Full(_)) => Err(ErrorKind::WouldBlock.into()),
+            Err(TrySendError::Disconnected(_)) => Err(ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ExecTransport {
+    fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+}
+
+impl Drop for ExecTransport {
+    fn drop(&mut self) {
+        stop_group(&mut self.child);
+    }
+}
+
+/// The master side of a new pseudo terminal. The slave side is kept open, in raw mode, so
+/// nothing is lost or echoed back while no other program has it open.
+pub struct PtyTransport {
+    master: File,
+    _slave: File,
+    path: PathBuf,
+    timeout: Duration,
+    notes: Vec<String>,
+}
+
+impl PtyTransport {
+    pub fn open(timeout: Duration) -> io::Result<PtyTransport> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+            // a full buffer, nobody reading the other end, must not block the serial thread
+            let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+
+        let path = fs::read_link(format!("/proc/self/fd/{}", slave.as_raw_fd()))?;
+        Ok(PtyTransport {
+            master,
+            _slave: slave,
+            timeout,
+            notes: vec![format!("── pty ready, the other end is {} ──", path.display())],
+            path,
+        })
+    }
+
+    /// Where other programs open the pty.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRawFd for PtyTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+}
+
+impl Read for PtyTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut fds = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = self.timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut fds, 1, timeout) } {
+            result if result < 0 => Err(io::Error::last_os_error()),
+            0 => Err(timed_out()),
+            _ => match self.master.read(buf) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => Err(timed_out()),
+                result => result,
+            },
+        }
+    }
+}
+
+impl Write for PtyTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+impl Transport for PtyTransport {
+    fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use super::open;
+    use crate::serial::mock::harness::{read_pid, running, stops, TempDir};
+
+    #[test]
+    fn closing_a_command_port_stops_the_whole_pipeline() {
+        let dir = TempDir::new("exec");
+        let pid_file = dir.path().join("pid");
+        let command = format!("trap '' TERM; sleep 60 & echo $! > {}; wait", pid_file.display());
+        let port = open(&format!("exec:{}", command), Duration::from_millis(5)).unwrap();
+        let pid = read_pid(&pid_file);
+        assert!(running(&pid));
+        drop(port);
+        assert!(stops(&pid), "sleep {} outlived its port", pid);
+    }
+
+    #[test]
+    fn writing_to_a_command_that_does_not_read_does_not_block() {
+        let mut port = open("exec:sleep 60", Duration::from_millis(5)).unwrap();
+        let chunk = [b'x'; 1024];
+        let refused = (0..10_000).find_map(|_| port.write(&chunk).err());
+        assert_eq!(refused.map(|err| err.kind()), Some(std::io::ErrorKind::WouldBlock));
+    }
+}
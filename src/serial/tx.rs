@@ -0,0 +1,349 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Write},
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+/// Most of the received data kept around while waiting for a prompt.
+const PROMPT_WINDOW: usize = 4096;
+/// A job fails once the port has not taken a single byte of it for this long.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+/// A job fails once the device has not printed the prompt for this long.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How fast queued data is written out.
+#[derive(Clone, Default)]
+pub struct Pacing {
+    pub char_delay: Duration,
+    pub line_delay: Duration,
+    /// Wait for the device to print this before sending each next line.
+    pub prompt: Option<Regex>,
+}
+
+impl Pacing {
+    fn is_paced(&self) -> bool {
+        !self.char_delay.is_zero() || !self.line_delay.is_zero() || self.prompt.is_some()
+    }
+}
+
+struct TxJob {
+    id: u64,
+    data: Vec<u8>,
+    sent: usize,
+    pacing: Pacing,
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct TxProgress {
+    /// Bytes written out of everything that is or was queued since the queue was last empty.
+    pub sent: usize,
+    pub total: usize,
+    /// Jobs still (partly) waiting in the queue.
+    pub queued: usize,
+    pub awaiting_prompt: bool,
+}
+
+impl TxProgress {
+    pub fn is_done(&self) -> bool {
+        self.queued == 0
+    }
+}
+
+/// How a queued job ended, with the number of bytes that made it out.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TxResult {
+    Sent(usize),
+    Failed(usize, String),
+    Cancelled(usize),
+}
+
+#[derive(Default)]
+pub struct TxOutcome {
+    /// Lines written, for the local echo.
+    pub echo: Vec<String>,
+    /// The new progress, if anything changed.
+    pub progress: Option<TxProgress>,
+    pub finished: Vec<(u64, TxResult)>,
+}
+
+/// Data waiting to be written to a port, in order.
+#[derive(Default)]
+pub struct TxQueue {
+    jobs: VecDeque<TxJob>,
+    next_id: u64,
+    next_at: Option<Instant>,
+    /// Since when the port has refused to take data.
+    stalled_since: Option<Instant>,
+    /// Since when the next line waits for the prompt.
+    awaiting_prompt: Option<Instant>,
+    received: Vec<u8>,
+    progress: TxProgress,
+    echo_pending: Vec<u8>,
+}
+
+impl TxQueue {
+    /// Queues `data` and returns the id its result will be reported under.
+    pub fn push(&mut self, data: Vec<u8>, pacing: Pacing) -> u64 {
+        if self.jobs.is_empty() {
+            self.progress = TxProgress::default();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.progress.total += data.len();
+        self.progress.queued += 1;
+        self.jobs.push_back(TxJob {
+            id,
+            data,
+            sent: 0,
+            pacing,
+        });
+        id
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Whether the port has to be read for the prompt the next line waits for.
+    pub fn is_awaiting_prompt(&self) -> bool {
+        self.awaiting_prompt.is_some()
+    }
+
+    /// Drops everything not written yet.
+    pub fn cancel(&mut self) -> TxOutcome {
+        let mut outcome = TxOutcome::default();
+        for job in self.jobs.drain(..) {
+            outcome.finished.push((job.id, TxResult::Cancelled(job.sent)));
+        }
+        self.flush_echo(&mut outcome.echo);
+        self.next_at = None;
+        self.stalled_since = None;
+        self.awaiting_prompt = None;
+        self.progress.total = self.progress.sent;
+        self.progress.queued = 0;
+        self.progress.awaiting_prompt = false;
+        outcome.progress = Some(self.progress.clone());
+        outcome
+    }
+
+    fn flush_echo(&mut self, echo: &mut Vec<String>) {
+        if !self.echo_pending.is_empty() {
+            echo.push(String::from_utf8_lossy(&self.echo_pending).into_owned());
+            self.echo_pending.clear();
+        }
+    }
+
+    fn finish_front(&mut self, result: TxResult, outcome: &mut TxOutcome) {
+        if let Some(job) = self.jobs.pop_front() {
+            if let TxResult::Failed(..) = result {
+                // whatever the job did not get out doesn't count towards the total anymore
+                self.progress.total -= job.data.len() - job.sent;
+            }
+            outcome.finished.push((job.id, result));
+        }
+        self.progress.queued = self.jobs.len();
+        self.stalled_since = None;
+        self.awaiting_prompt = None;
+        self.flush_echo(&mut outcome.echo);
+    }
+
+    /// Feeds data read from the port, for the prompt wait.
+    pub fn on_received(&mut self, bytes: &[u8]) {
+        if self.awaiting_prompt.is_none() {
+            return;
+        }
+        self.received.extend_from_slice(bytes);
+        if self.received.len() > PROMPT_WINDOW {
+            let excess = self.received.len() - PROMPT_WINDOW;
+            self.received.drain(..excess);
+        }
+    }
+
+    /// Writes whatever is due. The port may take less than offered or nothing at all, the rest
+    /// is retried on the next call.
+    pub fn service<W: Write + ?Sized>(&mut self, port: &mut W) -> TxOutcome {
+        self.service_at(port, Instant::now())
+    }
+
+    fn service_at<W: Write + ?Sized>(&mut self, port: &mut W, now: Instant) -> TxOutcome {
+        let mut outcome = TxOutcome::default();
+        let before = self.progress.clone();
+
+        while let Some(job) = self.jobs.front_mut() {
+            if job.sent >= job.data.len() {
+                // nothing to write, an empty job is done as soon as it comes up
+                let sent = job.sent;
+                self.finish_front(TxResult::Sent(sent), &mut outcome);
+                continue;
+            }
+            if let Some(awaiting_since) = self.awaiting_prompt {
+                let prompt = job.pacing.prompt.as_ref();
+                if prompt.is_some_and(|prompt| {
+                    prompt.is_match(&String::from_utf8_lossy(&self.received))
+                }) {
+                    self.awaiting_prompt = None;
+                    self.received.clear();
+                } else if now.duration_since(awaiting_since) >= PROMPT_TIMEOUT {
+                    let sent = job.sent;
+                    self.finish_front(
+                        TxResult::Failed(sent, "timed out waiting for the prompt".to_owned()),
+                        &mut outcome,
+                    );
+                    continue;
+                } else {
+                    break;
+                }
+            }
+            if self.next_at.is_some_and(|next_at| now < next_at) {
+                break;
+            }
+
+            let rest = &job.data[job.sent..];
+            let chunk = if !job.pacing.is_paced() {
+                rest.len()
+            } else if job.pacing.char_delay.is_zero() {
+                // whole lines at a time
+                rest.iter()
+                    .position(|&byte| byte == b'\n')
+                    .map_or(rest.len(), |idx| idx + 1)
+            } else {
+                1
+            };
+            let written = match port.write(&rest[..chunk]) {
+                Ok(0) => 0,
+                Ok(written) => written,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                    ) =>
+                {
+                    0
+                }
+                Err(err) => {
+                    let sent = job.sent;
+                    self.finish_front(TxResult::Failed(sent, err.to_string()), &mut outcome);
+                    continue;
+                }
+            };
+            if written == 0 {
+                // the port's buffer is full, come back later
+                let stalled_since = *self.stalled_since.get_or_insert(now);
+                if now.duration_since(stalled_since) >= STALL_TIMEOUT {
+                    let sent = job.sent;
+                    self.finish_front(
+                        TxResult::Failed(sent, "write timed out".to_owned()),
+                        &mut outcome,
+                    );
+                    continue;
+                }
+                break;
+            }
+            self.stalled_since = None;
+            self.echo_pending.extend_from_slice(&rest[..written]);
+            job.sent += written;
+            self.progress.sent += written;
+            if written < chunk {
+                // a partial write, the rest goes out as soon as the port takes it
+                break;
+            }
+
+            let ended_line = job.data[job.sent - 1] == b'\n';
+            let finished = job.sent >= job.data.len();
+            self.next_at = if !job.pacing.is_paced() {
+                None
+            } else if ended_line {
+                Some(now + job.pacing.line_delay)
+            } else {
+                Some(now + job.pacing.char_delay)
+            };
+            if ended_line && !finished && job.pacing.prompt.is_some() {
+                self.awaiting_prompt = Some(now);
+                self.received.clear();
+            }
+
+            while let Some(newline_idx) = self.echo_pending.iter().position(|&byte| byte == b'\n')
+            {
+                let mut line = self.echo_pending.drain(..=newline_idx).collect::<Vec<_>>();
+                while matches!(line.last(), Some(b'\n' | b'\r')) {
+                    line.pop();
+                }
+                outcome.echo.push(String::from_utf8_lossy(&line).into_owned());
+            }
+            if finished {
+                let sent = job.sent;
+                self.finish_front(TxResult::Sent(sent), &mut outcome);
+            }
+            if self.next_at.is_some() {
+                break;
+            }
+        }
+
+        self.progress.awaiting_prompt = self.awaiting_prompt.is_some();
+        outcome.progress = (self.progress != before).then(|| self.progress.clone());
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_job_is_sent_at_once() {
+        let mut queue = TxQueue::default();
+        let id = queue.push(Vec::new(), Pacing::default());
+        let mut port = Vec::new();
+        let outcome = queue.service(&mut port);
+        assert_eq!(outcome.finished, vec![(id, TxResult::Sent(0))]);
+        assert!(queue.is_idle());
+    }
+
+    #[test]
+    fn prompt_wait_times_out() {
+        let mut queue = TxQueue::default();
+        let pacing = Pacing {
+            prompt: Some(Regex::new("=> $").unwrap()),
+            ..Pacing::default()
+        };
+        let id = queue.push(b"first\nsecond\n".to_vec(), pacing);
+        let mut port = Vec::new();
+        let start = Instant::now();
+        assert!(queue.service_at(&mut port, start).finished.is_empty());
+        assert_eq!(port, b"first\n");
+        assert!(queue.is_awaiting_prompt());
+
+        queue.on_received(b"booting\n");
+        let waiting = queue.service_at(&mut port, start + PROMPT_TIMEOUT / 2);
+        assert!(waiting.finished.is_empty());
+        let outcome = queue.service_at(&mut port, start + PROMPT_TIMEOUT);
+        assert_eq!(
+            outcome.finished,
+            vec![(
+                id,
+                TxResult::Failed(6, "timed out waiting for the prompt".to_owned())
+            )]
+        );
+        assert_eq!(port, b"first\n");
+    }
+
+    #[test]
+    fn prompt_lets_the_next_line_go() {
+        let mut queue = TxQueue::default();
+        let pacing = Pacing {
+            prompt: Some(Regex::new("=> $").unwrap()),
+            ..Pacing::default()
+        };
+        let id = queue.push(b"first\nsecond\n".to_vec(), pacing);
+        let mut port = Vec::new();
+        let start = Instant::now();
+        queue.service_at(&mut port, start);
+        queue.on_received(b"=> ");
+        let outcome = queue.service_at(&mut port, start);
+        assert_eq!(outcome.finished, vec![(id, TxResult::Sent(13))]);
+        assert_eq!(port, b"first\nsecond\n");
+        assert_eq!(outcome.echo, vec!["second".to_owned()]);
+    }
+}
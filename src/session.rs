@@ -0,0 +1,114 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, serial::LineDirection};
+
+/// The session saved on exit and restored on the next start, unless `--session` names another.
+pub const LAST: &str = "last";
+
+/// What was open when determ exited, for example
+///
+/// ```toml
+/// active = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K5LXY-if00-port0"
+/// pane = "write"
+///
+/// [[port]]
+/// name = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K5LXY-if00-port0"
+/// timestamps = true
+///
+/// [[port]]
+/// name = "/dev/ttyACM0"
+/// paused = true
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+pub struct Session {
+    pub active: Option<String>,
+    /// The pane that had the focus: `ports`, `term` or `write`.
+    pub pane: Option<String>,
+    #[serde(default, rename = "port")]
+    pub ports: Vec<PortState>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PortState {
+    pub name: String,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default = "local_echo")]
+    pub local_echo: bool,
+    #[serde(default)]
+    pub timestamps: bool,
+    #[serde(default)]
+    pub error_markers: bool,
+    #[serde(default)]
+    pub broadcast: bool,
+    /// Logging goes on in a new file.
+    #[serde(default)]
+    pub logging: bool,
+    /// Only saved when the config file asks for it.
+    #[serde(default, rename = "line", skip_serializing_if = "Vec::is_empty")]
+    pub scrollback: Vec<SavedLine>,
+}
+
+fn local_echo() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedLine {
+    pub direction: LineDirection,
+    /// Milliseconds since the epoch.
+    pub time: u64,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub marked: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// `name.toml` in the `sessions` directory of the config directory, or `name` itself when it
+/// looks like a path.
+fn path(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+    config::dir().map(|dir| dir.join("sessions").join(format!("{}.toml", name)))
+}
+
+impl Session {
+    /// Reads the session called `name`, `None` when it was never saved.
+    pub fn load(name: &str) -> io::Result<Option<Session>> {
+        let Some(path) = path(name) else {
+            return Ok(None);
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        toml::from_str(&source).map(Some).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err.message()),
+            )
+        })
+    }
+
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        let path = path(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no config directory to save to")
+        })?;
+        if let Some(dir) = path.parent().filter(|dir| *dir != Path::new("")) {
+            fs::create_dir_all(dir)?;
+        }
+        let source = toml::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        fs::write(path, source)
+    }
+}
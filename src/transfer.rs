@@ -0,0 +1,409 @@
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+pub mod xmodem;
+pub mod zmodem;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Protocol {
+    Xmodem,
+    Xmodem1k,
+    XmodemCrc,
+    Ymodem,
+    Zmodem,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 5] = [
+        Protocol::Xmodem,
+        Protocol::XmodemCrc,
+        Protocol::Xmodem1k,
+        Protocol::Ymodem,
+        Protocol::Zmodem,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Xmodem => "XMODEM",
+            Protocol::Xmodem1k => "XMODEM-1K",
+            Protocol::XmodemCrc => "XMODEM-CRC",
+            Protocol::Ymodem => "YMODEM batch",
+            Protocol::Zmodem => "ZMODEM",
+        }
+    }
+}
+
+pub enum TransferDirection {
+    /// Files to upload, more than one only makes sense for YMODEM batches.
+    Send(Vec<PathBuf>),
+    /// Directory the received files are written to.
+    Receive(PathBuf),
+}
+
+pub struct TransferRequest {
+    pub protocol: Protocol,
+    pub direction: TransferDirection,
+    /// Ask a ZMODEM receiver to append to a shorter file of the same name rather than replace it.
+    pub resume: bool,
+    pub cancel: Arc<AtomicBool>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TransferState {
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+impl fmt::Display for TransferState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferState::Running => write!(f, "running"),
+            TransferState::Done => write!(f, "done"),
+            TransferState::Cancelled => write!(f, "cancelled"),
+            TransferState::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferProgress {
+    pub protocol: Protocol,
+    pub file: String,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    pub retries: u32,
+    pub state: TransferState,
+}
+
+impl TransferProgress {
+    pub fn new(protocol: Protocol) -> TransferProgress {
+        TransferProgress {
+            protocol,
+            file: String::new(),
+            bytes: 0,
+            total: None,
+            retries: 0,
+            state: TransferState::Running,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    Cancelled,
+    RemoteCancelled,
+    Timeout,
+    TooManyRetries,
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Cancelled => write!(f, "cancelled"),
+            TransferError::RemoteCancelled => write!(f, "cancelled by the remote side"),
+            TransferError::Timeout => write!(f, "timed out waiting for the remote side"),
+            TransferError::TooManyRetries => write!(f, "too many retries"),
+            TransferError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            TransferError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for TransferError {
+    fn from(err: io::Error) -> Self {
+        TransferError::Io(err)
+    }
+}
+
+/// Runs a transfer to completion on `port`, reporting progress through `report`. The final
+/// report always carries a state other than `TransferState::Running`.
+pub fn run<P: Read + Write + ?Sized>(
+    port: &mut P,
+    request: &TransferRequest,
+    report: &mut dyn FnMut(&TransferProgress),
+) {
+    let mut progress = TransferProgress::new(request.protocol);
+    let result = match &request.direction {
+        TransferDirection::Send(files) if request.protocol == Protocol::Zmodem => {
+            zmodem::send(port, files, request.resume, &request.cancel, &mut progress, report)
+        }
+        TransferDirection::Receive(dir) if request.protocol == Protocol::Zmodem => {
+            zmodem::receive(port, dir, &request.cancel, &mut progress, report)
+        }
+        TransferDirection::Send(files) => xmodem::send(
+            port,
+            request.protocol,
+            files,
+            &request.cancel,
+            &mut progress,
+            report,
+        ),
+        TransferDirection::Receive(dir) => xmodem::receive(
+            port,
+            request.protocol,
+            dir,
+            &request.cancel,
+            &mut progress,
+            report,
+        ),
+    };
+    progress.state = match result {
+        Ok(()) => TransferState::Done,
+        Err(TransferError::Cancelled) => TransferState::Cancelled,
+        Err(err) => TransferState::Failed(err.to_string()),
+    };
+    report(&progress);
+}
+
+/// Reads one byte, waiting at most `timeout`. `Ok(None)` means the timeout elapsed.
+pub(crate) fn read_byte<P: Read + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<Option<u8>, TransferError> {
+    let mut byte = [0_u8; 1];
+    if read_exact(port, &mut byte, timeout, cancel)? {
+        Ok(Some(byte[0]))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Fills `buf`, waiting at most `timeout` in total. Returns `false` if the timeout elapsed first.
+pub(crate) fn read_exact<P: Read + ?Sized>(
+    port: &mut P,
+    buf: &mut [u8],
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<bool, TransferError> {
+    let deadline = Instant::now() + timeout;
+    let mut filled = 0;
+    while filled < buf.len() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(TransferError::Cancelled);
+        }
+        match port.read(&mut buf[filled..]) {
+            Ok(n) if n > 0 => filled += n,
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+        if filled < buf.len() && Instant::now() >= deadline {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Drops whatever the remote side is still sending, until the line has been quiet for `quiet`.
+pub(crate) fn purge<P: Read + ?Sized>(
+    port: &mut P,
+    quiet: Duration,
+    cancel: &AtomicBool,
+) -> Result<(), TransferError> {
+    while read_byte(port, quiet, cancel)?.is_some() {}
+    Ok(())
+}
+
+/// Picks a name for a received file that does not overwrite anything in `dir`.
+pub(crate) fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "received.bin".to_owned());
+    let mut path = dir.join(&name);
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}.{}", name, n));
+        n += 1;
+    }
+    path
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+    for &byte in data {
+        crc = update_crc16(crc, byte);
+    }
+    crc
+}
+
+pub(crate) fn update_crc16(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ (u16::from(byte) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 {
+            (crc << 1) ^ 0x1021
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, thread};
+
+    use super::*;
+    use crate::serial::mock::harness::{pty_pair, TempDir};
+
+    /// Test data that doesn't fill its last block and has every byte value in it, the ones the
+    /// protocols escape included.
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| (idx * 7 + idx / 256) as u8).collect()
+    }
+
+    fn request(protocol: Protocol, direction: TransferDirection, resume: bool) -> TransferRequest {
+        TransferRequest {
+            protocol,
+            direction,
+            resume,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sends `files` from the device end of a pty to determ's end and returns how both sides
+    /// ended.
+    fn round_trip(
+        protocol: Protocol,
+        files: Vec<PathBuf>,
+        dir: PathBuf,
+        resume: bool,
+    ) -> (TransferState, TransferState) {
+        let (mut master, mut slave) = pty_pair().unwrap();
+        let receiver = thread::spawn(move || {
+            let mut state = TransferState::Running;
+            let request = request(protocol, TransferDirection::Receive(dir), false);
+            run(&mut master, &request, &mut |progress| {
+                state = progress.state.clone()
+            });
+            // closing the pty now would hang up on the sender before it read the last answer
+            (state, master)
+        });
+        let mut state = TransferState::Running;
+        let request = request(protocol, TransferDirection::Send(files), resume);
+        run(&mut *slave, &request, &mut |progress| {
+            state = progress.state.clone()
+        });
+        (state, receiver.join().unwrap().0)
+    }
+
+    fn received(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    fs::read(entry.path()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn xmodem_round_trips() {
+        for protocol in [Protocol::Xmodem, Protocol::XmodemCrc, Protocol::Xmodem1k] {
+            let scratch = TempDir::new(&format!("xmodem-{}", protocol.name()));
+            // XMODEM has no length, the receiver strips the padding, so no SUB at the end
+            let mut sent = data(3000);
+            sent.push(b'.');
+            let file = scratch.write("upload.bin", &sent);
+            let dir = scratch.path().join("received");
+            let states = round_trip(protocol, vec![file], dir.clone(), false);
+            assert_eq!(
+                states,
+                (TransferState::Done, TransferState::Done),
+                "{}",
+                protocol.name()
+            );
+            let files = received(&dir);
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].1, sent, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn ymodem_round_trips_a_batch() {
+        let scratch = TempDir::new("ymodem");
+        let long_name = format!("{}.bin", "a-rather-long-firmware-name-".repeat(6));
+        let files = vec![
+            scratch.write("first.bin", data(2000)),
+            scratch.write("empty.bin", []),
+            scratch.write(&long_name, data(130)),
+        ];
+        let dir = scratch.path().join("received");
+        let states = round_trip(Protocol::Ymodem, files, dir.clone(), false);
+        assert_eq!(states, (TransferState::Done, TransferState::Done));
+        let mut expected = vec![
+            (long_name, data(130)),
+            ("empty.bin".to_owned(), Vec::new()),
+            ("first.bin".to_owned(), data(2000)),
+        ];
+        expected.sort();
+        assert_eq!(received(&dir), expected);
+    }
+
+    #[test]
+    fn zmodem_round_trips() {
+        let scratch = TempDir::new("zmodem");
+        let files = vec![
+            scratch.write("image.bin", data(20000)),
+            scratch.write("notes.txt", "one\r\ntwo\r\n"),
+        ];
+        let dir = scratch.path().join("received");
+        let states = round_trip(Protocol::Zmodem, files, dir.clone(), false);
+        assert_eq!(states, (TransferState::Done, TransferState::Done));
+        assert_eq!(
+            received(&dir),
+            vec![
+                ("image.bin".to_owned(), data(20000)),
+                ("notes.txt".to_owned(), b"one\r\ntwo\r\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn zmodem_replaces_unless_resuming() {
+        let scratch = TempDir::new("zmodem-resume");
+        let image = data(5000);
+        let file = scratch.write("image.bin", &image);
+        let dir = scratch.path().join("received");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("image.bin"), b"stale").unwrap();
+
+        let states = round_trip(Protocol::Zmodem, vec![file.clone()], dir.clone(), false);
+        assert_eq!(states, (TransferState::Done, TransferState::Done));
+        assert_eq!(
+            received(&dir),
+            vec![
+                ("image.bin".to_owned(), b"stale".to_vec()),
+                ("image.bin.1".to_owned(), image.clone()),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("image.bin"), &image[..1000]).unwrap();
+        let states = round_trip(Protocol::Zmodem, vec![file], dir.clone(), true);
+        assert_eq!(states, (TransferState::Done, TransferState::Done));
+        assert_eq!(received(&dir), vec![("image.bin".to_owned(), image)]);
+    }
+}
@@ -0,0 +1,518 @@
+//! XMODEM (checksum, CRC and 1K) and YMODEM batch transfers.
+
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    crc16, purge, read_byte, read_exact, unique_path, Protocol, TransferError, TransferProgress,
+};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const CRC_START: u8 = b'C';
+
+const MAX_RETRIES: u32 = 10;
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+const START_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Check {
+    Checksum,
+    Crc,
+}
+
+impl Protocol {
+    fn block_size(&self) -> usize {
+        match self {
+            Protocol::Xmodem | Protocol::XmodemCrc => 128,
+            Protocol::Xmodem1k | Protocol::Ymodem | Protocol::Zmodem => 1024,
+        }
+    }
+
+    fn check(&self) -> Check {
+        match self {
+            Protocol::Xmodem => Check::Checksum,
+            _ => Check::Crc,
+        }
+    }
+}
+
+fn cancel_remote<P: Write + ?Sized>(port: &mut P) {
+    let _ = port.write_all(&[CAN; 8]);
+    let _ = port.flush();
+}
+
+/// Waits for the receiver to ask for the transfer to start and returns the check it asked for.
+fn wait_for_start<P: Read + Write + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<Check, TransferError> {
+    let mut last_can = false;
+    let deadline = std::time::Instant::now() + START_TIMEOUT;
+    while std::time::Instant::now() < deadline {
+        match read_byte(port, BYTE_TIMEOUT, cancel) {
+            Ok(Some(CRC_START)) => return Ok(Check::Crc),
+            Ok(Some(NAK)) => return Ok(Check::Checksum),
+            Ok(Some(CAN)) if last_can => return Err(TransferError::RemoteCancelled),
+            Ok(Some(byte)) => last_can = byte == CAN,
+            Ok(None) => {}
+            Err(err) => {
+                if matches!(err, TransferError::Cancelled) {
+                    cancel_remote(port);
+                }
+                return Err(err);
+            }
+        }
+    }
+    Err(TransferError::Timeout)
+}
+
+fn build_block(number: u8, payload: &[u8], size: usize, pad: u8, check: Check) -> Vec<u8> {
+    let mut block = Vec::with_capacity(size + 5);
+    block.push(if size == 1024 { STX } else { SOH });
+    block.push(number);
+    block.push(!number);
+    let start = block.len();
+    block.extend_from_slice(payload);
+    block.resize(start + size, pad);
+    match check {
+        Check::Crc => {
+            let crc = crc16(&block[start..]);
+            block.extend_from_slice(&crc.to_be_bytes());
+        }
+        Check::Checksum => {
+            let sum = block[start..]
+                .iter()
+                .fold(0_u8, |acc, &byte| acc.wrapping_add(byte));
+            block.push(sum);
+        }
+    }
+    block
+}
+
+/// Sends a single block until the receiver acknowledges it.
+fn send_block<P: Read + Write + ?Sized>(
+    port: &mut P,
+    block: &[u8],
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    let mut attempts = 0;
+    loop {
+        port.write_all(block)?;
+        port.flush()?;
+        let mut last_can = false;
+        let answer = loop {
+            match read_byte(port, BLOCK_TIMEOUT, cancel) {
+                Ok(Some(CAN)) if last_can => return Err(TransferError::RemoteCancelled),
+                Ok(Some(CAN)) => last_can = true,
+                Ok(Some(byte @ (ACK | NAK | CRC_START))) => break Some(byte),
+                Ok(Some(_)) => last_can = false,
+                Ok(None) => break None,
+                Err(err) => {
+                    if matches!(err, TransferError::Cancelled) {
+                        cancel_remote(port);
+                    }
+                    return Err(err);
+                }
+            }
+        };
+        if answer == Some(ACK) {
+            return Ok(());
+        }
+        attempts += 1;
+        progress.retries += 1;
+        report(progress);
+        if attempts >= MAX_RETRIES {
+            cancel_remote(port);
+            return Err(TransferError::TooManyRetries);
+        }
+    }
+}
+
+/// Sends EOT until the receiver acknowledges the end of the file. YMODEM receivers NAK the
+/// first one, which is handled like any other retry.
+fn send_eot<P: Read + Write + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<(), TransferError> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&[EOT])?;
+        port.flush()?;
+        if read_byte(port, BLOCK_TIMEOUT, cancel)? == Some(ACK) {
+            return Ok(());
+        }
+    }
+    Err(TransferError::TooManyRetries)
+}
+
+fn ymodem_header(path: &Path, size: u64) -> Vec<u8> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mtime = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let mut header = name.into_bytes();
+    header.push(0);
+    header.extend_from_slice(format!("{} {:o}", size, mtime).as_bytes());
+    header.push(0);
+    header
+}
+
+fn send_file_data<P: Read + Write + ?Sized>(
+    port: &mut P,
+    protocol: Protocol,
+    check: Check,
+    data: &[u8],
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    // 1K blocks require a CRC, fall back to 128 byte blocks for checksum receivers
+    let block_size = if check == Check::Crc {
+        protocol.block_size()
+    } else {
+        128
+    };
+    let mut number = 1_u8;
+    let mut offset = 0;
+    while offset < data.len() {
+        let remaining = data.len() - offset;
+        // don't pad a short tail out to a whole 1K block
+        let size = if remaining <= 128 { 128 } else { block_size };
+        let payload = &data[offset..offset + remaining.min(size)];
+        let block = build_block(number, payload, size, SUB, check);
+        send_block(port, &block, cancel, progress, report)?;
+        offset += payload.len();
+        number = number.wrapping_add(1);
+        progress.bytes = offset as u64;
+        report(progress);
+    }
+    send_eot(port, cancel)
+}
+
+pub fn send<P: Read + Write + ?Sized>(
+    port: &mut P,
+    protocol: Protocol,
+    files: &[PathBuf],
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    if files.is_empty() {
+        return Err(TransferError::Protocol("no file to send".to_owned()));
+    }
+    if protocol != Protocol::Ymodem && files.len() > 1 {
+        return Err(TransferError::Protocol(format!(
+            "{} sends a single file",
+            protocol.name()
+        )));
+    }
+
+    for path in files {
+        let data = fs::read(path)?;
+        progress.file = path.display().to_string();
+        progress.bytes = 0;
+        progress.total = Some(data.len() as u64);
+        report(progress);
+
+        let check = wait_for_start(port, cancel)?;
+        if protocol == Protocol::Ymodem {
+            let header = ymodem_header(path, data.len() as u64);
+            // a long name and size need a 1K block 0, which every YMODEM receiver accepts
+            let size = match header.len() {
+                0..=128 => 128,
+                129..=1024 => 1024,
+                _ => {
+                    cancel_remote(port);
+                    return Err(TransferError::Protocol(format!(
+                        "{}: the name is too long for a YMODEM header",
+                        path.display()
+                    )));
+                }
+            };
+            let header = build_block(0, &header, size, 0, Check::Crc);
+            send_block(port, &header, cancel, progress, report)?;
+            wait_for_start(port, cancel)?;
+        }
+        send_file_data(port, protocol, check, &data, cancel, progress, report)?;
+    }
+
+    if protocol == Protocol::Ymodem {
+        // an empty block 0 closes the batch
+        wait_for_start(port, cancel)?;
+        let end = build_block(0, &[], 128, 0, Check::Crc);
+        send_block(port, &end, cancel, progress, report)?;
+    }
+    Ok(())
+}
+
+enum Received {
+    Block(u8, Vec<u8>),
+    Eot,
+    Cancelled,
+    Bad,
+    Timeout,
+}
+
+fn read_block<P: Read + Write + ?Sized>(
+    port: &mut P,
+    check: Check,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<Received, TransferError> {
+    let size = match read_byte(port, timeout, cancel)? {
+        Some(SOH) => 128,
+        Some(STX) => 1024,
+        Some(EOT) => return Ok(Received::Eot),
+        Some(CAN) => {
+            return Ok(if read_byte(port, BYTE_TIMEOUT, cancel)? == Some(CAN) {
+                Received::Cancelled
+            } else {
+                Received::Bad
+            });
+        }
+        Some(_) => return Ok(Received::Bad),
+        None => return Ok(Received::Timeout),
+    };
+    let trailer = if check == Check::Crc { 2 } else { 1 };
+    let mut rest = vec![0_u8; 2 + size + trailer];
+    if !read_exact(port, &mut rest, BYTE_TIMEOUT, cancel)? {
+        return Ok(Received::Bad);
+    }
+    if rest[0] != !rest[1] {
+        return Ok(Received::Bad);
+    }
+    let payload = &rest[2..2 + size];
+    let valid = match check {
+        Check::Crc => crc16(payload).to_be_bytes() == rest[2 + size..],
+        Check::Checksum => {
+            payload
+                .iter()
+                .fold(0_u8, |acc, &byte| acc.wrapping_add(byte))
+                == rest[2 + size]
+        }
+    };
+    if !valid {
+        return Ok(Received::Bad);
+    }
+    Ok(Received::Block(rest[0], payload.to_vec()))
+}
+
+/// Receives blocks until the expected one arrives, acknowledging duplicates and asking for
+/// retransmission of damaged ones. `start` is sent while waiting for the first block.
+fn receive_block<P: Read + Write + ?Sized>(
+    port: &mut P,
+    check: Check,
+    expected: u8,
+    start: Option<u8>,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<Option<Vec<u8>>, TransferError> {
+    let mut errors = 0;
+    let timeout = if start.is_some() {
+        START_INTERVAL
+    } else {
+        BLOCK_TIMEOUT
+    };
+    let max_errors = if start.is_some() {
+        (START_TIMEOUT.as_secs() / START_INTERVAL.as_secs()) as u32
+    } else {
+        MAX_RETRIES
+    };
+    if let Some(byte) = start {
+        port.write_all(&[byte])?;
+        port.flush()?;
+    }
+    loop {
+        let received = match read_block(port, check, timeout, cancel) {
+            Err(TransferError::Cancelled) => {
+                cancel_remote(port);
+                return Err(TransferError::Cancelled);
+            }
+            other => other?,
+        };
+        let retry_with = match received {
+            Received::Block(number, payload) if number == expected => return Ok(Some(payload)),
+            Received::Block(number, _) if number == expected.wrapping_sub(1) => {
+                port.write_all(&[ACK])?;
+                port.flush()?;
+                continue;
+            }
+            Received::Block(number, _) => {
+                cancel_remote(port);
+                return Err(TransferError::Protocol(format!(
+                    "expected block {} but got {}",
+                    expected, number
+                )));
+            }
+            Received::Eot => return Ok(None),
+            Received::Cancelled => return Err(TransferError::RemoteCancelled),
+            Received::Bad => {
+                purge(port, BYTE_TIMEOUT, cancel)?;
+                NAK
+            }
+            Received::Timeout => start.unwrap_or(NAK),
+        };
+        errors += 1;
+        if start.is_none() {
+            progress.retries += 1;
+            report(progress);
+        }
+        if errors >= max_errors {
+            cancel_remote(port);
+            return Err(if start.is_some() {
+                TransferError::Timeout
+            } else {
+                TransferError::TooManyRetries
+            });
+        }
+        port.write_all(&[retry_with])?;
+        port.flush()?;
+    }
+}
+
+/// Receives file data blocks up to the final EOT and writes them to `out`.
+fn receive_file_data<P: Read + Write + ?Sized>(
+    port: &mut P,
+    protocol: Protocol,
+    mut start: Option<u8>,
+    out: &mut File,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    let check = protocol.check();
+    let mut expected = 1_u8;
+    // the last block is held back so its SUB padding can be stripped or the YMODEM size applied
+    let mut held: Option<Vec<u8>> = None;
+    loop {
+        match receive_block(port, check, expected, start.take(), cancel, progress, report)? {
+            Some(payload) => {
+                if let Some(previous) = held.replace(payload) {
+                    out.write_all(&previous)?;
+                    progress.bytes += previous.len() as u64;
+                }
+                port.write_all(&[ACK])?;
+                port.flush()?;
+                expected = expected.wrapping_add(1);
+                report(progress);
+            }
+            None => {
+                if protocol == Protocol::Ymodem {
+                    // YMODEM senders expect the first EOT to be NAKed
+                    port.write_all(&[NAK])?;
+                    port.flush()?;
+                    if read_byte(port, BLOCK_TIMEOUT, cancel)? != Some(EOT) {
+                        return Err(TransferError::Protocol("missing second EOT".to_owned()));
+                    }
+                }
+                port.write_all(&[ACK])?;
+                port.flush()?;
+                break;
+            }
+        }
+    }
+    if let Some(mut last) = held {
+        match progress.total {
+            Some(total) => last.truncate(total.saturating_sub(progress.bytes) as usize),
+            None => {
+                while last.last() == Some(&SUB) {
+                    last.pop();
+                }
+            }
+        }
+        out.write_all(&last)?;
+        progress.bytes += last.len() as u64;
+    }
+    out.flush()?;
+    report(progress);
+    Ok(())
+}
+
+pub fn receive<P: Read + Write + ?Sized>(
+    port: &mut P,
+    protocol: Protocol,
+    dir: &Path,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    fs::create_dir_all(dir)?;
+    let check = protocol.check();
+    let start = if check == Check::Crc { CRC_START } else { NAK };
+
+    if protocol != Protocol::Ymodem {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let path = unique_path(dir, &format!("xmodem-{}.bin", stamp));
+        progress.file = path.display().to_string();
+        report(progress);
+        let mut out = File::create(&path)?;
+        return receive_file_data(
+            port,
+            protocol,
+            Some(start),
+            &mut out,
+            cancel,
+            progress,
+            report,
+        );
+    }
+
+    loop {
+        let header = match receive_block(port, check, 0, Some(start), cancel, progress, report)? {
+            Some(header) => header,
+            None => return Err(TransferError::Protocol("unexpected EOT".to_owned())),
+        };
+        port.write_all(&[ACK])?;
+        port.flush()?;
+        let mut fields = header.split(|&byte| byte == 0);
+        let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+        if name.is_empty() {
+            // end of the batch
+            return Ok(());
+        }
+        let total = fields
+            .next()
+            .and_then(|info| String::from_utf8_lossy(info).split(' ').next().map(str::to_owned))
+            .and_then(|size| size.parse::<u64>().ok());
+
+        let path = unique_path(dir, &name);
+        progress.file = path.display().to_string();
+        progress.bytes = 0;
+        progress.total = total;
+        report(progress);
+        let mut out = File::create(&path)?;
+        receive_file_data(
+            port,
+            protocol,
+            Some(start),
+            &mut out,
+            cancel,
+            progress,
+            report,
+        )?;
+    }
+}
@@ -0,0 +1,825 @@
+//! ZMODEM transfers, compatible with lrzsz's `sz` and `rz`.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use super::{
+    crc16, purge, read_byte, read_exact, unique_path, TransferError, TransferProgress,
+};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+
+// data subpacket terminators
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT capabilities
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+
+// ZFILE conversion options
+/// Binary transfer, the file is written as it is sent.
+const ZCBIN: u8 = 1;
+/// Asks the receiver to resume an interrupted transfer.
+const ZCRESUM: u8 = 3;
+
+const SUBPACKET_SIZE: usize = 1024;
+const MAX_SUBPACKET_SIZE: usize = 8192;
+const MAX_RETRIES: u32 = 10;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `ZPAD ZPAD ZDLE ZHEX` and the hex encoded ZRQINIT frame type, sent by `sz` to start a
+/// download on our side.
+const INIT_SEQUENCE: &[u8] = b"**\x18B00";
+
+/// Returns where a ZRQINIT header starts in `buf`, if there is one.
+pub fn find_init(buf: &[u8]) -> Option<usize> {
+    buf.windows(INIT_SEQUENCE.len())
+        .position(|window| window == INIT_SEQUENCE)
+}
+
+#[derive(Clone, Copy)]
+struct Header {
+    kind: u8,
+    data: [u8; 4],
+}
+
+impl Header {
+    fn position(kind: u8, position: u64) -> Header {
+        Header {
+            kind,
+            data: (position as u32).to_le_bytes(),
+        }
+    }
+
+    /// A header carrying `ZF0` (the last data byte on the wire).
+    fn flags(kind: u8, zf0: u8) -> Header {
+        Header {
+            kind,
+            data: [0, 0, 0, zf0],
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        u64::from(u32::from_le_bytes(self.data))
+    }
+
+    fn zf0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn raw(&self) -> [u8; 5] {
+        [
+            self.kind,
+            self.data[0],
+            self.data[1],
+            self.data[2],
+            self.data[3],
+        ]
+    }
+}
+
+/// CRC-32 as used by ZMODEM (the IEEE 802.3 one).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn escape_into(out: &mut Vec<u8>, byte: u8) {
+    match byte {
+        ZDLE | 0x10 | 0x90 | XON | 0x91 | XOFF | 0x93 => {
+            out.push(ZDLE);
+            out.push(byte ^ 0x40);
+        }
+        _ => out.push(byte),
+    }
+}
+
+fn write_hex_header<P: Write + ?Sized>(port: &mut P, header: Header) -> std::io::Result<()> {
+    let raw = header.raw();
+    let mut out = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+    for byte in raw.iter().chain(crc16(&raw).to_be_bytes().iter()) {
+        out.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+    out.extend_from_slice(&[b'\r', b'\n' | 0x80]);
+    if header.kind != ZACK && header.kind != ZFIN {
+        out.push(XON);
+    }
+    port.write_all(&out)?;
+    port.flush()
+}
+
+fn write_bin_header<P: Write + ?Sized>(
+    port: &mut P,
+    header: Header,
+    use_crc32: bool,
+) -> std::io::Result<()> {
+    let raw = header.raw();
+    let mut out = vec![ZPAD, ZDLE, if use_crc32 { ZBIN32 } else { ZBIN }];
+    for &byte in raw.iter() {
+        escape_into(&mut out, byte);
+    }
+    if use_crc32 {
+        for byte in crc32(&raw).to_le_bytes() {
+            escape_into(&mut out, byte);
+        }
+    } else {
+        for byte in crc16(&raw).to_be_bytes() {
+            escape_into(&mut out, byte);
+        }
+    }
+    port.write_all(&out)?;
+    port.flush()
+}
+
+fn write_subpacket<P: Write + ?Sized>(
+    port: &mut P,
+    data: &[u8],
+    end: u8,
+    use_crc32: bool,
+) -> std::io::Result<()> {
+    let mut out = Vec::with_capacity(data.len() * 2 + 8);
+    for &byte in data {
+        escape_into(&mut out, byte);
+    }
+    out.push(ZDLE);
+    out.push(end);
+    let mut covered = data.to_vec();
+    covered.push(end);
+    if use_crc32 {
+        for byte in crc32(&covered).to_le_bytes() {
+            escape_into(&mut out, byte);
+        }
+    } else {
+        for byte in crc16(&covered).to_be_bytes() {
+            escape_into(&mut out, byte);
+        }
+    }
+    if end == ZCRCW {
+        out.push(XON);
+    }
+    port.write_all(&out)?;
+    port.flush()
+}
+
+fn cancel_remote<P: Write + ?Sized>(port: &mut P) {
+    // eight CANs followed by as many backspaces, like lrzsz
+    let mut abort = vec![ZDLE; 8];
+    abort.extend_from_slice(&[0x08; 8]);
+    let _ = port.write_all(&abort);
+    let _ = port.flush();
+}
+
+/// Timeouts and damaged frames are recovered from by asking the other side again.
+fn recoverable(err: &TransferError) -> bool {
+    matches!(err, TransferError::Timeout | TransferError::Protocol(_))
+}
+
+fn read_raw<P: Read + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<u8, TransferError> {
+    read_byte(port, timeout, cancel)?.ok_or(TransferError::Timeout)
+}
+
+enum Escaped {
+    Byte(u8),
+    End(u8),
+}
+
+/// Reads one byte of a ZDLE encoded stream, dropping flow control characters on the way.
+fn read_escaped<P: Read + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<Escaped, TransferError> {
+    loop {
+        match read_raw(port, BYTE_TIMEOUT, cancel)? {
+            XON | XOFF | 0x91 | 0x93 => {}
+            ZDLE => break,
+            byte => return Ok(Escaped::Byte(byte)),
+        }
+    }
+    let mut cancels = 1;
+    loop {
+        match read_raw(port, BYTE_TIMEOUT, cancel)? {
+            XON | XOFF | 0x91 | 0x93 => {}
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(TransferError::RemoteCancelled);
+                }
+            }
+            end @ (ZCRCE | ZCRCG | ZCRCQ | ZCRCW) => return Ok(Escaped::End(end)),
+            ZRUB0 => return Ok(Escaped::Byte(0x7f)),
+            ZRUB1 => return Ok(Escaped::Byte(0xff)),
+            byte if byte & 0x60 == 0x40 => return Ok(Escaped::Byte(byte ^ 0x40)),
+            byte => {
+                return Err(TransferError::Protocol(format!(
+                    "bad escape sequence {:#04x}",
+                    byte
+                )))
+            }
+        }
+    }
+}
+
+fn read_escaped_byte<P: Read + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<u8, TransferError> {
+    match read_escaped(port, cancel)? {
+        Escaped::Byte(byte) => Ok(byte),
+        Escaped::End(_) => Err(TransferError::Protocol("unexpected frame end".to_owned())),
+    }
+}
+
+fn read_hex_byte<P: Read + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<u8, TransferError> {
+    let mut digits = [0_u8; 2];
+    if !read_exact(port, &mut digits, BYTE_TIMEOUT, cancel)? {
+        return Err(TransferError::Timeout);
+    }
+    let digits = [digits[0] & 0x7f, digits[1] & 0x7f];
+    std::str::from_utf8(&digits)
+        .ok()
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| TransferError::Protocol("bad hex header".to_owned()))
+}
+
+/// Waits up to `timeout` for a header to start, and for the rest of one that has. The returned
+/// flag tells whether the frame that follows uses 32 bit CRCs.
+fn read_header<P: Read + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<(Header, bool), TransferError> {
+    let mut deadline = Instant::now() + timeout;
+    let mut seen_pad = false;
+    let mut cancels = 0;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let byte = match read_byte(port, remaining, cancel)? {
+            Some(byte) => byte,
+            None => return Err(TransferError::Timeout),
+        };
+        match byte {
+            ZPAD | 0xaa => {
+                seen_pad = true;
+                cancels = 0;
+                deadline = deadline.max(Instant::now() + BYTE_TIMEOUT);
+            }
+            ZDLE if seen_pad => break,
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(TransferError::RemoteCancelled);
+                }
+            }
+            _ => {
+                seen_pad = false;
+                cancels = 0;
+            }
+        }
+    }
+
+    let mut raw = [0_u8; 5];
+    let (use_crc32, valid) = match read_raw(port, BYTE_TIMEOUT, cancel)? & 0x7f {
+        ZHEX => {
+            for byte in raw.iter_mut() {
+                *byte = read_hex_byte(port, cancel)?;
+            }
+            let crc = [read_hex_byte(port, cancel)?, read_hex_byte(port, cancel)?];
+            // the CR LF trailer and XON are skipped by whoever reads next
+            (false, crc16(&raw).to_be_bytes() == crc)
+        }
+        ZBIN => {
+            for byte in raw.iter_mut() {
+                *byte = read_escaped_byte(port, cancel)?;
+            }
+            let crc = [
+                read_escaped_byte(port, cancel)?,
+                read_escaped_byte(port, cancel)?,
+            ];
+            (false, crc16(&raw).to_be_bytes() == crc)
+        }
+        ZBIN32 => {
+            for byte in raw.iter_mut() {
+                *byte = read_escaped_byte(port, cancel)?;
+            }
+            let mut crc = [0_u8; 4];
+            for byte in crc.iter_mut() {
+                *byte = read_escaped_byte(port, cancel)?;
+            }
+            (true, crc32(&raw).to_le_bytes() == crc)
+        }
+        format => {
+            return Err(TransferError::Protocol(format!(
+                "unknown header format {:#04x}",
+                format
+            )))
+        }
+    };
+    if !valid {
+        return Err(TransferError::Protocol("bad header CRC".to_owned()));
+    }
+    Ok((
+        Header {
+            kind: raw[0],
+            data: [raw[1], raw[2], raw[3], raw[4]],
+        },
+        use_crc32,
+    ))
+}
+
+/// Reads a data subpacket and returns it along with the terminator that ended it.
+fn read_subpacket<P: Read + ?Sized>(
+    port: &mut P,
+    use_crc32: bool,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, u8), TransferError> {
+    let mut data = Vec::with_capacity(SUBPACKET_SIZE);
+    let end = loop {
+        match read_escaped(port, cancel)? {
+            Escaped::Byte(byte) => {
+                if data.len() >= MAX_SUBPACKET_SIZE {
+                    return Err(TransferError::Protocol("subpacket too long".to_owned()));
+                }
+                data.push(byte);
+            }
+            Escaped::End(end) => break end,
+        }
+    };
+    data.push(end);
+    let valid = if use_crc32 {
+        let mut crc = [0_u8; 4];
+        for byte in crc.iter_mut() {
+            *byte = read_escaped_byte(port, cancel)?;
+        }
+        crc32(&data).to_le_bytes() == crc
+    } else {
+        let crc = [
+            read_escaped_byte(port, cancel)?,
+            read_escaped_byte(port, cancel)?,
+        ];
+        crc16(&data).to_be_bytes() == crc
+    };
+    data.pop();
+    if !valid {
+        return Err(TransferError::Protocol("bad data CRC".to_owned()));
+    }
+    Ok((data, end))
+}
+
+/// Opens the file a ZFILE announced. With `resume`, a shorter file of the same name is
+/// continued instead of starting a new one; the returned offset is where writing starts.
+fn open_target(
+    dir: &Path,
+    name: &str,
+    total: Option<u64>,
+    resume: bool,
+) -> std::io::Result<(PathBuf, File, u64)> {
+    if resume {
+        if let Some(file_name) = Path::new(name).file_name() {
+            let path = dir.join(file_name);
+            if let Ok(meta) = fs::metadata(&path) {
+                if meta.is_file() && total.is_some_and(|total| meta.len() < total) {
+                    let file = OpenOptions::new().append(true).open(&path)?;
+                    return Ok((path, file, meta.len()));
+                }
+            }
+        }
+    }
+    let path = unique_path(dir, name);
+    let file = File::create(&path)?;
+    Ok((path, file, 0))
+}
+
+pub fn receive<P: Read + Write + ?Sized>(
+    port: &mut P,
+    dir: &Path,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    let result = receive_files(port, dir, cancel, progress, report);
+    if matches!(result, Err(TransferError::Cancelled | TransferError::TooManyRetries)) {
+        cancel_remote(port);
+    }
+    result
+}
+
+fn receive_files<P: Read + Write + ?Sized>(
+    port: &mut P,
+    dir: &Path,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    fs::create_dir_all(dir)?;
+    let zrinit = Header::flags(ZRINIT, CANFDX | CANOVIO | CANFC32);
+    write_hex_header(port, zrinit)?;
+    // repeated whenever the sender goes quiet or a header arrives damaged
+    let mut last_sent = zrinit;
+    // the file being received and the name the sender gave it
+    let mut file: Option<(File, String)> = None;
+    let mut offset = 0_u64;
+    let mut errors = 0;
+
+    loop {
+        let (header, use_crc32) = match read_header(port, HEADER_TIMEOUT, cancel) {
+            Ok(header) => header,
+            Err(err) if recoverable(&err) => {
+                errors += 1;
+                progress.retries += 1;
+                report(progress);
+                if errors >= MAX_RETRIES {
+                    return Err(TransferError::TooManyRetries);
+                }
+                write_hex_header(port, last_sent)?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        match header.kind {
+            ZRQINIT => write_hex_header(port, zrinit)?,
+            ZSINIT => {
+                // the attention string is of no use to us
+                match read_subpacket(port, use_crc32, cancel) {
+                    Ok(_) => write_hex_header(port, Header::position(ZACK, 0))?,
+                    Err(err) if recoverable(&err) => {
+                        write_hex_header(port, Header::position(ZNAK, 0))?
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            ZFILE => {
+                let info = match read_subpacket(port, use_crc32, cancel) {
+                    Ok((info, _)) => info,
+                    Err(err) if recoverable(&err) => {
+                        write_hex_header(port, Header::position(ZNAK, 0))?;
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                let mut fields = info.split(|&byte| byte == 0);
+                let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+                let total = fields
+                    .next()
+                    .and_then(|rest| {
+                        String::from_utf8_lossy(rest)
+                            .split(' ')
+                            .next()
+                            .map(str::to_owned)
+                    })
+                    .and_then(|size| size.parse::<u64>().ok());
+
+                // the sender repeats ZFILE when it missed our answer, carry on with the same file
+                if file.as_ref().is_some_and(|(_, open)| *open == name) {
+                    last_sent = Header::position(ZRPOS, offset);
+                    write_hex_header(port, last_sent)?;
+                    continue;
+                }
+                let (path, out, start) =
+                    open_target(dir, &name, total, header.zf0() == ZCRESUM)?;
+                file = Some((out, name));
+                offset = start;
+                progress.file = path.display().to_string();
+                progress.bytes = start;
+                progress.total = total;
+                report(progress);
+                last_sent = Header::position(ZRPOS, offset);
+                write_hex_header(port, last_sent)?;
+            }
+            ZDATA => {
+                let Some((out, _)) = file.as_mut() else {
+                    write_hex_header(port, zrinit)?;
+                    continue;
+                };
+                if header.offset() != offset {
+                    purge(port, Duration::from_millis(100), cancel)?;
+                    last_sent = Header::position(ZRPOS, offset);
+                    write_hex_header(port, last_sent)?;
+                    continue;
+                }
+                loop {
+                    match read_subpacket(port, use_crc32, cancel) {
+                        Ok((data, end)) => {
+                            out.write_all(&data)?;
+                            offset += data.len() as u64;
+                            progress.bytes = offset;
+                            report(progress);
+                            errors = 0;
+                            match end {
+                                ZCRCW => {
+                                    write_hex_header(port, Header::position(ZACK, offset))?;
+                                    break;
+                                }
+                                ZCRCQ => write_hex_header(port, Header::position(ZACK, offset))?,
+                                ZCRCG => {}
+                                _ => break,
+                            }
+                        }
+                        Err(err) if recoverable(&err) => {
+                            errors += 1;
+                            progress.retries += 1;
+                            report(progress);
+                            if errors >= MAX_RETRIES {
+                                return Err(TransferError::TooManyRetries);
+                            }
+                            purge(port, Duration::from_millis(100), cancel)?;
+                            last_sent = Header::position(ZRPOS, offset);
+                            write_hex_header(port, last_sent)?;
+                            break;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            // an EOF for another offset is stale, the sender will act on our ZRPOS
+            ZEOF if header.offset() == offset => {
+                if let Some((mut out, _)) = file.take() {
+                    out.flush()?;
+                }
+                last_sent = zrinit;
+                write_hex_header(port, zrinit)?;
+            }
+            ZFIN => {
+                write_hex_header(port, Header::position(ZFIN, 0))?;
+                // "over and out", behind the trailer of the sender's ZFIN. Not worth failing for.
+                let mut seen = 0;
+                while seen < 2 {
+                    match read_byte(port, BYTE_TIMEOUT, cancel) {
+                        Ok(Some(b'O')) => seen += 1,
+                        Ok(Some(_)) => {}
+                        _ => break,
+                    }
+                }
+                return Ok(());
+            }
+            ZABORT | ZFERR | ZCAN => return Err(TransferError::RemoteCancelled),
+            _ => {}
+        }
+    }
+}
+
+fn file_info(path: &Path, size: u64, files_left: usize, bytes_left: u64) -> Vec<u8> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mtime = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let mut info = name.into_bytes();
+    info.push(0);
+    info.extend_from_slice(
+        format!("{} {:o} 0 0 {} {}", size, mtime, files_left, bytes_left).as_bytes(),
+    );
+    info.push(0);
+    info
+}
+
+/// Checks, without waiting for one to start, whether the receiver sent a header while we are
+/// streaming data.
+fn poll_header<P: Read + ?Sized>(
+    port: &mut P,
+    cancel: &AtomicBool,
+) -> Result<Option<Header>, TransferError> {
+    match read_header(port, Duration::ZERO, cancel) {
+        Ok((header, _)) => Ok(Some(header)),
+        Err(err) if recoverable(&err) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Streams a file from `start` until the receiver acknowledges its end with ZRINIT.
+fn send_data<P: Read + Write + ?Sized>(
+    port: &mut P,
+    data: &[u8],
+    start: u64,
+    use_crc32: bool,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    let len = data.len();
+    let mut pos = (start as usize).min(len);
+    let mut errors = 0;
+    let mut reposition = |pos: &mut usize, header: Header, progress: &mut TransferProgress| {
+        *pos = (header.offset() as usize).min(len);
+        errors += 1;
+        progress.retries += 1;
+        if errors >= MAX_RETRIES {
+            Err(TransferError::TooManyRetries)
+        } else {
+            Ok(())
+        }
+    };
+
+    'frame: loop {
+        write_bin_header(port, Header::position(ZDATA, pos as u64), use_crc32)?;
+        while pos < len {
+            let end = (pos + SUBPACKET_SIZE).min(len);
+            let terminator = if end == len { ZCRCE } else { ZCRCG };
+            write_subpacket(port, &data[pos..end], terminator, use_crc32)?;
+            pos = end;
+            progress.bytes = pos as u64;
+            report(progress);
+            if let Some(header) = poll_header(port, cancel)? {
+                match header.kind {
+                    ZRPOS => {
+                        reposition(&mut pos, header, progress)?;
+                        report(progress);
+                        continue 'frame;
+                    }
+                    ZABORT | ZFERR | ZCAN => return Err(TransferError::RemoteCancelled),
+                    _ => {}
+                }
+            }
+        }
+        if len == 0 {
+            write_subpacket(port, &[], ZCRCE, use_crc32)?;
+        }
+
+        let mut attempts = 0;
+        loop {
+            write_bin_header(port, Header::position(ZEOF, len as u64), use_crc32)?;
+            match read_header(port, HEADER_TIMEOUT, cancel) {
+                Ok((header, _)) => match header.kind {
+                    ZRINIT | ZSKIP => return Ok(()),
+                    ZRPOS => {
+                        reposition(&mut pos, header, progress)?;
+                        report(progress);
+                        continue 'frame;
+                    }
+                    ZABORT | ZFERR | ZCAN => return Err(TransferError::RemoteCancelled),
+                    _ => {}
+                },
+                Err(err) if recoverable(&err) => {}
+                Err(err) => return Err(err),
+            }
+            attempts += 1;
+            if attempts >= MAX_RETRIES {
+                return Err(TransferError::TooManyRetries);
+            }
+        }
+    }
+}
+
+/// Uploads `files`. With `resume` the receiver is asked to append to shorter files it already
+/// has, otherwise they are replaced.
+pub fn send<P: Read + Write + ?Sized>(
+    port: &mut P,
+    files: &[PathBuf],
+    resume: bool,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    let result = send_files(port, files, resume, cancel, progress, report);
+    if matches!(result, Err(TransferError::Cancelled | TransferError::TooManyRetries)) {
+        cancel_remote(port);
+    }
+    result
+}
+
+fn send_files<P: Read + Write + ?Sized>(
+    port: &mut P,
+    files: &[PathBuf],
+    resume: bool,
+    cancel: &AtomicBool,
+    progress: &mut TransferProgress,
+    report: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), TransferError> {
+    if files.is_empty() {
+        return Err(TransferError::Protocol("no file to send".to_owned()));
+    }
+    // starts rz on a shell prompt, harmless if it is already waiting
+    port.write_all(b"rz\r")?;
+    let mut attempts = 0;
+    let use_crc32 = loop {
+        write_hex_header(port, Header::position(ZRQINIT, 0))?;
+        match read_header(port, HEADER_TIMEOUT, cancel) {
+            Ok((header, _)) if header.kind == ZRINIT => break header.zf0() & CANFC32 != 0,
+            Ok((header, _)) if matches!(header.kind, ZABORT | ZFERR | ZCAN) => {
+                return Err(TransferError::RemoteCancelled)
+            }
+            Ok(_) => {}
+            Err(err) if recoverable(&err) => {}
+            Err(err) => return Err(err),
+        }
+        attempts += 1;
+        if attempts >= MAX_RETRIES {
+            return Err(TransferError::Timeout);
+        }
+    };
+
+    let sizes = files
+        .iter()
+        .map(|path| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0))
+        .collect::<Vec<_>>();
+    for (idx, path) in files.iter().enumerate() {
+        let data = fs::read(path)?;
+        progress.file = path.display().to_string();
+        progress.bytes = 0;
+        progress.total = Some(data.len() as u64);
+        report(progress);
+
+        let info = file_info(
+            path,
+            data.len() as u64,
+            files.len() - idx,
+            sizes[idx..].iter().sum(),
+        );
+        let conversion = if resume { ZCRESUM } else { ZCBIN };
+        let mut attempts = 0;
+        let start = loop {
+            write_bin_header(port, Header::flags(ZFILE, conversion), use_crc32)?;
+            write_subpacket(port, &info, ZCRCW, use_crc32)?;
+            match read_header(port, HEADER_TIMEOUT, cancel) {
+                Ok((header, _)) => match header.kind {
+                    ZRPOS => break Some(header.offset()),
+                    ZSKIP => break None,
+                    ZABORT | ZFERR | ZCAN => return Err(TransferError::RemoteCancelled),
+                    _ => {}
+                },
+                Err(err) if recoverable(&err) => {}
+                Err(err) => return Err(err),
+            }
+            attempts += 1;
+            if attempts >= MAX_RETRIES {
+                return Err(TransferError::TooManyRetries);
+            }
+        };
+        if let Some(start) = start {
+            send_data(port, &data, start, use_crc32, cancel, progress, report)?;
+        }
+    }
+
+    for _ in 0..MAX_RETRIES {
+        write_hex_header(port, Header::position(ZFIN, 0))?;
+        match read_header(port, HEADER_TIMEOUT, cancel) {
+            Ok((header, _)) if header.kind == ZFIN => {
+                // the receiver may already be gone, which is fine at this point
+                let _ = port.write_all(b"OO");
+                let _ = port.flush();
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(err) if recoverable(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    // every file made it, a receiver that never answers ZFIN is not worth failing for
+    Ok(())
+}
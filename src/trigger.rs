@@ -0,0 +1,312 @@
+use std::{
+    fs, io,
+    process::{Command, Stdio},
+    thread,
+};
+
+use glob::Pattern;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::serial::{identity, utils::check_flow};
+
+/// Where triggers are read from, relative to the working directory.
+pub const TRIGGER_FILE: &str = "triggers.toml";
+
+/// A triggers file, for example
+///
+/// ```toml
+/// [[trigger]]
+/// name = "stop autoboot"
+/// on = 'Hit any key to stop autoboot'
+/// send = " "
+///
+/// [[trigger]]
+/// on = 'Guru Meditation'
+/// ports = "/dev/ttyUSB*"
+/// pause = true
+/// bell = true
+/// mark = true
+///
+/// [[trigger]]
+/// on = 'login: $'
+/// control = "r1:d0:s1000:d1:r0"
+/// run = "notify-send rebooted"
+/// ```
+#[derive(Deserialize)]
+struct TriggersFile {
+    #[serde(default)]
+    trigger: Vec<TriggerConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TriggerConfig {
+    name: Option<String>,
+    /// Matched against every line received, and against the start of a line that is slow to end,
+    /// like a prompt. A trigger fires once for a line.
+    on: String,
+    /// Ports the trigger applies to, all of them when left out.
+    ports: Option<String>,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    /// `$1`, `${name}` and so on are replaced by what the regex captured.
+    send: Option<String>,
+    #[serde(default)]
+    pause: bool,
+    log: Option<LogAction>,
+    #[serde(default)]
+    bell: bool,
+    #[serde(default)]
+    mark: bool,
+    /// A control line sequence like `r1:d0:s1000:d1:r0`: `d` and `r` set DTR and RTS, `s` sleeps
+    /// for the given milliseconds.
+    control: Option<String>,
+    /// Run with `sh`, with the line in `$DETERM_LINE` and the port in `$DETERM_PORT`.
+    run: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogAction {
+    Start,
+    Stop,
+}
+
+#[derive(Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub on: Regex,
+    ports: Option<Pattern>,
+    pub enabled: bool,
+    send: Option<String>,
+    pub pause: bool,
+    pub log: Option<LogAction>,
+    pub bell: bool,
+    pub mark: bool,
+    control: Option<String>,
+    run: Option<String>,
+    /// How often the trigger matched since it was loaded.
+    pub fired: u64,
+    /// The profile the trigger came with, it only fires on ports with that profile.
+    pub profile: Option<String>,
+}
+
+/// What a firing trigger leaves to the main loop.
+pub enum TriggerAction {
+    Send(String),
+    Control(String),
+    Pause,
+    /// A command and the line that started it.
+    Run(String, String),
+}
+
+impl Trigger {
+    pub fn new(config: TriggerConfig) -> Result<Trigger, String> {
+        let on = Regex::new(&config.on).map_err(|err| err.to_string())?;
+        let ports = config
+            .ports
+            .as_deref()
+            .map(Pattern::new)
+            .transpose()
+            .map_err(|err| err.to_string())?;
+        if let Some(control) = &config.control {
+            check_flow(control)?;
+        }
+        Ok(Trigger {
+            name: config.name.unwrap_or_else(|| config.on.clone()),
+            on,
+            ports,
+            enabled: config.enabled,
+            send: config.send,
+            pause: config.pause,
+            log: config.log,
+            bell: config.bell,
+            mark: config.mark,
+            control: config.control,
+            run: config.run,
+            fired: 0,
+            profile: None,
+        })
+    }
+
+    /// Whether the trigger fires on `port_name`.
+    pub fn applies_to(&self, port_name: &str, kernel_name: Option<&str>) -> bool {
+        self.ports
+            .as_ref()
+            .is_none_or(|pattern| identity::glob_matches(pattern, port_name, kernel_name))
+    }
+
+    /// What the trigger does, for the list of triggers and the note when it fires.
+    pub fn describe(&self) -> String {
+        let mut actions = Vec::new();
+        if let Some(send) = &self.send {
+            actions.push(format!("send {:?}", send));
+        }
+        if self.pause {
+            actions.push("pause".to_owned());
+        }
+        match self.log {
+            Some(LogAction::Start) => actions.push("start logging".to_owned()),
+            Some(LogAction::Stop) => actions.push("stop logging".to_owned()),
+            None => {}
+        }
+        if self.bell {
+            actions.push("bell".to_owned());
+        }
+        if self.mark {
+            actions.push("mark".to_owned());
+        }
+        if let Some(control) = &self.control {
+            actions.push(format!("control {}", control));
+        }
+        if let Some(run) = &self.run {
+            actions.push(format!("run {}", run));
+        }
+        actions.join(", ")
+    }
+
+    /// Checks `line` from `port_name`, also known as `kernel_name`, which has the profile called
+    /// `profile`. When the trigger fires, the actions the UI doesn't take care of by itself are
+    /// returned.
+    pub fn fire(
+        &mut self,
+        port_name: &str,
+        kernel_name: Option<&str>,
+        profile: Option<&str>,
+        line: &str,
+    ) -> Option<Vec<TriggerAction>> {
+        if !self.enabled
+            || !self.applies_to(port_name, kernel_name)
+            || self.profile.as_deref().is_some_and(|name| Some(name) != profile)
+        {
+            return None;
+        }
+        let captures = self.on.captures(line)?;
+        self.fired += 1;
+        let mut actions = Vec::new();
+        if let Some(send) = &self.send {
+            let mut text = String::new();
+            captures.expand(send, &mut text);
+            actions.push(TriggerAction::Send(text));
+        }
+        if let Some(control) = &self.control {
+            actions.push(TriggerAction::Control(control.clone()));
+        }
+        if self.pause {
+            actions.push(TriggerAction::Pause);
+        }
+        if let Some(command) = &self.run {
+            actions.push(TriggerAction::Run(command.clone(), line.to_owned()));
+        }
+        Some(actions)
+    }
+}
+
+/// Starts a trigger's command for `line` from `port_name`, in the background.
+pub fn run_command(command: &str, port_name: &str, line: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("DETERM_LINE", line)
+        .env("DETERM_PORT", port_name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // nobody would collect it otherwise
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Reads the triggers file in the working directory, which doesn't have to exist. Triggers can
+/// also come with the config file, see `config::Config`.
+pub fn load() -> io::Result<Vec<Trigger>> {
+    let source = match fs::read_to_string(TRIGGER_FILE) {
+        Ok(source) => source,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let invalid = |err: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", TRIGGER_FILE, err),
+        )
+    };
+    let file: TriggersFile =
+        toml::from_str(&source).map_err(|err| invalid(err.message().to_owned()))?;
+    file.trigger
+        .into_iter()
+        .map(|config| Trigger::new(config).map_err(invalid))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(source: &str) -> Result<Trigger, String> {
+        Trigger::new(toml::from_str::<TriggerConfig>(source).unwrap())
+    }
+
+    #[test]
+    fn send_gets_the_captures() {
+        let mut trigger = trigger(
+            r#"
+on = 'reset (\w+) in (?P<secs>\d+)s'
+send = "ack $1 ${secs}\r\n"
+pause = true
+"#,
+        )
+        .unwrap();
+        let actions = trigger.fire("virtual:loopback", None, None, "reset board in 5s");
+        assert!(matches!(
+            actions.as_deref(),
+            Some([TriggerAction::Send(text), TriggerAction::Pause]) if text == "ack board 5\r\n"
+        ));
+        assert!(trigger.fire("virtual:loopback", None, None, "ready").is_none());
+        assert_eq!(trigger.fired, 1);
+    }
+
+    #[test]
+    fn fires_on_the_ports_it_names() {
+        let mut trigger = trigger("on = 'boot'\nports = '/dev/ttyUSB*'").unwrap();
+        assert!(trigger.fire("/dev/ttyUSB0", None, None, "boot").is_some());
+        // a USB adapter shown by its /dev/serial/by-id link
+        let by_id = "/dev/serial/by-id/usb-FTDI_FT232R-if00-port0";
+        assert!(trigger.fire(by_id, Some("/dev/ttyUSB1"), None, "boot").is_some());
+        assert!(trigger.fire("/dev/ttyACM0", None, None, "boot").is_none());
+    }
+
+    #[test]
+    fn fires_only_with_its_profile() {
+        let mut trigger = trigger("on = 'boot'").unwrap();
+        trigger.profile = Some("esp32".to_owned());
+        assert!(trigger.fire("/dev/ttyUSB0", None, Some("esp32"), "boot").is_some());
+        assert!(trigger.fire("/dev/ttyUSB0", None, Some("console"), "boot").is_none());
+        assert!(trigger.fire("/dev/ttyUSB0", None, None, "boot").is_none());
+    }
+
+    #[test]
+    fn a_disabled_trigger_does_not_fire() {
+        let mut trigger = trigger("on = 'boot'\nenabled = false").unwrap();
+        assert!(trigger.fire("/dev/ttyUSB0", None, None, "boot").is_none());
+        assert_eq!(trigger.fired, 0);
+    }
+
+    #[test]
+    fn control_sequences_are_checked() {
+        let mut good = trigger("on = 'boot'\ncontrol = 'r1:d0:s1000:d1:r0'").unwrap();
+        assert!(matches!(
+            good.fire("/dev/ttyUSB0", None, None, "boot").as_deref(),
+            Some([TriggerAction::Control(control)]) if control == "r1:d0:s1000:d1:r0"
+        ));
+        for bad in ["", "d2", "r1:", "s", "x1", "d0,r1", "s99999999999999999999"] {
+            let source = format!("on = 'boot'\ncontrol = '{}'", bad);
+            assert!(trigger(&source).is_err(), "{:?} was taken", bad);
+        }
+    }
+}
@@ -0,0 +1,930 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use ratatui::{
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use regex::Regex;
+use tui_textarea::TextArea;
+
+use crate::{
+    centered_rect,
+    script::{ScriptHandle, ScriptInfo},
+    serial::{
+        details::Section, fault::FaultConfig, repeat::ScheduleInfo, share::ShareInfo, tx::Pacing,
+    },
+    transfer::{Protocol, TransferProgress, TransferState},
+    trigger::Trigger,
+};
+
+fn popup_block(title: &str) -> Block<'_> {
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::LightGreen))
+        .style(Style::default().bg(Color::Black).fg(Color::White))
+        .title(format!("╮ {} ╭", title))
+}
+
+pub struct TransferSetup {
+    pub path: TextArea<'static>,
+    pub protocol: usize,
+    pub receive: bool,
+    /// Only ZMODEM uploads can resume, see `TransferRequest::resume`.
+    pub resume: bool,
+}
+
+impl TransferSetup {
+    pub fn new() -> TransferSetup {
+        TransferSetup {
+            path: TextArea::default(),
+            protocol: 0,
+            receive: false,
+            resume: false,
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        Protocol::ALL[self.protocol % Protocol::ALL.len()]
+    }
+}
+
+pub struct TransferView {
+    pub progress: TransferProgress,
+    pub cancel: Arc<AtomicBool>,
+}
+
+pub fn render_transfer_setup(frame: &mut Frame, area: Rect, setup: &mut TransferSetup) {
+    let popup = centered_rect(64, 8, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("file transfer");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(1),
+        ])
+        .split(inner);
+
+    let mut mode = vec![
+        Span::raw(if setup.receive { " receive " } else { " send " }),
+        Span::styled(
+            format!(" {} ", setup.protocol().name()),
+            Style::default().fg(Color::Black).bg(Color::LightGreen),
+        ),
+    ];
+    if !setup.receive && setup.protocol() == Protocol::Zmodem {
+        mode.push(Span::raw(if setup.resume {
+            " resuming partial files (Alt+r)"
+        } else {
+            " replacing existing files (Alt+r)"
+        }));
+    }
+    frame.render_widget(Paragraph::new(Line::from(mode)), rows[0]);
+    setup.path.set_block(Block::default().borders(Borders::ALL).title(if setup.receive {
+        "directory (empty for the download directory)"
+    } else if matches!(setup.protocol(), Protocol::Ymodem | Protocol::Zmodem) {
+        "files (separated by ;)"
+    } else {
+        "file"
+    }));
+    frame.render_widget(setup.path.widget(), rows[1]);
+    frame.render_widget(
+        Paragraph::new("Tab protocol · Shift+Tab send/receive · Enter start · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[2],
+    );
+}
+
+fn human_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+pub fn render_transfer_progress(frame: &mut Frame, area: Rect, view: &TransferView) {
+    let progress = &view.progress;
+    let popup = centered_rect(64, 8, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block(progress.protocol.name());
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(1),
+        ])
+        .split(inner);
+
+    frame.render_widget(Paragraph::new(progress.file.clone()), rows[0]);
+    let ratio = match progress.total {
+        Some(total) if total > 0 => (progress.bytes as f64 / total as f64).min(1.0),
+        _ => 0.0,
+    };
+    let amount = match progress.total {
+        Some(total) => format!("{} / {}", human_bytes(progress.bytes), human_bytes(total)),
+        None => human_bytes(progress.bytes),
+    };
+    frame.render_widget(
+        Gauge::default()
+            .gauge_style(Style::default().fg(Color::LightGreen).bg(Color::DarkGray))
+            .ratio(ratio)
+            .label(amount),
+        rows[1],
+    );
+    frame.render_widget(
+        Paragraph::new(format!("retries: {}", progress.retries)),
+        rows[2],
+    );
+    let color = match &progress.state {
+        TransferState::Done => Color::LightGreen,
+        TransferState::Failed(_) => Color::LightRed,
+        TransferState::Running | TransferState::Cancelled => Color::LightYellow,
+    };
+    frame.render_widget(
+        Paragraph::new(progress.state.to_string()).style(Style::default().fg(color)),
+        rows[3],
+    );
+    frame.render_widget(
+        Paragraph::new(if progress.state == TransferState::Running {
+            "Esc cancel"
+        } else {
+            "Enter/Esc close"
+        })
+        .alignment(Alignment::Right)
+        .style(Style::default().fg(Color::Gray)),
+        rows[4],
+    );
+}
+
+const PACING_FIELDS: [&str; 4] = [
+    "file to send (empty to only change the pacing)",
+    "delay between characters (ms)",
+    "delay between lines (ms)",
+    "wait for prompt before each line (regex, optional)",
+];
+
+pub struct PacingSetup {
+    pub fields: [TextArea<'static>; 4],
+    pub focus: usize,
+    pub error: Option<String>,
+}
+
+impl PacingSetup {
+    pub fn new(pacing: &Pacing) -> PacingSetup {
+        let prompt = pacing
+            .prompt
+            .as_ref()
+            .map(|prompt| prompt.as_str().to_owned())
+            .unwrap_or_default();
+        PacingSetup {
+            fields: [
+                TextArea::default(),
+                TextArea::new(vec![pacing.char_delay.as_millis().to_string()]),
+                TextArea::new(vec![pacing.line_delay.as_millis().to_string()]),
+                TextArea::new(vec![prompt]),
+            ],
+            focus: 0,
+            error: None,
+        }
+    }
+
+    pub fn focused(&mut self) -> &mut TextArea<'static> {
+        &mut self.fields[self.focus]
+    }
+
+    /// Returns the file to send, if one was given, and the pacing to send it with.
+    pub fn parse(&self) -> Result<(Option<PathBuf>, Pacing), String> {
+        let value = |idx: usize| self.fields[idx].lines()[0].trim().to_owned();
+        let millis = |idx: usize| {
+            let text = value(idx);
+            if text.is_empty() {
+                return Ok(Duration::ZERO);
+            }
+            text.parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("{}: not a number of milliseconds", PACING_FIELDS[idx]))
+        };
+        let prompt = value(3);
+        let pacing = Pacing {
+            char_delay: millis(1)?,
+            line_delay: millis(2)?,
+            prompt: if prompt.is_empty() {
+                None
+            } else {
+                Some(Regex::new(&prompt).map_err(|err| err.to_string())?)
+            },
+        };
+        let file = value(0);
+        Ok(((!file.is_empty()).then(|| PathBuf::from(file)), pacing))
+    }
+}
+
+pub fn render_pacing_setup(frame: &mut Frame, area: Rect, setup: &mut PacingSetup) {
+    let popup = centered_rect(70, 15, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("send file / pacing");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(1),
+        ])
+        .split(inner);
+
+    for (idx, field) in setup.fields.iter_mut().enumerate() {
+        let border = if idx == setup.focus {
+            Style::default().fg(Color::LightGreen)
+        } else {
+            Style::default()
+        };
+        field.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(border)
+                .title(PACING_FIELDS[idx]),
+        );
+        frame.render_widget(field.widget(), rows[idx]);
+    }
+    let hint = match &setup.error {
+        Some(error) => Paragraph::new(error.clone()).style(Style::default().fg(Color::LightRed)),
+        None => Paragraph::new("Tab next field · Enter apply · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+    };
+    frame.render_widget(hint, rows[4]);
+}
+
+pub struct ShareSetup {
+    pub address: TextArea<'static>,
+    pub read_only: bool,
+}
+
+impl ShareSetup {
+    /// A new share is only reachable from this machine and read-only, anything more has to be
+    /// asked for.
+    pub fn new(share: Option<&ShareInfo>) -> ShareSetup {
+        let (address, read_only) = match share {
+            Some(share) => (share.addr.to_string(), share.read_only),
+            None => ("127.0.0.1:4000".to_owned(), true),
+        };
+        ShareSetup {
+            address: TextArea::new(vec![address]),
+            read_only,
+        }
+    }
+
+    /// Whether the address only lets in clients on this machine.
+    fn is_local(&self) -> bool {
+        let address = self.address.lines()[0].trim();
+        address
+            .parse::<SocketAddr>()
+            .is_ok_and(|addr| addr.ip().is_loopback())
+            || address.starts_with("localhost:")
+    }
+}
+
+pub fn render_share_setup(
+    frame: &mut Frame,
+    area: Rect,
+    setup: &mut ShareSetup,
+    share: Option<&ShareInfo>,
+) {
+    let popup = centered_rect(64, 14, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("share over TCP");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+
+    let state = match share {
+        Some(share) => format!(
+            " listening on {}{} ",
+            share.addr,
+            if share.read_only { ", read-only" } else { "" }
+        ),
+        None => " not shared ".to_owned(),
+    };
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(
+                state,
+                Style::default().fg(Color::Black).bg(if share.is_some() {
+                    Color::LightGreen
+                } else {
+                    Color::Gray
+                }),
+            ),
+            Span::raw(if setup.read_only {
+                " clients may only read"
+            } else {
+                " clients may read and write"
+            }),
+        ])),
+        rows[0],
+    );
+    let title = if setup.is_local() {
+        Line::from("listen on")
+    } else {
+        Line::styled(
+            "listen on · reachable from other hosts",
+            Style::default().fg(Color::Yellow),
+        )
+    };
+    setup
+        .address
+        .set_block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(setup.address.widget(), rows[1]);
+
+    let clients = match share {
+        Some(share) if !share.clients.is_empty() => share
+            .clients
+            .iter()
+            .map(|addr| Line::from(format!("● {}", addr)))
+            .collect::<Vec<_>>(),
+        Some(_) => vec![Line::styled("no clients", Style::default().fg(Color::Gray))],
+        None => Vec::new(),
+    };
+    frame.render_widget(
+        Paragraph::new(clients).block(Block::default().title("clients")),
+        rows[2],
+    );
+    frame.render_widget(
+        Paragraph::new(if share.is_some() {
+            "Tab read-only · Enter restart · Del stop sharing · Esc close"
+        } else {
+            "Tab read-only · Enter share · Esc close"
+        })
+        .style(Style::default().fg(Color::Gray)),
+        rows[3],
+    );
+}
+
+pub struct TeeSetup {
+    pub command: TextArea<'static>,
+    pub feed_back: bool,
+}
+
+impl TeeSetup {
+    pub fn new(tee: Option<&(String, bool)>) -> TeeSetup {
+        let (command, feed_back) = tee.cloned().unwrap_or_default();
+        TeeSetup {
+            command: TextArea::new(vec![command]),
+            feed_back,
+        }
+    }
+}
+
+pub fn render_tee_setup(
+    frame: &mut Frame,
+    area: Rect,
+    setup: &mut TeeSetup,
+    tee: Option<&(String, bool)>,
+) {
+    let popup = centered_rect(72, 8, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("pipe into a command");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(1),
+        ])
+        .split(inner);
+
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(
+                if tee.is_some() { " attached " } else { " not attached " },
+                Style::default().fg(Color::Black).bg(if tee.is_some() {
+                    Color::LightGreen
+                } else {
+                    Color::Gray
+                }),
+            ),
+            Span::raw(if setup.feed_back {
+                " stdout is written to the port"
+            } else {
+                " stdout is shown"
+            }),
+        ])),
+        rows[0],
+    );
+    setup
+        .command
+        .set_block(Block::default().borders(Borders::ALL).title("sh -c"));
+    frame.render_widget(setup.command.widget(), rows[1]);
+    frame.render_widget(
+        Paragraph::new("Tab stdout to port · Enter run · Del stop · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[2],
+    );
+}
+
+pub fn render_script_picker(
+    frame: &mut Frame,
+    area: Rect,
+    scripts: &[ScriptInfo],
+    running: &[ScriptHandle],
+    state: &mut ListState,
+) {
+    let popup = centered_rect(72, 16, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("scripts");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(inner);
+
+    let items = if scripts.is_empty() {
+        vec![ListItem::new(Line::styled(
+            "no *.rhai scripts here or in scripts/",
+            Style::default().fg(Color::Gray),
+        ))]
+    } else {
+        scripts
+            .iter()
+            .map(|script| {
+                let mut spans = vec![Span::raw(script.name.clone())];
+                if let Some(key) = script.key {
+                    spans.push(Span::styled(
+                        format!("  F{}", key),
+                        Style::default().fg(Color::LightCyan),
+                    ));
+                }
+                if let Some(pattern) = &script.on_open {
+                    spans.push(Span::styled(
+                        format!("  on open {}", pattern),
+                        Style::default().fg(Color::Gray),
+                    ));
+                }
+                let ports = running
+                    .iter()
+                    .filter(|handle| handle.name == script.name)
+                    .map(|handle| handle.port.as_str())
+                    .collect::<Vec<_>>();
+                if !ports.is_empty() {
+                    spans.push(Span::styled(
+                        format!("  ● running on {}", ports.join(", ")),
+                        Style::default().fg(Color::LightGreen),
+                    ));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect()
+    };
+    frame.render_stateful_widget(
+        List::new(items).highlight_style(Style::default().fg(Color::Black).bg(Color::LightGreen)),
+        rows[0],
+        state,
+    );
+    frame.render_widget(
+        Paragraph::new("Enter run on this port · Del stop · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[1],
+    );
+}
+
+const REPEAT_FIELDS: [&str; 3] = [
+    "data (\\r \\n \\t \\xNN escapes)",
+    "every (ms)",
+    "times (empty for no limit)",
+];
+
+/// The periodic sends popup: a form to add one, and the list of all of them.
+pub struct RepeatSetup {
+    pub fields: [TextArea<'static>; 3],
+    /// One of the fields, or the list after them.
+    pub focus: usize,
+    pub list: ListState,
+    pub error: Option<String>,
+}
+
+impl RepeatSetup {
+    pub const LIST: usize = 3;
+
+    pub fn new() -> RepeatSetup {
+        RepeatSetup {
+            fields: [
+                TextArea::default(),
+                TextArea::new(vec!["1000".to_owned()]),
+                TextArea::default(),
+            ],
+            focus: 0,
+            list: ListState::default(),
+            error: None,
+        }
+    }
+
+    pub fn focused(&mut self) -> &mut TextArea<'static> {
+        &mut self.fields[self.focus.min(self.fields.len() - 1)]
+    }
+
+    /// Returns the data to send, how often and how many times.
+    pub fn parse(&self) -> Result<(String, Duration, Option<u64>), String> {
+        let value = |idx: usize| self.fields[idx].lines()[0].trim().to_owned();
+        let data = self.fields[0].lines()[0].clone();
+        if data.is_empty() {
+            return Err("nothing to send".to_owned());
+        }
+        let every = value(1)
+            .parse::<u64>()
+            .ok()
+            .filter(|&millis| millis > 0)
+            .map(Duration::from_millis)
+            .ok_or_else(|| format!("{}: not a number of milliseconds", REPEAT_FIELDS[1]))?;
+        let count = value(2);
+        let count = if count.is_empty() {
+            None
+        } else {
+            Some(
+                count
+                    .parse::<u64>()
+                    .map_err(|_| format!("{}: not a number", REPEAT_FIELDS[2]))?,
+            )
+        };
+        Ok((data, every, count))
+    }
+}
+
+pub fn render_repeat_setup(
+    frame: &mut Frame,
+    area: Rect,
+    setup: &mut RepeatSetup,
+    schedules: &[ScheduleInfo],
+) {
+    let popup = centered_rect(76, 20, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("repeat");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+
+    let border = |idx: usize| {
+        if idx == setup.focus {
+            Style::default().fg(Color::LightGreen)
+        } else {
+            Style::default()
+        }
+    };
+    for (idx, field) in setup.fields.iter_mut().enumerate() {
+        field.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(border(idx))
+                .title(REPEAT_FIELDS[idx]),
+        );
+        frame.render_widget(field.widget(), rows[idx]);
+    }
+
+    let items = schedules
+        .iter()
+        .map(|schedule| {
+            let times = match schedule.count {
+                Some(count) => format!("{}/{}", schedule.sent, count),
+                None => schedule.sent.to_string(),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    if schedule.paused { "‖ " } else { "▶ " },
+                    Style::default().fg(if schedule.paused {
+                        Color::Gray
+                    } else {
+                        Color::LightGreen
+                    }),
+                ),
+                Span::raw(format!(
+                    "{} every {} ms",
+                    schedule.text,
+                    schedule.every.as_millis()
+                )),
+                Span::styled(
+                    format!("  {} · sent {}", schedule.port, times),
+                    Style::default().fg(Color::Gray),
+                ),
+            ]))
+        })
+        .collect::<Vec<_>>();
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(border(RepeatSetup::LIST))
+                .title("running"),
+        )
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::LightGreen));
+    frame.render_stateful_widget(list, rows[3], &mut setup.list);
+
+    let hint = match &setup.error {
+        Some(error) => Paragraph::new(error.clone()).style(Style::default().fg(Color::LightRed)),
+        None if setup.focus == RepeatSetup::LIST => {
+            Paragraph::new("🠕 🠗 select · Space pause/resume · Del remove · Tab form · Esc close")
+                .style(Style::default().fg(Color::Gray))
+        }
+        None => Paragraph::new("Tab next field · Enter start on this port · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+    };
+    frame.render_widget(hint, rows[4]);
+}
+
+pub fn render_trigger_list(
+    frame: &mut Frame,
+    area: Rect,
+    triggers: &[Trigger],
+    enabled: bool,
+    state: &mut ListState,
+) {
+    let popup = centered_rect(80, 16, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block(if enabled { "triggers" } else { "triggers (all off)" });
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(inner);
+
+    let items = if triggers.is_empty() {
+        vec![ListItem::new(Line::styled(
+            "no triggers in triggers.toml",
+            Style::default().fg(Color::Gray),
+        ))]
+    } else {
+        triggers
+            .iter()
+            .map(|trigger| {
+                let on = trigger.enabled && enabled;
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        if trigger.enabled { "[x] " } else { "[ ] " },
+                        Style::default().fg(if on { Color::LightGreen } else { Color::Gray }),
+                    ),
+                    Span::raw(trigger.name.clone()),
+                    Span::styled(
+                        format!("  {}", trigger.describe()),
+                        Style::default().fg(Color::Gray),
+                    ),
+                    Span::styled(
+                        format!("  fired {}", trigger.fired),
+                        Style::default().fg(Color::LightCyan),
+                    ),
+                ]))
+            })
+            .collect()
+    };
+    frame.render_stateful_widget(
+        List::new(items).highlight_style(Style::default().fg(Color::Black).bg(Color::LightGreen)),
+        rows[0],
+        state,
+    );
+    frame.render_widget(
+        Paragraph::new("Space toggle · a all on/off · r reload · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[1],
+    );
+}
+
+pub struct FaultSetup {
+    pub spec: TextArea<'static>,
+    pub error: Option<String>,
+}
+
+impl FaultSetup {
+    pub fn new(faults: Option<&FaultConfig>) -> FaultSetup {
+        FaultSetup {
+            spec: TextArea::new(vec![faults.map(FaultConfig::to_string).unwrap_or_default()]),
+            error: None,
+        }
+    }
+
+    /// The faults asked for, `None` when the field is left empty.
+    pub fn parse(&self) -> Result<Option<FaultConfig>, String> {
+        let spec = self.spec.lines()[0].trim();
+        if spec.is_empty() {
+            return Ok(None);
+        }
+        spec.parse().map(Some)
+    }
+}
+
+pub fn render_fault_setup(
+    frame: &mut Frame,
+    area: Rect,
+    setup: &mut FaultSetup,
+    faults: Option<&FaultConfig>,
+) {
+    let popup = centered_rect(84, 11, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("fault injection");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(2),
+            Constraint::Min(1),
+        ])
+        .split(inner);
+
+    frame.render_widget(
+        Paragraph::new(Line::from(Span::styled(
+            if faults.is_some() { " injecting " } else { " off " },
+            Style::default().fg(Color::Black).bg(if faults.is_some() {
+                Color::LightRed
+            } else {
+                Color::Gray
+            }),
+        ))),
+        rows[0],
+    );
+    setup.spec.set_block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("faults, empty for none"),
+    );
+    frame.render_widget(setup.spec.widget(), rows[1]);
+    let help = match &setup.error {
+        Some(error) => Paragraph::new(error.as_str()).style(Style::default().fg(Color::LightRed)),
+        None => Paragraph::new(
+            "drop=% dup=% corrupt=% delay=%:ms break=per min disconnect=per min:ms seed=n only=rx|tx",
+        )
+        .style(Style::default().fg(Color::Gray)),
+    };
+    frame.render_widget(help, rows[2]);
+    frame.render_widget(
+        Paragraph::new("Enter apply · Del stop · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[3],
+    );
+}
+
+pub fn render_open_prompt(frame: &mut Frame, area: Rect, name: &mut TextArea<'static>) {
+    let popup = centered_rect(100, 6, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("open port");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1)])
+        .split(inner);
+
+    name.set_block(Block::default().borders(Borders::ALL).title(
+        "device, rfc2217://, tcp:, unix:, exec:, pty, sim:, sniff:, bridge:<a>,<b> or virtual:",
+    ));
+    frame.render_widget(name.widget(), rows[0]);
+    frame.render_widget(
+        Paragraph::new("Enter open · Esc close").style(Style::default().fg(Color::Gray)),
+        rows[1],
+    );
+}
+
+pub fn render_alias_prompt(
+    frame: &mut Frame,
+    area: Rect,
+    port_name: &str,
+    alias: &mut TextArea<'static>,
+) {
+    let popup = centered_rect(60, 6, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block("alias");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1)])
+        .split(inner);
+
+    alias.set_block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("name for {}", port_name)),
+    );
+    frame.render_widget(alias.widget(), rows[0]);
+    frame.render_widget(
+        Paragraph::new("Enter save, empty to remove · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[1],
+    );
+}
+
+/// What is known about a port from the list, gathered when the popup was opened.
+pub struct DetailsView {
+    pub port: String,
+    pub sections: Vec<Section>,
+    pub scroll: u16,
+}
+
+impl DetailsView {
+    pub fn new(port: String) -> DetailsView {
+        DetailsView {
+            sections: crate::serial::details::gather(&port),
+            port,
+            scroll: 0,
+        }
+    }
+}
+
+pub fn render_port_details(frame: &mut Frame, area: Rect, view: &DetailsView) {
+    let popup = centered_rect(110, 30, area);
+    frame.render_widget(Clear, popup);
+    let block = popup_block(&view.port);
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(inner);
+
+    let width = view
+        .sections
+        .iter()
+        .flat_map(|section| &section.rows)
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(28);
+    let mut lines = Vec::new();
+    for section in &view.sections {
+        if !lines.is_empty() {
+            lines.push(Line::raw(""));
+        }
+        lines.push(Line::styled(
+            section.title,
+            Style::default().fg(Color::LightYellow),
+        ));
+        for (label, value) in &section.rows {
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("  {:width$}  ", label, width = width),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::raw(value.clone()),
+            ]));
+        }
+    }
+    frame.render_widget(Paragraph::new(lines).scroll((view.scroll, 0)), rows[0]);
+    frame.render_widget(
+        Paragraph::new("🠕 🠗 scroll · r refresh · Esc close")
+            .style(Style::default().fg(Color::Gray)),
+        rows[1],
+    );
+}